@@ -1,30 +1,162 @@
 //! Account file loading module.
 //!
-//! Supports two formats:
+//! Supports three formats:
 //! - `Name = Address`
 //! - `Name Address`
+//! - `Address` (name auto-generated as `addr_<first6>`)
+//!
+//! Named formats may be followed by `start:YYYY-MM-DD` / `end:YYYY-MM-DD` to
+//! limit the dates an account is queried for, and by a group tag (`cold` or
+//! `group:cold`) used for group subtotals.
+//!
+//! Files ending in `.yaml`/`.yml` are read as a list of `Account` records,
+//! which can also carry a display color and a note.
 
 use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::path::Path;
 
+/// Group of accounts without a group tag
+pub const DEFAULT_GROUP: &str = "default";
+
+/// Inclusive date window an account is tracked in (open-ended when `None`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateWindow {
+    pub start: Option<NaiveDate>,
+    pub end: Option<NaiveDate>,
+}
+
+impl DateWindow {
+    /// Whether `date` falls inside the window
+    pub fn contains(&self, date: NaiveDate) -> bool {
+        self.start.is_none_or(|s| date >= s) && self.end.is_none_or(|e| date <= e)
+    }
+}
+
+/// Whether an account's window (if any) includes the given `YYYY-MM-DD` date
+pub fn is_active_on(windows: &HashMap<String, DateWindow>, name: &str, date_str: &str) -> bool {
+    match (
+        windows.get(name),
+        NaiveDate::parse_from_str(date_str, "%Y-%m-%d"),
+    ) {
+        (Some(window), Ok(date)) => window.contains(date),
+        _ => true,
+    }
+}
+
+/// A single account line from an accounts file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountEntry {
+    /// 1-based line number in the source (position in the list for YAML)
+    pub line: usize,
+    pub name: String,
+    pub address: String,
+    /// Optional per-account active window
+    pub window: DateWindow,
+    /// Optional group tag
+    pub group: Option<String>,
+    /// Optional graph color (`#rrggbb`, YAML only)
+    pub color: Option<String>,
+    /// Optional free-form note (YAML only)
+    pub note: Option<String>,
+}
+
+/// One account record of a YAML accounts file
+///
+/// ```yaml
+/// - name: Alice
+///   address: 5Grwva...
+///   group: cold
+///   color: "#1f77b4"
+///   note: Ledger
+///   start: 2024-09-01
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Account {
+    pub name: String,
+    pub address: String,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+    #[serde(default)]
+    pub start: Option<NaiveDate>,
+    #[serde(default)]
+    pub end: Option<NaiveDate>,
+}
+
 /// Load accounts from a text file
 ///
-/// Supports two formats:
+/// Supports three formats:
 /// - `Name = Address`
 /// - `Name Address` (space-separated)
+/// - `Address` (bare address, named `addr_<first6>`)
 ///
 /// Lines starting with `#` are treated as comments.
 pub fn load_accounts<P: AsRef<Path>>(file_path: P) -> Result<HashMap<String, String>> {
+    Ok(entries_to_map(load_account_entries(file_path)?))
+}
+
+/// Load accounts from standard input, using the same formats as `load_accounts`
+pub fn load_accounts_from_stdin() -> Result<HashMap<String, String>> {
+    Ok(entries_to_map(parse_account_entries(
+        std::io::stdin().lock(),
+    )?))
+}
+
+/// Parse accounts from any line-oriented reader
+pub fn parse_accounts<R: BufRead>(reader: R) -> Result<HashMap<String, String>> {
+    Ok(entries_to_map(parse_account_entries(reader)?))
+}
+
+/// Load account entries (with line numbers) from a text or YAML file,
+/// chosen by extension
+pub fn load_account_entries<P: AsRef<Path>>(file_path: P) -> Result<Vec<AccountEntry>> {
     let path = file_path.as_ref();
     let file = File::open(path).context(format!("Accounts file not found: {:?}", path))?;
-    let reader = BufReader::new(file);
+    let is_yaml = path
+        .extension()
+        .is_some_and(|ext| ext == "yaml" || ext == "yml");
+    if is_yaml {
+        parse_yaml_account_entries(BufReader::new(file))
+    } else {
+        parse_account_entries(BufReader::new(file))
+    }
+}
 
-    let mut accounts = HashMap::new();
+/// Parse a YAML list of `Account` records into entries
+pub fn parse_yaml_account_entries<R: std::io::Read>(reader: R) -> Result<Vec<AccountEntry>> {
+    let accounts: Vec<Account> =
+        serde_norway::from_reader(reader).context("Invalid YAML accounts file")?;
+    Ok(accounts
+        .into_iter()
+        .enumerate()
+        .map(|(idx, account)| AccountEntry {
+            line: idx + 1,
+            name: account.name,
+            address: account.address,
+            window: DateWindow {
+                start: account.start,
+                end: account.end,
+            },
+            group: account.group,
+            color: account.color,
+            note: account.note,
+        })
+        .collect())
+}
 
-    for line in reader.lines() {
+/// Parse account entries (with line numbers) from any line-oriented reader
+pub fn parse_account_entries<R: BufRead>(reader: R) -> Result<Vec<AccountEntry>> {
+    let mut entries: Vec<AccountEntry> = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
         let line = line?;
         let line = line.trim();
 
@@ -34,29 +166,174 @@ pub fn load_accounts<P: AsRef<Path>>(file_path: P) -> Result<HashMap<String, Str
         }
 
         // Parse "name = address" or "name address" format
-        if line.contains('=') {
+        let parsed = if line.contains('=') {
             let parts: Vec<&str> = line.splitn(2, '=').collect();
             if parts.len() == 2 {
-                let name = parts[0].trim().to_string();
-                let address = parts[1].trim().to_string();
-                if !name.is_empty() && !address.is_empty() {
-                    accounts.insert(name, address);
-                }
+                let mut rest = parts[1].split_whitespace();
+                let address = rest.next().unwrap_or("").to_string();
+                Some((parts[0].trim().to_string(), address, rest.collect()))
+            } else {
+                None
             }
         } else {
             // Space-separated format
             let parts: Vec<&str> = line.split_whitespace().collect();
             if parts.len() >= 2 {
-                let name = parts[0].to_string();
-                let address = parts[1].to_string();
-                if !name.is_empty() && !address.is_empty() {
-                    accounts.insert(name, address);
-                }
+                Some((
+                    parts[0].to_string(),
+                    parts[1].to_string(),
+                    parts[2..].to_vec(),
+                ))
+            } else if parts.len() == 1 {
+                // Bare address
+                let address = parts[0].to_string();
+                Some((
+                    generate_account_name(&address, &entries),
+                    address,
+                    Vec::new(),
+                ))
+            } else {
+                None
+            }
+        };
+
+        if let Some((name, address, extras)) = parsed {
+            if !name.is_empty() && !address.is_empty() {
+                let window = parse_window(&extras)
+                    .with_context(|| format!("Invalid account options on line {}", idx + 1))?;
+                entries.push(AccountEntry {
+                    line: idx + 1,
+                    name,
+                    address,
+                    window,
+                    group: parse_group(&extras),
+                    ..Default::default()
+                });
             }
         }
     }
 
-    Ok(accounts)
+    Ok(entries)
+}
+
+/// Parse trailing `start:YYYY-MM-DD` / `end:YYYY-MM-DD` tokens, rejecting
+/// unknown `key:value` options
+fn parse_window(tokens: &[&str]) -> Result<DateWindow> {
+    let mut window = DateWindow::default();
+    for token in tokens.iter().take_while(|token| !token.starts_with('#')) {
+        match token.split_once(':') {
+            Some(("start", date)) => {
+                window.start = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?)
+            }
+            Some(("end", date)) => window.end = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?),
+            Some(("group", _)) | None => {}
+            Some((key, _)) => {
+                anyhow::bail!("Unknown option '{}' (expected start:, end: or group:)", key)
+            }
+        }
+    }
+    Ok(window)
+}
+
+/// Parse a trailing group tag: `group:<tag>`, or a bare token that isn't a
+/// `key:value` option. A `#` starts a trailing comment.
+fn parse_group(tokens: &[&str]) -> Option<String> {
+    tokens
+        .iter()
+        .take_while(|token| !token.starts_with('#'))
+        .find_map(|token| match token.strip_prefix("group:") {
+            Some(group) => Some(group.to_string()),
+            None if !token.contains(':') => Some(token.to_string()),
+            None => None,
+        })
+}
+
+/// Check there is at least one account and every address parses as SS58,
+/// reporting all invalid entries at once
+pub fn validate_account_entries(entries: &[AccountEntry]) -> Result<()> {
+    if entries.is_empty() {
+        anyhow::bail!("No accounts found (the accounts list is empty or all comments)");
+    }
+
+    let failures: Vec<String> = entries
+        .iter()
+        .filter_map(|entry| {
+            crate::parse_ss58_address(&entry.address)
+                .err()
+                .map(|e| format!("  line {}: {} - {}", entry.line, entry.name, e))
+        })
+        .collect();
+
+    if !failures.is_empty() {
+        anyhow::bail!(
+            "{} invalid address(es) in accounts:\n{}",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Collapse entries into a name -> address map (later lines win on duplicate names)
+pub fn entries_to_map(entries: Vec<AccountEntry>) -> HashMap<String, String> {
+    entries.into_iter().map(|e| (e.name, e.address)).collect()
+}
+
+/// Collect the per-account windows of entries (later lines win on duplicate names)
+pub fn entries_to_windows(entries: &[AccountEntry]) -> HashMap<String, DateWindow> {
+    entries.iter().map(|e| (e.name.clone(), e.window)).collect()
+}
+
+/// Collect the graph colors set on entries
+pub fn entries_to_colors(entries: &[AccountEntry]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .filter_map(|e| Some((e.name.clone(), e.color.clone()?)))
+        .collect()
+}
+
+/// Collect the notes set on entries
+pub fn entries_to_notes(entries: &[AccountEntry]) -> HashMap<String, String> {
+    entries
+        .iter()
+        .filter_map(|e| Some((e.name.clone(), e.note.clone()?)))
+        .collect()
+}
+
+/// Collect each entry's group, with untagged accounts in `DEFAULT_GROUP`.
+/// `None` when no entry is tagged, so group output can be skipped.
+pub fn entries_to_groups(entries: &[AccountEntry]) -> Option<HashMap<String, String>> {
+    if entries.iter().all(|e| e.group.is_none()) {
+        return None;
+    }
+    Some(
+        entries
+            .iter()
+            .map(|e| {
+                let group = e.group.as_deref().unwrap_or(DEFAULT_GROUP);
+                (e.name.clone(), group.to_string())
+            })
+            .collect(),
+    )
+}
+
+/// Generate a stable short name (`addr_<first6>`) for a bare address
+fn generate_account_name(address: &str, existing: &[AccountEntry]) -> String {
+    let taken = |name: &str| existing.iter().any(|e| e.name == name);
+    let prefix: String = address.chars().take(6).collect();
+    let base = format!("addr_{}", prefix);
+    if !taken(&base) {
+        return base;
+    }
+
+    let mut n = 2;
+    loop {
+        let candidate = format!("{}_{}", base, n);
+        if !taken(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
 }
 
 #[cfg(test)]
@@ -98,4 +375,130 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_bare_addresses() -> Result<()> {
+        let input = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY\n\
+                     Bob 5FHneW46xGXgs5mUiveU4sbAp8p5T3f2RC8M2Yx84b25zS8v\n\
+                     5GrwvaAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA\n";
+
+        let accounts = parse_accounts(input.as_bytes())?;
+
+        assert_eq!(accounts.len(), 3);
+        assert_eq!(
+            accounts.get("addr_5Grwva").unwrap(),
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert_eq!(
+            accounts.get("addr_5Grwva_2").unwrap(),
+            "5GrwvaAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"
+        );
+        assert!(accounts.contains_key("Bob"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_account_entries() -> Result<()> {
+        let input = "Alice = 5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY\n\
+                     # comment\n\
+                     Typo = 5GrwvaEF5zXb26Fz9\n\
+                     Bad notanaddress\n";
+
+        let entries = parse_account_entries(input.as_bytes())?;
+        let err = validate_account_entries(&entries).unwrap_err().to_string();
+
+        assert!(err.starts_with("2 invalid address(es)"));
+        assert!(err.contains("line 3: Typo"));
+        assert!(err.contains("line 4: Bad"));
+        assert!(!err.contains("Alice"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_account_windows() -> Result<()> {
+        let input = "Alice = 5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY start:2025-01-01\n\
+                     Bob 5FHneW46xGXgs5mUiveU4sbAp8p5T3f2RC8M2Yx84b25zS8v start:2024-10-01 end:2024-12-31\n";
+
+        let entries = parse_account_entries(input.as_bytes())?;
+        let windows = entries_to_windows(&entries);
+        let date = |s: &str| NaiveDate::parse_from_str(s, "%Y-%m-%d").unwrap();
+
+        assert_eq!(
+            entries[0].address,
+            "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        );
+        assert!(!windows["Alice"].contains(date("2024-12-31")));
+        assert!(windows["Alice"].contains(date("2025-06-01")));
+        assert!(windows["Bob"].contains(date("2024-10-01")));
+        assert!(!windows["Bob"].contains(date("2025-01-01")));
+
+        assert!(parse_account_entries("Carol 5Grwva start:tomorrow".as_bytes()).is_err());
+
+        // A misspelled key is an error naming its line, not a silent no-op
+        let err = parse_account_entries("Dave 5Grwva\nErin 5FHneW strat:2025-01-01".as_bytes())
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("line 2"));
+        assert!(format!("{:#}", err).contains("strat"));
+        // Options after a comment are not parsed
+        assert!(parse_account_entries("Frank 5Grwva # see: ledger".as_bytes()).is_ok());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_account_groups() -> Result<()> {
+        let input = "Alice = 5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY cold\n\
+                     Bob 5FHneW46xGXgs5mUiveU4sbAp8p5T3f2RC8M2Yx84b25zS8v start:2024-10-01 group:hot\n\
+                     Carol 5FLSigC9H72J3S38shFafEw2CSrt1G699RY9d9NrvkR54s9S\n";
+
+        let entries = parse_account_entries(input.as_bytes())?;
+        let groups = entries_to_groups(&entries).unwrap();
+
+        assert_eq!(groups["Alice"], "cold");
+        assert_eq!(groups["Bob"], "hot");
+        assert_eq!(groups["Carol"], DEFAULT_GROUP);
+        assert!(entries[1].window.start.is_some());
+
+        // Without any tag there is nothing to group
+        assert!(entries_to_groups(&entries[2..]).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_all_comments_file_is_rejected() -> Result<()> {
+        let input = "# Alice = 5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY\n\n  # Bob\n";
+        let entries = parse_account_entries(input.as_bytes())?;
+
+        assert!(entries.is_empty());
+        let err = validate_account_entries(&entries).unwrap_err();
+        assert!(err.to_string().contains("No accounts found"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_yaml_accounts() -> Result<()> {
+        let yaml = "\
+- name: Alice
+  address: 5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY
+  group: cold
+  color: \"#1f77b4\"
+  note: Ledger
+  start: 2024-09-01
+- name: Bob
+  address: 5FHneW46xGXgs5mUiveU4sbAp8p5T3f2RC8M2Yx84b25zS8v
+";
+        let entries = parse_yaml_account_entries(yaml.as_bytes())?;
+
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].group.as_deref(), Some("cold"));
+        assert_eq!(entries[0].window.start, NaiveDate::from_ymd_opt(2024, 9, 1));
+        assert_eq!(entries_to_colors(&entries)["Alice"], "#1f77b4");
+        assert_eq!(entries_to_notes(&entries)["Alice"], "Ledger");
+        assert_eq!(entries[1].line, 2);
+        assert!(entries[1].color.is_none());
+        Ok(())
+    }
 }
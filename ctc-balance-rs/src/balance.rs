@@ -1,17 +1,44 @@
 //! Balance query module for Creditcoin3 accounts.
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use subxt::{
     backend::{legacy::LegacyRpcMethods, rpc::RpcClient},
+    ext::scale_value::{
+        scale::{self, PortableRegistry},
+        Value,
+    },
     OnlineClient, PolkadotConfig,
 };
 
-use crate::CTC_DIVISOR;
+use tracing::{debug, warn};
+
+use crate::{CtcError, CTC_DIVISOR};
+
+/// Storage keys read per `state_queryStorageAt` call
+const BATCH_KEYS: usize = 100;
+
+/// Set once the node has rejected `state_queryStorageAt`
+static BATCH_UNSUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a failed batched read means the node can't batch at all, i.e. it
+/// rejected `state_queryStorageAt` itself. Any other failure (a timeout, one
+/// account's entry failing to decode) only costs that block its batch.
+fn batch_unsupported(error: &anyhow::Error) -> bool {
+    let message = format!("{:#}", error).to_lowercase();
+    ["method not found", "-32601", "not supported"]
+        .iter()
+        .any(|n| message.contains(n))
+}
 
 /// Account balance data
+///
+/// Missing fields default to 0.0 so older cache files still load.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Balance {
     /// Free balance (CTC)
     pub free: f64,
@@ -19,6 +46,10 @@ pub struct Balance {
     pub reserved: f64,
     /// Frozen balance (CTC)
     pub frozen: f64,
+    /// Transactions sent (`System.Account` nonce); `None` for asset balances,
+    /// accounts without an entry and caches written before it was read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<u32>,
 }
 
 impl Balance {
@@ -28,6 +59,7 @@ impl Balance {
             free: 0.0,
             reserved: 0.0,
             frozen: 0.0,
+            nonce: None,
         }
     }
 
@@ -35,6 +67,75 @@ impl Balance {
     pub fn total(&self) -> f64 {
         self.free + self.reserved
     }
+
+    /// Transferable amount: free minus the frozen lock, floored at 0.
+    ///
+    /// In Substrate `frozen` is a lock on part of `free` (e.g. staking), not a
+    /// separate pot, so it is subtracted from `free` rather than added anywhere.
+    pub fn spendable(&self) -> f64 {
+        (self.free - self.frozen).max(0.0)
+    }
+
+    /// Everything the account owns: free (including any frozen part) + reserved.
+    ///
+    /// Frozen funds are already inside `free`, so adding them would double count.
+    pub fn owned(&self) -> f64 {
+        self.free + self.reserved
+    }
+
+    /// Nothing free, but funds held as reserved or frozen (e.g. fully bonded):
+    /// a real balance that the free metric shows as zero
+    pub fn is_reserved_only(&self) -> bool {
+        self.free == 0.0 && (self.reserved > 0.0 || self.frozen > 0.0)
+    }
+
+    /// Value of this balance under the given metric
+    pub fn metric(&self, metric: BalanceMetric) -> f64 {
+        match metric {
+            BalanceMetric::Free => self.free,
+            BalanceMetric::Spendable => self.spendable(),
+            BalanceMetric::Owned => self.owned(),
+        }
+    }
+}
+
+/// Which balance figure the CSV balance and total columns represent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BalanceMetric {
+    /// Free balance, including any frozen part
+    #[default]
+    Free,
+    /// Free minus frozen
+    Spendable,
+    /// Free + reserved
+    Owned,
+}
+
+impl BalanceMetric {
+    /// Short lowercase name, as accepted on the command line
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Free => "free",
+            Self::Spendable => "spendable",
+            Self::Owned => "owned",
+        }
+    }
+}
+
+impl std::str::FromStr for BalanceMetric {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "free" => Ok(Self::Free),
+            "spendable" => Ok(Self::Spendable),
+            "owned" => Ok(Self::Owned),
+            _ => Err(format!(
+                "Invalid balance metric '{}': expected free, spendable or owned",
+                s
+            )),
+        }
+    }
 }
 
 impl Default for Balance {
@@ -43,11 +144,108 @@ impl Default for Balance {
     }
 }
 
+/// Balances of many accounts at one block. An account whose read failed is
+/// listed with its error rather than failing the whole block.
+#[derive(Debug, Default)]
+pub struct PartialBalances {
+    pub balances: HashMap<String, Balance>,
+    /// Error per failed account
+    pub failed: BTreeMap<String, String>,
+}
+
+impl PartialBalances {
+    /// Split per-account results into balances and failures
+    pub fn from_results(results: impl IntoIterator<Item = (String, Result<Balance>)>) -> Self {
+        let mut partial = Self::default();
+        for (name, res) in results {
+            match res {
+                Ok(balance) => {
+                    partial.balances.insert(name, balance);
+                }
+                Err(e) => {
+                    partial.failed.insert(name, format!("{:#}", e));
+                }
+            }
+        }
+        partial
+    }
+
+    /// Record the balances read for `date` in `history` (as `metric`) and in
+    /// `cache`. A failed account's cell is removed from `history` rather than
+    /// filled in, so it is written empty and fetched again on the next run.
+    pub fn merge_into(
+        self,
+        date: &str,
+        metric: BalanceMetric,
+        history: &mut HashMap<String, HashMap<String, f64>>,
+        cache: &mut crate::cache::BalanceCache,
+    ) {
+        for name in self.failed.keys() {
+            if let Some(h) = history.get_mut(name) {
+                h.remove(date);
+            }
+        }
+        for (name, balance) in self.balances {
+            history
+                .entry(name.clone())
+                .or_default()
+                .insert(date.to_string(), balance.metric(metric));
+            cache
+                .entry(name)
+                .or_default()
+                .insert(date.to_string(), balance);
+        }
+    }
+
+    /// Every balance, or an error naming the accounts that failed
+    pub fn complete(self) -> Result<HashMap<String, Balance>> {
+        if self.failed.is_empty() {
+            return Ok(self.balances);
+        }
+        let failures: Vec<String> = self
+            .failed
+            .iter()
+            .map(|(name, e)| format!("{}: {}", name, e))
+            .collect();
+        anyhow::bail!(
+            "{} of {} accounts failed ({})",
+            self.failed.len(),
+            self.failed.len() + self.balances.len(),
+            failures.join("; ")
+        )
+    }
+}
+
+/// Metadata of a token in pallet `Assets` (`Assets.Metadata`)
+#[derive(Debug, Clone)]
+pub struct AssetMetadata {
+    pub symbol: String,
+    pub decimals: u32,
+}
+
+impl AssetMetadata {
+    /// Divisor converting raw asset units to whole tokens
+    pub fn divisor(&self) -> f64 {
+        10f64.powi(self.decimals as i32)
+    }
+}
+
 /// Balance tracker for Creditcoin3 accounts
+///
+/// Clones share the underlying connection.
+#[derive(Clone)]
 pub struct BalanceTracker {
     url: String,
     client: Option<OnlineClient<PolkadotConfig>>,
-    _rpc: Option<LegacyRpcMethods<PolkadotConfig>>,
+    rpc: Option<LegacyRpcMethods<PolkadotConfig>>,
+    /// Read all accounts of a block in `state_queryStorageAt` batches
+    batch: bool,
+    /// Asset tracked instead of native CTC, with its divisor once resolved
+    asset: Option<(u128, Option<f64>)>,
+    /// Divisor for native balances (see `ChainConnector::divisor`)
+    divisor: f64,
+    /// Shared cap on storage reads in flight across trackers
+    inflight: Option<Arc<tokio::sync::Semaphore>>,
 }
 
 impl BalanceTracker {
@@ -56,29 +254,83 @@ impl BalanceTracker {
         Self {
             url: url.to_string(),
             client: None,
-            _rpc: None,
+            rpc: None,
+            batch: false,
+            asset: None,
+            divisor: CTC_DIVISOR,
+            inflight: None,
         }
     }
 
+    /// Create a tracker on clients that are already connected (see `rpc_client_from_url`)
+    pub fn from_clients(
+        client: OnlineClient<PolkadotConfig>,
+        rpc: LegacyRpcMethods<PolkadotConfig>,
+    ) -> Self {
+        let mut tracker = Self::new("");
+        tracker.client = Some(client);
+        tracker.rpc = Some(rpc);
+        tracker
+    }
+
+    /// Set the divisor for native balances (defaults to `CTC_DIVISOR`)
+    pub fn set_divisor(&mut self, divisor: f64) {
+        self.divisor = divisor;
+    }
+
     /// Set the online client (injection for tracker reuse)
     pub fn set_client(&mut self, client: OnlineClient<PolkadotConfig>) {
         self.client = Some(client);
     }
 
+    /// Set the RPC methods (injection for tracker reuse)
+    pub fn set_rpc(&mut self, rpc: LegacyRpcMethods<PolkadotConfig>) {
+        self.rpc = Some(rpc);
+    }
+
+    /// Turn batched reads in `get_all_balances` on or off (off by default).
+    /// Batching needs the RPC methods from `connect` or `set_rpc`.
+    pub fn set_batch(&mut self, batch: bool) {
+        self.batch = batch;
+    }
+
+    /// Share a semaphore bounding the storage reads in flight, so trackers
+    /// working on different dates draw from one pool
+    pub fn set_inflight_limit(&mut self, semaphore: Arc<tokio::sync::Semaphore>) {
+        self.inflight = Some(semaphore);
+    }
+
+    /// Wait for a slot in the shared pool, if there is one
+    async fn inflight_permit(&self) -> Result<Option<tokio::sync::OwnedSemaphorePermit>> {
+        match &self.inflight {
+            Some(semaphore) => Ok(Some(semaphore.clone().acquire_owned().await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Track an `Assets` token instead of native CTC (`None` = native)
+    ///
+    /// `get_balance` and `get_all_balances` then read `Assets.Account`.
+    pub fn set_asset(&mut self, asset_id: Option<u128>) {
+        self.asset = asset_id.map(|id| (id, None));
+    }
+
     /// Connect to the node
     pub async fn connect(&mut self) -> Result<()> {
-        let rpc_client = RpcClient::from_url(&self.url)
-            .await
-            .context("Failed to connect to RPC")?;
+        let rpc_client = crate::rpc_client_from_url(&self.url).await?;
+        self.connect_with(rpc_client).await
+    }
 
+    /// Connect over an existing RPC connection instead of opening one from the URL
+    pub async fn connect_with(&mut self, rpc_client: RpcClient) -> Result<()> {
         let client = OnlineClient::<PolkadotConfig>::from_rpc_client(rpc_client.clone())
             .await
-            .context("Failed to create online client")?;
+            .map_err(|e| CtcError::Connection(format!("Failed to create online client: {}", e)))?;
 
         let rpc = LegacyRpcMethods::<PolkadotConfig>::new(rpc_client);
 
         self.client = Some(client);
-        self._rpc = Some(rpc);
+        self.rpc = Some(rpc);
         Ok(())
     }
 
@@ -92,23 +344,20 @@ impl BalanceTracker {
 
     /// Get the client
     fn client(&self) -> Result<&OnlineClient<PolkadotConfig>> {
-        self.client
-            .as_ref()
-            .context("Not connected. Call connect() first.")
+        self.client.as_ref().ok_or_else(|| {
+            CtcError::Connection("Not connected. Call connect() first.".into()).into()
+        })
     }
 
     /// Get account balance at a specific block
     pub async fn get_balance(&mut self, address: &str, block_hash: &str) -> Result<Balance> {
+        if let Some((asset_id, _)) = self.asset {
+            return self.get_asset_balance(asset_id, address, block_hash).await;
+        }
         self.ensure_connected().await?;
         let client = self.client()?;
 
-        // Parse the block hash
-        let hash_bytes =
-            hex::decode(block_hash.trim_start_matches("0x")).context("Invalid block hash")?;
-        let hash: [u8; 32] = hash_bytes
-            .try_into()
-            .map_err(|_| anyhow::anyhow!("Invalid hash length"))?;
-        let block_hash = subxt::utils::H256::from(hash);
+        let block_hash = crate::parse_block_hash(block_hash)?;
 
         // Parse address as AccountId32
         let account_id = crate::parse_ss58_address(address)?;
@@ -122,77 +371,431 @@ impl BalanceTracker {
         let storage_value = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?;
 
         match storage_value {
-            Some(value) => {
-                let decoded = value.to_value()?;
-                let mut free = 0u128;
-                let mut reserved = 0u128;
-                let mut frozen = 0u128;
-
-                // System.Account structure: { nonce, consumers, providers, sufficients, data: { free, reserved, frozen, flags } }
-                if let subxt::ext::scale_value::ValueDef::Composite(
-                    subxt::ext::scale_value::Composite::Named(fields),
-                ) = decoded.value
-                {
-                    for (name, field) in fields {
-                        if name.as_str() == "data" {
-                            // Extract balance data from the nested 'data' field
-                            if let subxt::ext::scale_value::ValueDef::Composite(
-                                subxt::ext::scale_value::Composite::Named(data_fields),
-                            ) = field.value
-                            {
-                                for (data_name, data_field) in data_fields {
-                                    match data_name.as_str() {
-                                        "free" => {
-                                            if let subxt::ext::scale_value::ValueDef::Primitive(
-                                                subxt::ext::scale_value::Primitive::U128(val),
-                                            ) = data_field.value
-                                            {
-                                                free = val;
-                                            }
-                                        }
-                                        "reserved" => {
-                                            if let subxt::ext::scale_value::ValueDef::Primitive(
-                                                subxt::ext::scale_value::Primitive::U128(val),
-                                            ) = data_field.value
-                                            {
-                                                reserved = val;
-                                            }
-                                        }
-                                        "frozen" => {
-                                            if let subxt::ext::scale_value::ValueDef::Primitive(
-                                                subxt::ext::scale_value::Primitive::U128(val),
-                                            ) = data_field.value
-                                            {
-                                                frozen = val;
-                                            }
-                                        }
-                                        _ => {}
+            Some(value) => Ok(self.balance_from_account(value.to_value()?)),
+            None => Ok(Balance::zero()),
+        }
+    }
+
+    /// Reduce a decoded `System.Account` entry to a `Balance`
+    fn balance_from_account(&self, decoded: Value<u32>) -> Balance {
+        let mut free = 0u128;
+        let mut reserved = 0u128;
+        let mut frozen = 0u128;
+        let mut nonce = None;
+
+        // System.Account structure: { nonce, consumers, providers, sufficients, data: { free, reserved, frozen, flags } }
+        if let subxt::ext::scale_value::ValueDef::Composite(
+            subxt::ext::scale_value::Composite::Named(fields),
+        ) = decoded.value
+        {
+            for (name, field) in fields {
+                if name.as_str() == "nonce" {
+                    if let subxt::ext::scale_value::ValueDef::Primitive(
+                        subxt::ext::scale_value::Primitive::U128(val),
+                    ) = field.value
+                    {
+                        nonce = u32::try_from(val).ok();
+                    }
+                } else if name.as_str() == "data" {
+                    // Extract balance data from the nested 'data' field
+                    if let subxt::ext::scale_value::ValueDef::Composite(
+                        subxt::ext::scale_value::Composite::Named(data_fields),
+                    ) = field.value
+                    {
+                        for (data_name, data_field) in data_fields {
+                            match data_name.as_str() {
+                                "free" => {
+                                    if let subxt::ext::scale_value::ValueDef::Primitive(
+                                        subxt::ext::scale_value::Primitive::U128(val),
+                                    ) = data_field.value
+                                    {
+                                        free = val;
                                     }
                                 }
+                                "reserved" => {
+                                    if let subxt::ext::scale_value::ValueDef::Primitive(
+                                        subxt::ext::scale_value::Primitive::U128(val),
+                                    ) = data_field.value
+                                    {
+                                        reserved = val;
+                                    }
+                                }
+                                "frozen" => {
+                                    if let subxt::ext::scale_value::ValueDef::Primitive(
+                                        subxt::ext::scale_value::Primitive::U128(val),
+                                    ) = data_field.value
+                                    {
+                                        frozen = val;
+                                    }
+                                }
+                                _ => {}
                             }
                         }
                     }
                 }
+            }
+        }
 
-                Ok(Balance {
-                    free: free as f64 / CTC_DIVISOR,
-                    reserved: reserved as f64 / CTC_DIVISOR,
-                    frozen: frozen as f64 / CTC_DIVISOR,
-                })
+        Balance {
+            free: free as f64 / self.divisor,
+            reserved: reserved as f64 / self.divisor,
+            frozen: frozen as f64 / self.divisor,
+            nonce,
+        }
+    }
+
+    /// Raw decoded `System.Account` entry as JSON (nonce, consumers, providers,
+    /// sufficients and `data`), or `null` when the account has no entry.
+    /// For debugging what the chain returned before it was reduced to `Balance`.
+    pub async fn get_raw_account(
+        &mut self,
+        address: &str,
+        block_hash: &str,
+    ) -> Result<serde_json::Value> {
+        self.ensure_connected().await?;
+        let client = self.client()?;
+
+        let block_hash = crate::parse_block_hash(block_hash)?;
+        let account_id = crate::parse_ss58_address(address)?;
+        let storage_address = subxt::dynamic::storage(
+            "System",
+            "Account",
+            vec![subxt::dynamic::Value::from_bytes(account_id.0)],
+        );
+
+        match crate::retry!(client.storage().at(block_hash).fetch(&storage_address))? {
+            Some(value) => Ok(value_to_json(&value.to_value()?)),
+            None => Ok(serde_json::Value::Null),
+        }
+    }
+
+    /// Fail with `NotFound` when the runtime has no `Assets` pallet
+    fn ensure_assets_pallet(&self) -> Result<()> {
+        if self.client()?.metadata().pallet_by_name("Assets").is_none() {
+            return Err(
+                CtcError::NotFound("pallet Assets is not present on this chain".into()).into(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Get an asset's symbol and decimals (`Assets.Metadata`) at a block
+    pub async fn get_asset_metadata(
+        &mut self,
+        asset_id: u128,
+        block_hash: &str,
+    ) -> Result<AssetMetadata> {
+        use subxt::ext::scale_value::{Composite, Primitive, ValueDef};
+
+        self.ensure_connected().await?;
+        self.ensure_assets_pallet()?;
+        let client = self.client()?;
+        let block_hash = crate::parse_block_hash(block_hash)?;
+
+        let storage_address = subxt::dynamic::storage(
+            "Assets",
+            "Metadata",
+            vec![subxt::dynamic::Value::u128(asset_id)],
+        );
+        let Some(value) = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?
+        else {
+            return Err(CtcError::NotFound(format!("metadata for asset {}", asset_id)).into());
+        };
+
+        let mut metadata = AssetMetadata {
+            symbol: String::new(),
+            decimals: 0,
+        };
+        if let ValueDef::Composite(Composite::Named(fields)) = value.to_value()?.value {
+            for (name, field) in fields {
+                match (name.as_str(), field.value) {
+                    ("decimals", ValueDef::Primitive(Primitive::U128(d))) => {
+                        metadata.decimals = d as u32;
+                    }
+                    ("symbol", ValueDef::Composite(symbol)) => {
+                        let bytes: Vec<u8> = symbol
+                            .values()
+                            .filter_map(|v| match v.value {
+                                ValueDef::Primitive(Primitive::U128(b)) => Some(b as u8),
+                                _ => None,
+                            })
+                            .collect();
+                        metadata.symbol = String::from_utf8_lossy(&bytes).into_owned();
+                    }
+                    _ => {}
+                }
             }
-            None => Ok(Balance::zero()),
         }
+        Ok(metadata)
+    }
+
+    /// Get an account's holdings of an asset (`Assets.Account`) at a block
+    ///
+    /// The whole balance is reported as `free`; it also counts as `frozen` while
+    /// the asset account is frozen or blocked. Assets have no reserved balance.
+    pub async fn get_asset_balance(
+        &mut self,
+        asset_id: u128,
+        address: &str,
+        block_hash: &str,
+    ) -> Result<Balance> {
+        use subxt::ext::scale_value::{Composite, Primitive, ValueDef};
+
+        let divisor = match self.asset {
+            Some((id, Some(divisor))) if id == asset_id => divisor,
+            _ => {
+                let divisor = self
+                    .get_asset_metadata(asset_id, block_hash)
+                    .await?
+                    .divisor();
+                if matches!(self.asset, Some((id, _)) if id == asset_id) {
+                    self.asset = Some((asset_id, Some(divisor)));
+                }
+                divisor
+            }
+        };
+
+        self.ensure_connected().await?;
+        let client = self.client()?;
+        let block_hash = crate::parse_block_hash(block_hash)?;
+        let account_id = crate::parse_ss58_address(address)?;
+
+        let storage_address = subxt::dynamic::storage(
+            "Assets",
+            "Account",
+            vec![
+                subxt::dynamic::Value::u128(asset_id),
+                subxt::dynamic::Value::from_bytes(account_id.0),
+            ],
+        );
+        let Some(value) = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?
+        else {
+            return Ok(Balance::zero());
+        };
+
+        // Assets.Account: { balance, status: Liquid | Frozen | Blocked, reason, extra }
+        // (older runtimes: { balance, is_frozen, ... })
+        let mut balance = 0u128;
+        let mut is_frozen = false;
+        if let ValueDef::Composite(Composite::Named(fields)) = value.to_value()?.value {
+            for (name, field) in fields {
+                match (name.as_str(), field.value) {
+                    ("balance", ValueDef::Primitive(Primitive::U128(val))) => balance = val,
+                    ("status", ValueDef::Variant(status)) => {
+                        is_frozen = status.name != "Liquid";
+                    }
+                    ("is_frozen", ValueDef::Primitive(Primitive::Bool(frozen))) => {
+                        is_frozen = frozen;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let amount = balance as f64 / divisor;
+        Ok(Balance {
+            free: amount,
+            reserved: 0.0,
+            frozen: if is_frozen { amount } else { 0.0 },
+            nonce: None,
+        })
+    }
+
+    /// Get the active bonded stake (`Staking.Ledger.active`) of a stash at a block
+    pub async fn get_active_stake(&mut self, address: &str, block_hash: &str) -> Result<f64> {
+        use subxt::ext::scale_value::{Composite, Primitive, ValueDef};
+
+        self.ensure_connected().await?;
+        let client = self.client()?;
+        let block_hash = crate::parse_block_hash(block_hash)?;
+        let account_id = crate::parse_ss58_address(address)?;
+
+        // Staking.Bonded: stash -> controller
+        let bonded_address = subxt::dynamic::storage(
+            "Staking",
+            "Bonded",
+            vec![subxt::dynamic::Value::from_bytes(account_id.0)],
+        );
+        let Some(controller) =
+            crate::retry!(client.storage().at(block_hash).fetch(&bonded_address))?
+        else {
+            return Ok(0.0);
+        };
+        let controller = controller.to_value()?.remove_context();
+
+        // Staking.Ledger: controller -> { stash, total, active, unlocking, ... }
+        let ledger_address = subxt::dynamic::storage("Staking", "Ledger", vec![controller]);
+        let Some(ledger) = crate::retry!(client.storage().at(block_hash).fetch(&ledger_address))?
+        else {
+            return Ok(0.0);
+        };
+
+        let mut active = 0u128;
+        if let ValueDef::Composite(Composite::Named(fields)) = ledger.to_value()?.value {
+            for (name, field) in fields {
+                if name == "active" {
+                    if let ValueDef::Primitive(Primitive::U128(val)) = field.value {
+                        active = val;
+                    }
+                }
+            }
+        }
+        Ok(active as f64 / self.divisor)
+    }
+
+    /// Get active stakes for multiple accounts in parallel
+    pub async fn get_all_active_stakes(
+        &mut self,
+        accounts: &HashMap<String, String>,
+        block_hash: &str,
+    ) -> Result<HashMap<String, f64>> {
+        self.ensure_connected().await?;
+
+        use futures::stream::{self, StreamExt};
+        let mut stream = stream::iter(accounts.iter())
+            .map(|(name, address)| {
+                // Stake is always native, whatever asset this tracker follows
+                let mut tracker = self.clone();
+                tracker.asset = None;
+                async move {
+                    let res = match tracker.inflight_permit().await {
+                        Ok(_permit) => tracker.get_active_stake(address, block_hash).await,
+                        Err(e) => Err(e),
+                    };
+                    (name.clone(), res)
+                }
+            })
+            .buffer_unordered(crate::CONCURRENCY_STORAGE);
+
+        let mut stakes = HashMap::new();
+        while let Some((name, res)) = stream.next().await {
+            stakes.insert(name, res?);
+        }
+
+        Ok(stakes)
+    }
+
+    /// Native balances of `accounts` read with `state_queryStorageAt`, up to
+    /// `BATCH_KEYS` storage keys per request instead of one request per account
+    async fn get_balances_batched(
+        &self,
+        accounts: &HashMap<String, String>,
+        block_hash: &str,
+    ) -> Result<HashMap<String, Balance>> {
+        let client = self.client()?;
+        let rpc = self
+            .rpc
+            .as_ref()
+            .ok_or_else(|| CtcError::Connection("No RPC methods for batched reads".into()))?;
+        let hash = crate::parse_block_hash(block_hash)?;
+        let metadata = client.metadata();
+        let value_ty = metadata
+            .pallet_by_name("System")
+            .and_then(|pallet| {
+                pallet
+                    .storage()
+                    .and_then(|storage| storage.entry_by_name("Account"))
+                    .map(|entry| entry.entry_type().value_ty())
+            })
+            .ok_or_else(|| CtcError::Decode("System.Account missing from metadata".into()))?;
+
+        let mut keys = Vec::with_capacity(accounts.len());
+        for (name, address) in accounts {
+            let account_id = crate::parse_ss58_address(address)?;
+            let storage_address = subxt::dynamic::storage(
+                "System",
+                "Account",
+                vec![subxt::dynamic::Value::from_bytes(account_id.0)],
+            );
+            keys.push((name, client.storage().address_bytes(&storage_address)?));
+        }
+
+        let mut values: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+        for chunk in keys.chunks(BATCH_KEYS) {
+            let _permit = self.inflight_permit().await?;
+            let change_sets = crate::retry!(
+                rpc.state_query_storage_at(chunk.iter().map(|(_, key)| key.as_slice()), Some(hash))
+            )?;
+            for change_set in change_sets {
+                for (key, value) in change_set.changes {
+                    if let Some(value) = value {
+                        values.insert(key.0, value.0);
+                    }
+                }
+            }
+        }
+
+        self.balances_from_entries(&keys, &values, value_ty, metadata.types())
+    }
+
+    /// Balances from the raw `System.Account` entries (of type `value_ty`) of a
+    /// batched read, matched to accounts by storage key. Accounts without an
+    /// entry are absent (or `None`) in the change set and get a zero balance.
+    fn balances_from_entries(
+        &self,
+        keys: &[(&String, Vec<u8>)],
+        values: &HashMap<Vec<u8>, Vec<u8>>,
+        value_ty: u32,
+        types: &PortableRegistry,
+    ) -> Result<HashMap<String, Balance>> {
+        let mut balances = HashMap::new();
+        for (name, key) in keys {
+            let balance = match values.get(key) {
+                Some(bytes) => {
+                    let value = scale::decode_as_type(&mut &bytes[..], value_ty, types)
+                        .map_err(|e| CtcError::Decode(format!("System.Account: {}", e)))?;
+                    self.balance_from_account(value)
+                }
+                None => Balance::zero(),
+            };
+            balances.insert((*name).clone(), balance);
+        }
+        Ok(balances)
     }
 
     /// Get balances for multiple accounts in parallel
+    ///
+    /// Accounts whose read fails are reported in `PartialBalances::failed`;
+    /// the error case is kept for failures that affect every account.
     pub async fn get_all_balances(
         &mut self,
         accounts: &HashMap<String, String>,
         block_hash: &str,
-    ) -> Result<HashMap<String, Balance>> {
+    ) -> Result<PartialBalances> {
         self.ensure_connected().await?;
 
-        let client = self.client.clone().context("Client not initialized")?;
+        // Resolve the asset divisor once rather than per account
+        if let Some((asset_id, None)) = self.asset {
+            let divisor = self
+                .get_asset_metadata(asset_id, block_hash)
+                .await?
+                .divisor();
+            self.asset = Some((asset_id, Some(divisor)));
+        }
+        if self.asset.is_none()
+            && self.batch
+            && self.rpc.is_some()
+            && !BATCH_UNSUPPORTED.load(Ordering::Relaxed)
+        {
+            match self.get_balances_batched(accounts, block_hash).await {
+                Ok(balances) => {
+                    return Ok(PartialBalances {
+                        balances,
+                        failed: BTreeMap::new(),
+                    })
+                }
+                Err(e) => {
+                    if batch_unsupported(&e) && !BATCH_UNSUPPORTED.swap(true, Ordering::Relaxed) {
+                        warn!(
+                            "Batched storage reads unavailable ({}); reading accounts one by one",
+                            e
+                        );
+                    } else {
+                        debug!("Batched read failed ({}); reading accounts one by one", e);
+                    }
+                }
+            }
+        }
         let block_hash_str = block_hash.to_string();
 
         use futures::stream::{self, StreamExt};
@@ -200,29 +803,71 @@ impl BalanceTracker {
             .map(|(name, address)| {
                 let name = name.clone();
                 let address = address.clone();
-                let client = client.clone();
                 let block_hash = block_hash_str.clone();
-                let url = self.url.clone();
+                let mut tracker = self.clone();
 
                 async move {
-                    let mut tracker = BalanceTracker {
-                        url,
-                        client: Some(client),
-                        _rpc: None,
+                    let res = match tracker.inflight_permit().await {
+                        Ok(_permit) => tracker.get_balance(&address, &block_hash).await,
+                        Err(e) => Err(e),
                     };
-                    let res = tracker.get_balance(&address, &block_hash).await;
                     (name, res)
                 }
             })
             .buffer_unordered(crate::CONCURRENCY_STORAGE);
 
-        let mut balances = HashMap::new();
-        while let Some((name, res)) = stream.next().await {
-            let balance = res?;
-            balances.insert(name, balance);
+        let mut results = Vec::with_capacity(accounts.len());
+        while let Some(result) = stream.next().await {
+            results.push(result);
         }
 
-        Ok(balances)
+        Ok(PartialBalances::from_results(results))
+    }
+}
+
+/// Convert a decoded SCALE value to JSON.
+///
+/// Named composites become objects, unnamed ones arrays and variants
+/// `{"<name>": fields}`. Integers are written as decimal strings so u128
+/// amounts survive exactly.
+fn value_to_json<T>(value: &Value<T>) -> serde_json::Value {
+    use serde_json::Value as Json;
+    use subxt::ext::scale_value::{Composite, Primitive, ValueDef};
+
+    fn composite<T>(c: &Composite<T>) -> Json {
+        match c {
+            Composite::Named(fields) => Json::Object(
+                fields
+                    .iter()
+                    .map(|(name, v)| (name.clone(), value_to_json(v)))
+                    .collect(),
+            ),
+            Composite::Unnamed(values) => Json::Array(values.iter().map(value_to_json).collect()),
+        }
+    }
+
+    match &value.value {
+        ValueDef::Composite(c) => composite(c),
+        ValueDef::Variant(v) => {
+            let mut map = serde_json::Map::new();
+            map.insert(v.name.clone(), composite(&v.values));
+            Json::Object(map)
+        }
+        ValueDef::BitSequence(bits) => Json::String(
+            bits.iter()
+                .map(|b| if b { '1' } else { '0' })
+                .collect::<String>(),
+        ),
+        ValueDef::Primitive(p) => match p {
+            Primitive::Bool(b) => Json::Bool(*b),
+            Primitive::Char(c) => Json::String(c.to_string()),
+            Primitive::String(s) => Json::String(s.clone()),
+            Primitive::U128(n) => Json::String(n.to_string()),
+            Primitive::I128(n) => Json::String(n.to_string()),
+            Primitive::U256(bytes) | Primitive::I256(bytes) => {
+                Json::String(format!("0x{}", hex::encode(bytes)))
+            }
+        },
     }
 }
 
@@ -250,7 +895,231 @@ mod tests {
             free: 100.0,
             reserved: 50.0,
             frozen: 10.0,
+            nonce: None,
         };
         assert_eq!(b.total(), 150.0);
     }
+
+    #[test]
+    fn test_balance_lock_semantics() {
+        // Staking freezes part of free; the frozen part is still owned
+        let staker = Balance {
+            free: 100.0,
+            reserved: 5.0,
+            frozen: 80.0,
+            nonce: None,
+        };
+        assert_eq!(staker.spendable(), 20.0);
+        assert_eq!(staker.owned(), 105.0);
+
+        // Locks can exceed free (e.g. overlapping locks); spendable floors at 0
+        let over_locked = Balance {
+            free: 50.0,
+            reserved: 0.0,
+            frozen: 70.0,
+            nonce: None,
+        };
+        assert_eq!(over_locked.spendable(), 0.0);
+        assert_eq!(over_locked.owned(), 50.0);
+
+        assert_eq!(staker.metric(BalanceMetric::Free), 100.0);
+        assert_eq!(staker.metric(BalanceMetric::Spendable), 20.0);
+        assert_eq!(staker.metric(BalanceMetric::Owned), 105.0);
+        assert!(!staker.is_reserved_only());
+
+        let bonded = Balance {
+            free: 0.0,
+            reserved: 500.0,
+            frozen: 0.0,
+            nonce: None,
+        };
+        assert!(bonded.is_reserved_only());
+        assert!(!Balance::zero().is_reserved_only());
+    }
+
+    #[test]
+    fn test_partial_balances_keep_successes() {
+        let ok = |free: f64| {
+            Ok(Balance {
+                free,
+                ..Balance::zero()
+            })
+        };
+        let partial = PartialBalances::from_results([
+            ("A".to_string(), ok(1.0)),
+            ("B".to_string(), Err(anyhow::anyhow!("connection reset"))),
+            ("C".to_string(), ok(3.0)),
+        ]);
+        assert_eq!(partial.balances.len(), 2);
+        assert_eq!(partial.balances["C"].free, 3.0);
+        assert_eq!(
+            partial.failed.keys().collect::<Vec<_>>(),
+            vec![&"B".to_string()]
+        );
+
+        let err = partial.complete().unwrap_err().to_string();
+        assert!(err.starts_with("1 of 3 accounts failed"), "{}", err);
+    }
+
+    #[test]
+    fn test_partial_balances_leave_failed_cell_missing() {
+        let date = "2024-09-02".to_string();
+        let names = vec!["A".to_string(), "B".to_string()];
+        // B had a stale value for the date from an earlier run
+        let mut history = HashMap::from([("B".to_string(), HashMap::from([(date.clone(), 9.0)]))]);
+        let mut cache = crate::cache::BalanceCache::new();
+        let partial = PartialBalances::from_results([
+            (
+                "A".to_string(),
+                Ok(Balance {
+                    free: 2.0,
+                    ..Balance::zero()
+                }),
+            ),
+            ("B".to_string(), Err(anyhow::anyhow!("connection reset"))),
+        ]);
+        partial.merge_into(&date, BalanceMetric::Free, &mut history, &mut cache);
+
+        assert_eq!(history["A"][&date], 2.0);
+        assert!(!history["B"].contains_key(&date));
+        assert!(!cache.contains_key("B"));
+
+        let entries = crate::csv_output::build_history_entries(
+            std::slice::from_ref(&date),
+            &names,
+            &HashMap::new(),
+            None,
+            &history,
+            &HashMap::new(),
+            &[],
+        );
+        assert_eq!(entries[0].balances.get("A"), Some(&2.0));
+        assert_eq!(entries[0].balances.get("B"), None);
+        assert_eq!(entries[0].total, 2.0);
+    }
+
+    #[test]
+    fn test_balance_from_account_nonce() {
+        let account = Value::named_composite([
+            ("nonce", Value::u128(42)),
+            (
+                "data",
+                Value::named_composite([
+                    ("free", Value::u128(3 * CTC_DIVISOR as u128)),
+                    ("reserved", Value::u128(0)),
+                    ("frozen", Value::u128(0)),
+                ]),
+            ),
+        ])
+        .map_context(|_| 0u32);
+        let balance = BalanceTracker::new("").balance_from_account(account);
+        assert_eq!(balance.nonce, Some(42));
+        assert_eq!(balance.free, 3.0);
+    }
+
+    #[test]
+    fn test_batched_entries_decode() {
+        use codec::Encode;
+        use scale_info::{MetaType, Registry, TypeInfo};
+
+        #[derive(Encode, TypeInfo)]
+        struct AccountData {
+            free: u128,
+            reserved: u128,
+            frozen: u128,
+            flags: u128,
+        }
+        #[derive(Encode, TypeInfo)]
+        struct AccountInfo {
+            nonce: u32,
+            consumers: u32,
+            providers: u32,
+            sufficients: u32,
+            data: AccountData,
+        }
+
+        let mut registry = Registry::new();
+        let value_ty = registry.register_type(&MetaType::new::<AccountInfo>()).id;
+        let types = PortableRegistry::from(registry);
+
+        let info = AccountInfo {
+            nonce: 7,
+            consumers: 0,
+            providers: 1,
+            sufficients: 0,
+            data: AccountData {
+                free: 5 * CTC_DIVISOR as u128,
+                reserved: 2 * CTC_DIVISOR as u128,
+                frozen: 0,
+                flags: 0,
+            },
+        };
+        let (a, b) = ("A".to_string(), "B".to_string());
+        let keys = vec![(&a, vec![1u8]), (&b, vec![2u8])];
+        // Only A has an entry; B was never funded
+        let values = HashMap::from([(vec![1u8], info.encode())]);
+
+        let balances = BalanceTracker::new("")
+            .balances_from_entries(&keys, &values, value_ty, &types)
+            .unwrap();
+        assert_eq!(balances["A"].free, 5.0);
+        assert_eq!(balances["A"].reserved, 2.0);
+        assert_eq!(balances["A"].nonce, Some(7));
+        assert_eq!(balances["B"].total(), 0.0);
+
+        // A truncated entry is a decode error, not a zero balance
+        let values = HashMap::from([(vec![1u8], vec![0u8; 3])]);
+        let err = BalanceTracker::new("")
+            .balances_from_entries(&keys, &values, value_ty, &types)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<CtcError>(),
+            Some(CtcError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_batch_unsupported() {
+        // A node without the method rejects it for good: fall back per account
+        let missing = CtcError::from_message("Method not found: state_queryStorageAt");
+        assert!(batch_unsupported(&missing.into()));
+        // Transient failures keep batching on for later blocks
+        let timeout = CtcError::Connection("request timeout".into());
+        assert!(!batch_unsupported(&timeout.into()));
+        assert!(!batch_unsupported(&anyhow::anyhow!("unclassified")));
+        // A bad entry is permanent too, but says nothing about the method
+        let decode = CtcError::Decode("System.Account: not enough data".into());
+        assert!(!batch_unsupported(&decode.into()));
+        let pruned = CtcError::from_message("State already discarded for 0xabc");
+        assert!(!batch_unsupported(&pruned.into()));
+    }
+
+    #[test]
+    fn test_value_to_json_account() {
+        let account = Value::named_composite([
+            ("nonce", Value::u128(3)),
+            (
+                "data",
+                Value::named_composite([
+                    ("free", Value::u128(u128::MAX)),
+                    ("flags", Value::unnamed_composite([Value::u128(1)])),
+                ]),
+            ),
+        ]);
+        assert_eq!(
+            value_to_json(&account),
+            serde_json::json!({
+                "nonce": "3",
+                "data": {"free": u128::MAX.to_string(), "flags": ["1"]}
+            })
+        );
+    }
+
+    #[test]
+    fn test_balance_missing_fields_default() {
+        let b: Balance = serde_json::from_str(r#"{"free": 12.5}"#).unwrap();
+        assert_eq!(b.free, 12.5);
+        assert_eq!(b.reserved, 0.0);
+        assert_eq!(b.frozen, 0.0);
+    }
 }
@@ -1,51 +1,187 @@
 //! Block cache management module.
 //!
 //! Caches date->block mappings in JSON format for performance.
+//! Rewards, full balances and active stakes are cached alongside in the same format.
+//! Any cache path ending in `.gz` is read and written gzip-compressed.
 
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use chrono::{NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
-use std::io::{BufReader, BufWriter};
-use std::path::Path;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
+use crate::balance::Balance;
 use crate::chain::BlockInfo;
 
-/// Block cache type alias
-pub type BlockCache = HashMap<String, BlockInfo>;
+/// Path of a cache file in `dir`: `<stem>.json`, or `<stem>.json.gz` when compressed
+pub fn cache_path(dir: &Path, stem: &str, compress: bool) -> PathBuf {
+    if compress {
+        dir.join(format!("{}.json.gz", stem))
+    } else {
+        dir.join(format!("{}.json", stem))
+    }
+}
 
-/// Load block cache from JSON file
-pub fn load_block_cache<P: AsRef<Path>>(cache_file: P) -> Result<BlockCache> {
-    let path = cache_file.as_ref();
+fn is_gzip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "gz")
+}
+
+/// Read a JSON cache, gunzipping `.gz` files.
+///
+/// A missing `.json.gz` falls back to the uncompressed `.json` next to it, so
+/// switching on compression keeps an existing cache; a missing file is empty.
+fn read_json<T: DeserializeOwned + Default>(path: &Path, what: &str) -> Result<T> {
+    let path = if is_gzip(path) && !path.exists() {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    };
 
     if !path.exists() {
-        return Ok(HashMap::new());
+        return Ok(T::default());
     }
 
-    let file = File::open(path).context("Failed to open cache file")?;
+    let file = File::open(&path).with_context(|| format!("Failed to open {} file", what))?;
     let reader = BufReader::new(file);
 
-    let cache: BlockCache = serde_json::from_reader(reader).context("Failed to parse cache")?;
+    let cache = if is_gzip(&path) {
+        serde_json::from_reader(GzDecoder::new(reader))
+    } else {
+        serde_json::from_reader(reader)
+    }
+    .with_context(|| format!("Failed to parse {}", what))?;
 
     Ok(cache)
 }
 
-/// Save block cache to JSON file
-pub fn save_block_cache<P: AsRef<Path>>(cache_file: P, cache: &BlockCache) -> Result<()> {
-    let path = cache_file.as_ref();
-
+/// Write a JSON cache, gzipping when the path ends in `.gz`
+fn write_json<T: Serialize>(path: &Path, cache: &T, what: &str) -> Result<()> {
     // Ensure parent directory exists
     if let Some(parent) = path.parent() {
         fs::create_dir_all(parent).context("Failed to create cache directory")?;
     }
 
-    let file = File::create(path).context("Failed to create cache file")?;
+    let file = File::create(path).with_context(|| format!("Failed to create {} file", what))?;
     let writer = BufWriter::new(file);
 
-    serde_json::to_writer(writer, cache).context("Failed to write cache")?;
+    if is_gzip(path) {
+        let mut encoder = GzEncoder::new(writer, Compression::default());
+        serde_json::to_writer(&mut encoder, cache)
+            .with_context(|| format!("Failed to write {}", what))?;
+        encoder
+            .finish()?
+            .flush()
+            .with_context(|| format!("Failed to write {}", what))?;
+    } else {
+        serde_json::to_writer(writer, cache)
+            .with_context(|| format!("Failed to write {}", what))?;
+    }
 
     Ok(())
 }
 
+/// Block cache type alias
+pub type BlockCache = HashMap<String, BlockInfo>;
+
+/// Load block cache from JSON file (gzip-compressed if the path ends in `.gz`)
+pub fn load_block_cache<P: AsRef<Path>>(cache_file: P) -> Result<BlockCache> {
+    read_json(cache_file.as_ref(), "cache")
+}
+
+/// Save block cache to JSON file (gzip-compressed if the path ends in `.gz`)
+pub fn save_block_cache<P: AsRef<Path>>(cache_file: P, cache: &BlockCache) -> Result<()> {
+    write_json(cache_file.as_ref(), cache, "cache")
+}
+
+/// Time of day and timezone each date is sampled at.
+///
+/// Block cache keys encode both, so runs with different snapshot points never
+/// share entries: midnight UTC keeps the plain `YYYY-MM-DD` key, anything else
+/// becomes `YYYY-MM-DDTHH:MM` with an `@Zone/Name` suffix for non-UTC zones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Snapshot {
+    pub time: NaiveTime,
+    pub tz: Tz,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            time: NaiveTime::MIN,
+            tz: Tz::UTC,
+        }
+    }
+}
+
+impl Snapshot {
+    /// Block cache key for a date
+    pub fn key(&self, date: &str) -> String {
+        if *self == Self::default() {
+            return date.to_string();
+        }
+        let mut key = format!("{}T{}", date, self.time.format("%H:%M"));
+        if self.tz != Tz::UTC {
+            key.push('@');
+            key.push_str(self.tz.name());
+        }
+        key
+    }
+
+    /// File name suffix for outputs and per-date caches; empty for the default
+    pub fn tag(&self) -> String {
+        let mut tag = String::new();
+        if self.time != NaiveTime::MIN {
+            tag.push_str(&format!("_{}", self.time.format("%H%M")));
+        }
+        if self.tz != Tz::UTC {
+            tag.push('_');
+            tag.push_str(&self.tz.name().replace('/', "-"));
+        }
+        tag
+    }
+
+    /// Unix timestamp (seconds) of this snapshot point on a date
+    pub fn timestamp(&self, date: NaiveDate) -> u64 {
+        let local = date.and_time(self.time);
+        // A skipped local time (DST gap) falls back to its UTC reading
+        self.tz
+            .from_local_datetime(&local)
+            .earliest()
+            .map(|t| t.timestamp())
+            .unwrap_or_else(|| local.and_utc().timestamp()) as u64
+    }
+
+    /// Current date in the snapshot timezone
+    pub fn today(&self) -> NaiveDate {
+        Utc::now().with_timezone(&self.tz).date_naive()
+    }
+}
+
+/// Entries of `cache` taken at `snapshot`, keyed by plain date
+pub fn snapshot_view(cache: &BlockCache, snapshot: &Snapshot) -> BlockCache {
+    cache
+        .iter()
+        .filter_map(|(key, info)| {
+            let date = key.get(..10)?;
+            (snapshot.key(date) == *key).then(|| (date.to_string(), info.clone()))
+        })
+        .collect()
+}
+
+/// Store a date-keyed `view` back into `cache` under `snapshot`'s keys
+pub fn merge_snapshot_view(cache: &mut BlockCache, view: &BlockCache, snapshot: &Snapshot) {
+    for (date, info) in view {
+        cache.insert(snapshot.key(date), info.clone());
+    }
+}
+
 /// Merge new entries into existing cache
 pub fn merge_cache(cache: &mut BlockCache, new_entries: BlockCache) {
     for (date, info) in new_entries {
@@ -62,41 +198,93 @@ pub fn get_cached_block<'a>(cache: &'a BlockCache, date: &str) -> Option<&'a Blo
 // Reward Cache
 // ============================================================================
 
-/// Reward cache type: account_name -> date -> reward_amount
-pub type RewardCache = HashMap<String, HashMap<String, f64>>;
-
-/// Load reward cache from JSON file
-pub fn load_reward_cache<P: AsRef<Path>>(cache_file: P) -> Result<RewardCache> {
-    let path = cache_file.as_ref();
+/// A cached daily reward, with the validators that produced it when known.
+///
+/// Stored as a plain number when there is no breakdown, so caches written
+/// before breakdowns existed load unchanged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "CachedRewardRepr", into = "CachedRewardRepr")]
+pub struct CachedReward {
+    pub total: f64,
+    /// Validator (SS58 address) -> amount; empty when not attributed
+    pub by_validator: BTreeMap<String, f64>,
+}
 
-    if !path.exists() {
-        return Ok(HashMap::new());
+impl From<f64> for CachedReward {
+    fn from(total: f64) -> Self {
+        Self {
+            total,
+            by_validator: BTreeMap::new(),
+        }
     }
+}
 
-    let file = File::open(path).context("Failed to open reward cache file")?;
-    let reader = BufReader::new(file);
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CachedRewardRepr {
+    Total(f64),
+    Detailed {
+        total: f64,
+        by_validator: BTreeMap<String, f64>,
+    },
+}
 
-    let cache: RewardCache =
-        serde_json::from_reader(reader).context("Failed to parse reward cache")?;
+impl From<CachedRewardRepr> for CachedReward {
+    fn from(repr: CachedRewardRepr) -> Self {
+        match repr {
+            CachedRewardRepr::Total(total) => total.into(),
+            CachedRewardRepr::Detailed {
+                total,
+                by_validator,
+            } => Self {
+                total,
+                by_validator,
+            },
+        }
+    }
+}
 
-    Ok(cache)
+impl From<CachedReward> for CachedRewardRepr {
+    fn from(reward: CachedReward) -> Self {
+        if reward.by_validator.is_empty() {
+            Self::Total(reward.total)
+        } else {
+            Self::Detailed {
+                total: reward.total,
+                by_validator: reward.by_validator,
+            }
+        }
+    }
 }
 
-/// Save reward cache to JSON file
-pub fn save_reward_cache<P: AsRef<Path>>(cache_file: P, cache: &RewardCache) -> Result<()> {
-    let path = cache_file.as_ref();
+/// Reward cache type: account_name -> date -> reward
+pub type RewardCache = HashMap<String, HashMap<String, CachedReward>>;
 
-    // Ensure parent directory exists
-    if let Some(parent) = path.parent() {
-        fs::create_dir_all(parent).context("Failed to create cache directory")?;
-    }
+/// Reward totals only: account_name -> date -> reward_amount
+pub type RewardTotals = HashMap<String, HashMap<String, f64>>;
 
-    let file = File::create(path).context("Failed to create reward cache file")?;
-    let writer = BufWriter::new(file);
+/// Drop the validator breakdowns, keeping each day's total
+pub fn reward_totals(cache: &RewardCache) -> RewardTotals {
+    cache
+        .iter()
+        .map(|(account, dates)| {
+            let totals = dates
+                .iter()
+                .map(|(date, reward)| (date.clone(), reward.total))
+                .collect();
+            (account.clone(), totals)
+        })
+        .collect()
+}
 
-    serde_json::to_writer(writer, cache).context("Failed to write reward cache")?;
+/// Load reward cache from JSON file
+pub fn load_reward_cache<P: AsRef<Path>>(cache_file: P) -> Result<RewardCache> {
+    read_json(cache_file.as_ref(), "reward cache")
+}
 
-    Ok(())
+/// Save reward cache to JSON file
+pub fn save_reward_cache<P: AsRef<Path>>(cache_file: P, cache: &RewardCache) -> Result<()> {
+    write_json(cache_file.as_ref(), cache, "reward cache")
 }
 
 /// Merge new reward entries into existing cache
@@ -109,9 +297,211 @@ pub fn merge_reward_cache(cache: &mut RewardCache, new_entries: RewardCache) {
     }
 }
 
+/// What the reward cache holds for a stretch of consecutive dates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CoverageKind {
+    /// Cached with a non-zero reward
+    Rewarded,
+    /// Cached as exactly zero
+    Zero,
+    /// No cache entry
+    Missing,
+}
+
+/// A run of consecutive dates with the same coverage kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageSpan {
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub days: usize,
+    pub kind: CoverageKind,
+}
+
+/// Split `dates` (ascending) into runs by what the cache holds for `account`
+pub fn reward_coverage(
+    cache: &RewardCache,
+    account: &str,
+    dates: &[NaiveDate],
+) -> Vec<CoverageSpan> {
+    let mut spans: Vec<CoverageSpan> = Vec::new();
+    for &date in dates {
+        let kind = match get_cached_reward(cache, account, &date.format("%Y-%m-%d").to_string()) {
+            Some(reward) if reward != 0.0 => CoverageKind::Rewarded,
+            Some(_) => CoverageKind::Zero,
+            None => CoverageKind::Missing,
+        };
+        match spans.last_mut() {
+            Some(span) if span.kind == kind && span.end.succ_opt() == Some(date) => {
+                span.end = date;
+                span.days += 1;
+            }
+            _ => spans.push(CoverageSpan {
+                start: date,
+                end: date,
+                days: 1,
+                kind,
+            }),
+        }
+    }
+    spans
+}
+
 /// Get cached reward for an account and date
 pub fn get_cached_reward(cache: &RewardCache, account: &str, date: &str) -> Option<f64> {
     cache
         .get(account)
-        .and_then(|dates| dates.get(date).copied())
+        .and_then(|dates| dates.get(date))
+        .map(|reward| reward.total)
+}
+
+/// Address each account's cached rewards were scanned for: account_name -> address
+pub type RewardAccounts = HashMap<String, String>;
+
+/// Load the reward cache's account addresses from JSON file
+pub fn load_reward_accounts<P: AsRef<Path>>(cache_file: P) -> Result<RewardAccounts> {
+    read_json(cache_file.as_ref(), "reward accounts")
+}
+
+/// Save the reward cache's account addresses to JSON file
+pub fn save_reward_accounts<P: AsRef<Path>>(
+    cache_file: P,
+    accounts: &RewardAccounts,
+) -> Result<()> {
+    write_json(cache_file.as_ref(), accounts, "reward accounts")
+}
+
+/// Drop cached rewards of accounts whose name now points at a different
+/// address than when they were scanned, so their dates count as missing.
+/// Accounts with no recorded address are kept. Returns the dropped names.
+pub fn drop_moved_reward_accounts(
+    cache: &mut RewardCache,
+    scanned: &RewardAccounts,
+    accounts: &HashMap<String, String>,
+) -> Vec<String> {
+    let same = |a: &str, b: &str| match (crate::parse_ss58_address(a), crate::parse_ss58_address(b))
+    {
+        // The same key under another SS58 prefix is the same account
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    };
+    let mut dropped: Vec<String> = accounts
+        .iter()
+        .filter(|(name, address)| {
+            cache.contains_key(*name)
+                && scanned
+                    .get(*name)
+                    .is_some_and(|previous| !same(previous, address))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    dropped.sort();
+    for name in &dropped {
+        cache.remove(name);
+    }
+    dropped
+}
+
+// ============================================================================
+// Balance Cache
+// ============================================================================
+
+/// Balance cache type: account_name -> date -> full balance (free/reserved/frozen)
+pub type BalanceCache = HashMap<String, HashMap<String, Balance>>;
+
+/// Load balance cache from JSON file
+pub fn load_balance_cache<P: AsRef<Path>>(cache_file: P) -> Result<BalanceCache> {
+    read_json(cache_file.as_ref(), "balance cache")
+}
+
+/// Save balance cache to JSON file
+pub fn save_balance_cache<P: AsRef<Path>>(cache_file: P, cache: &BalanceCache) -> Result<()> {
+    write_json(cache_file.as_ref(), cache, "balance cache")
+}
+
+// ============================================================================
+// Stake Cache
+// ============================================================================
+
+/// Stake cache type: account_name -> date -> active bonded stake (CTC)
+pub type StakeCache = HashMap<String, HashMap<String, f64>>;
+
+/// Load stake cache from JSON file
+pub fn load_stake_cache<P: AsRef<Path>>(cache_file: P) -> Result<StakeCache> {
+    read_json(cache_file.as_ref(), "stake cache")
+}
+
+/// Save stake cache to JSON file
+pub fn save_stake_cache<P: AsRef<Path>>(cache_file: P, cache: &StakeCache) -> Result<()> {
+    write_json(cache_file.as_ref(), cache, "stake cache")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drop_moved_reward_accounts() {
+        let alice = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+        let bob = "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty";
+        let mut cache: RewardCache = HashMap::new();
+        for name in ["A", "B", "C"] {
+            cache
+                .entry(name.to_string())
+                .or_default()
+                .insert("2024-09-01".to_string(), 1.0.into());
+        }
+        let scanned: RewardAccounts = [("A", alice), ("B", alice)]
+            .into_iter()
+            .map(|(n, a)| (n.to_string(), a.to_string()))
+            .collect();
+        // A unchanged, B re-pointed, C scanned before addresses were recorded
+        let accounts: HashMap<String, String> = [("A", alice), ("B", bob), ("C", bob)]
+            .into_iter()
+            .map(|(n, a)| (n.to_string(), a.to_string()))
+            .collect();
+
+        let dropped = drop_moved_reward_accounts(&mut cache, &scanned, &accounts);
+        assert_eq!(dropped, vec!["B".to_string()]);
+        assert!(cache.contains_key("A") && cache.contains_key("C"));
+        assert!(!cache.contains_key("B"));
+    }
+
+    #[test]
+    fn test_reward_cache_reads_scalar_and_breakdown() {
+        let json = r#"{"A": {"2024-09-01": 1.5, "2024-09-02": {"total": 2.0, "by_validator": {"V1": 1.25, "V2": 0.75}}}}"#;
+        let cache: RewardCache = serde_json::from_str(json).unwrap();
+
+        assert_eq!(cache["A"]["2024-09-01"], CachedReward::from(1.5));
+        assert_eq!(cache["A"]["2024-09-02"].by_validator["V1"], 1.25);
+        assert_eq!(reward_totals(&cache)["A"]["2024-09-02"], 2.0);
+
+        // Entries without a breakdown are written back as plain numbers
+        let written = serde_json::to_value(&cache).unwrap();
+        assert_eq!(written["A"]["2024-09-01"], serde_json::json!(1.5));
+        assert_eq!(written["A"]["2024-09-02"]["total"], serde_json::json!(2.0));
+    }
+
+    #[test]
+    fn test_reward_coverage_spans() {
+        let json = r#"{"A": {"2024-09-01": 1.0, "2024-09-02": 2.0, "2024-09-03": 0.0, "2024-09-05": 1.0}}"#;
+        let cache: RewardCache = serde_json::from_str(json).unwrap();
+        let start = NaiveDate::from_ymd_opt(2024, 9, 1).unwrap();
+        let dates: Vec<NaiveDate> = start.iter_days().take(6).collect();
+
+        let kinds: Vec<(CoverageKind, usize)> = reward_coverage(&cache, "A", &dates)
+            .iter()
+            .map(|s| (s.kind, s.days))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (CoverageKind::Rewarded, 2),
+                (CoverageKind::Zero, 1),
+                (CoverageKind::Missing, 1),
+                (CoverageKind::Rewarded, 1),
+                (CoverageKind::Missing, 1),
+            ]
+        );
+        assert_eq!(reward_coverage(&cache, "B", &dates).len(), 1);
+    }
 }
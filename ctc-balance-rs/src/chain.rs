@@ -3,20 +3,123 @@
 //! Provides WebSocket RPC connection and block query functionality.
 
 use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use futures::stream::{BoxStream, StreamExt};
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
 use subxt::{
     backend::{legacy::LegacyRpcMethods, rpc::RpcClient},
     OnlineClient, PolkadotConfig,
 };
 
-use crate::{BLOCK_TIME_SECONDS, NODE_URL};
+use crate::{CtcError, BLOCK_TIME_SECONDS, CTC_DECIMALS, NODE_URL};
+
+/// Blocks checked on each side of the best candidate when bisection misses
+const NEARBY_SCAN_BLOCKS: u64 = 30;
+
+/// Smallest search window used around an anchor-based estimate
+const ANCHOR_MIN_WINDOW: u64 = 300;
+
+/// Search window around a genesis-based estimate before any search has run
+const DEFAULT_SEARCH_WINDOW: u64 = 40000;
+
+/// Smallest window a genesis-based search shrinks to after accurate estimates
+const MIN_SEARCH_WINDOW: u64 = 2000;
+
+/// Entries kept by the block lookup cache
+const BLOCK_LOOKUP_CAPACITY: usize = 4096;
+
+/// Default largest timestamp error (seconds) accepted for a found block
+pub const DEFAULT_MAX_BLOCK_ERROR_SECS: u64 = 5 * BLOCK_TIME_SECONDS;
+
+/// Extrinsic hash as reported by wallets and explorers: blake2-256 of its encoding
+fn extrinsic_hash(encoded: &[u8]) -> subxt::utils::H256 {
+    use blake2::{digest::consts::U32, Blake2b, Digest};
+
+    let digest: [u8; 32] = Blake2b::<U32>::digest(encoded).into();
+    subxt::utils::H256::from(digest)
+}
 
 /// Block information with number and hash
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BlockInfo {
     pub block: u64,
     pub hash: String,
+    /// Runtime spec version at this block (absent in caches written by older versions)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub spec_version: Option<u32>,
+}
+
+/// Which chain head counts as "latest"
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HeadMode {
+    /// Latest finalized block (reproducible)
+    #[default]
+    Finalized,
+    /// Best (possibly non-finalized) block
+    Best,
+}
+
+impl std::str::FromStr for HeadMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "finalized" => Ok(Self::Finalized),
+            "best" => Ok(Self::Best),
+            _ => Err(format!("Invalid head '{}': expected finalized or best", s)),
+        }
+    }
+}
+
+/// Least-recently-used map of block number -> (hash, timestamp) for the block
+/// search, which revisits the same blocks across overlapping searches
+#[derive(Debug)]
+pub struct BlockLookupCache {
+    capacity: usize,
+    entries: HashMap<u64, (String, u64)>,
+    /// Block numbers from least to most recently used
+    order: VecDeque<u64>,
+    /// Starting window for genesis-based searches, sized from how far the
+    /// previous estimate missed (kept here so connectors sharing the cache
+    /// share it too)
+    initial_window: u64,
+}
+
+impl BlockLookupCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            initial_window: DEFAULT_SEARCH_WINDOW,
+        }
+    }
+
+    fn touch(&mut self, block: u64) {
+        if let Some(pos) = self.order.iter().position(|&b| b == block) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(block);
+    }
+
+    fn get(&mut self, block: u64) -> Option<(String, u64)> {
+        let entry = self.entries.get(&block).cloned()?;
+        self.touch(block);
+        Some(entry)
+    }
+
+    fn insert(&mut self, block: u64, hash: String, timestamp: u64) {
+        if self.entries.insert(block, (hash, timestamp)).is_none()
+            && self.entries.len() > self.capacity
+        {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.touch(block);
+    }
 }
 
 /// Chain connector for Creditcoin3
@@ -25,6 +128,18 @@ pub struct ChainConnector {
     client: Option<Arc<OnlineClient<PolkadotConfig>>>,
     rpc: Option<Arc<LegacyRpcMethods<PolkadotConfig>>>,
     genesis_timestamp: Option<u64>,
+    /// Block whose timestamp marks the start of the chain (1 unless overridden)
+    genesis_block: u64,
+    head: HeadMode,
+    /// Token decimals from the chain's system properties, once read
+    decimals: Option<u32>,
+    /// Address prefix from the chain's system properties, once read
+    ss58_format: Option<u16>,
+    /// Largest timestamp error accepted by the block search
+    max_error_secs: u64,
+    /// Recently looked-up block hashes and timestamps, and the adaptive search
+    /// window (shareable between connectors)
+    block_lookups: Arc<Mutex<BlockLookupCache>>,
 }
 
 impl ChainConnector {
@@ -35,9 +150,38 @@ impl ChainConnector {
             client: None,
             rpc: None,
             genesis_timestamp: None,
+            genesis_block: 1,
+            head: HeadMode::default(),
+            decimals: None,
+            ss58_format: None,
+            max_error_secs: DEFAULT_MAX_BLOCK_ERROR_SECS,
+            block_lookups: Arc::new(Mutex::new(BlockLookupCache::new(BLOCK_LOOKUP_CAPACITY))),
         }
     }
 
+    /// Create a connector on clients that are already connected (see `rpc_client_from_url`)
+    pub fn from_clients(
+        client: Arc<OnlineClient<PolkadotConfig>>,
+        rpc: Arc<LegacyRpcMethods<PolkadotConfig>>,
+    ) -> Self {
+        let mut connector = Self::new(Some(""));
+        connector.client = Some(client);
+        connector.rpc = Some(rpc);
+        connector
+    }
+
+    /// Divisor converting raw native units to whole tokens
+    ///
+    /// Uses the decimals read by `get_chain_info`, or `CTC_DECIMALS` before that.
+    pub fn divisor(&self) -> f64 {
+        10f64.powi(self.decimals.unwrap_or(CTC_DECIMALS) as i32)
+    }
+
+    /// Address prefix read by `get_chain_info`, or `DEFAULT_SS58_PREFIX` before that
+    pub fn ss58_format(&self) -> u16 {
+        self.ss58_format.unwrap_or(crate::DEFAULT_SS58_PREFIX)
+    }
+
     /// Get the URL
     pub fn url(&self) -> &str {
         &self.url
@@ -45,13 +189,15 @@ impl ChainConnector {
 
     /// Connect to the node
     pub async fn connect(&mut self) -> Result<()> {
-        let rpc_client = RpcClient::from_url(&self.url)
-            .await
-            .context("Failed to connect to RPC")?;
+        let rpc_client = crate::rpc_client_from_url(&self.url).await?;
+        self.connect_with(rpc_client).await
+    }
 
+    /// Connect over an existing RPC connection instead of opening one from the URL
+    pub async fn connect_with(&mut self, rpc_client: RpcClient) -> Result<()> {
         let client = OnlineClient::<PolkadotConfig>::from_rpc_client(rpc_client.clone())
             .await
-            .context("Failed to create online client")?;
+            .map_err(|e| CtcError::Connection(format!("Failed to create online client: {}", e)))?;
 
         let rpc = LegacyRpcMethods::<PolkadotConfig>::new(rpc_client);
 
@@ -71,9 +217,9 @@ impl ChainConnector {
 
     /// Get the online client
     pub fn client(&self) -> Result<&Arc<OnlineClient<PolkadotConfig>>> {
-        self.client
-            .as_ref()
-            .context("Not connected. Call connect() first.")
+        self.client.as_ref().ok_or_else(|| {
+            CtcError::Connection("Not connected. Call connect() first.".into()).into()
+        })
     }
 
     /// Set the online client (injection for connector reuse)
@@ -83,9 +229,9 @@ impl ChainConnector {
 
     /// Get the RPC methods
     pub fn rpc(&self) -> Result<&Arc<LegacyRpcMethods<PolkadotConfig>>> {
-        self.rpc
-            .as_ref()
-            .context("Not connected. Call connect() first.")
+        self.rpc.as_ref().ok_or_else(|| {
+            CtcError::Connection("Not connected. Call connect() first.".into()).into()
+        })
     }
 
     /// Set the RPC methods (injection for connector reuse)
@@ -93,6 +239,27 @@ impl ChainConnector {
         self.rpc = Some(rpc);
     }
 
+    /// Get the block lookup cache
+    pub fn block_lookups(&self) -> Arc<Mutex<BlockLookupCache>> {
+        self.block_lookups.clone()
+    }
+
+    /// Set the block lookup cache (injection for connector reuse)
+    pub fn set_block_lookups(&mut self, lookups: Arc<Mutex<BlockLookupCache>>) {
+        self.block_lookups = lookups;
+    }
+
+    /// Choose which head `get_latest_block_number` resolves to
+    pub fn set_head_mode(&mut self, head: HeadMode) {
+        self.head = head;
+    }
+
+    /// Largest timestamp error (seconds) the block search accepts before
+    /// failing instead of returning its best candidate
+    pub fn set_max_block_error(&mut self, secs: u64) {
+        self.max_error_secs = secs;
+    }
+
     /// Get chain information
     pub async fn get_chain_info(&mut self) -> Result<ChainInfo> {
         self.ensure_connected().await?;
@@ -109,6 +276,24 @@ impl ChainConnector {
             .await
             .unwrap_or_else(|_| "Unknown".to_string());
 
+        // Token decimals from system properties, 18 (CTC) if missing
+        let props = rpc.system_properties().await.ok();
+        let decimals = props.as_ref().and_then(parse_token_decimals);
+        let ss58_format = props
+            .as_ref()
+            .and_then(|p| p.get("ss58Format")?.as_u64())
+            .and_then(|f| u16::try_from(f).ok())
+            .unwrap_or(crate::DEFAULT_SS58_PREFIX);
+        if decimals.is_none() {
+            tracing::warn!(
+                "tokenDecimals missing from system properties; assuming {}",
+                CTC_DECIMALS
+            );
+        }
+        let decimals = decimals.unwrap_or(CTC_DECIMALS);
+        self.decimals = Some(decimals);
+        self.ss58_format = Some(ss58_format);
+
         Ok(ChainInfo {
             chain: chain_name,
             version: format!(
@@ -116,6 +301,8 @@ impl ChainConnector {
                 runtime_version.spec_version, runtime_version.transaction_version
             ),
             genesis_hash: format!("{:?}", genesis_hash),
+            decimals,
+            ss58_format,
         })
     }
 
@@ -125,13 +312,52 @@ impl ChainConnector {
         let rpc = self.rpc()?;
 
         let hash = crate::retry!(rpc.chain_get_block_hash(Some(block_number.into())))?
-            .context(format!("Block {} not found", block_number))?;
+            .ok_or_else(|| CtcError::NotFound(format!("Block {} not found", block_number)))?;
 
         Ok(format!("{:?}", hash))
     }
 
-    /// Get latest finalized block number
+    /// Get the runtime spec version active at a block
+    pub async fn spec_version_at(&mut self, block_hash: &str) -> Result<u32> {
+        self.ensure_connected().await?;
+        let rpc = self.rpc()?;
+        let hash = crate::parse_block_hash(block_hash)?;
+
+        let version = crate::retry!(rpc.state_get_runtime_version(Some(hash)))?;
+        Ok(version.spec_version)
+    }
+
+    /// Get latest block number according to the configured head mode
     pub async fn get_latest_block_number(&mut self) -> Result<u64> {
+        match self.head {
+            HeadMode::Finalized => self.get_finalized_block_number().await,
+            HeadMode::Best => self.get_best_block_number().await,
+        }
+    }
+
+    /// Get latest finalized block number (via `chain_getFinalizedHead`)
+    pub async fn get_finalized_block_number(&mut self) -> Result<u64> {
+        self.ensure_connected().await?;
+        let rpc = self.rpc()?;
+
+        let hash = crate::retry!(rpc.chain_get_finalized_head())?;
+        let header = crate::retry!(rpc.chain_get_header(Some(hash)))?.context("No header")?;
+        Ok(header.number as u64)
+    }
+
+    /// Block numbers of new chain heads as they arrive, finalized or best per
+    /// the head mode. The stream ends when the subscription's connection closes.
+    pub async fn subscribe_new_heads(&self) -> Result<BoxStream<'static, Result<u64>>> {
+        let rpc = self.rpc()?;
+        let heads = match self.head {
+            HeadMode::Finalized => rpc.chain_subscribe_finalized_heads().await?,
+            HeadMode::Best => rpc.chain_subscribe_new_heads().await?,
+        };
+        Ok(heads.map(|header| Ok(header?.number as u64)).boxed())
+    }
+
+    /// Get best (possibly non-finalized) block number
+    pub async fn get_best_block_number(&mut self) -> Result<u64> {
         self.ensure_connected().await?;
         let rpc = self.rpc()?;
 
@@ -144,46 +370,150 @@ impl ChainConnector {
         self.ensure_connected().await?;
         let client = self.client()?;
 
-        // Parse the block hash
-        let hash_bytes =
-            hex::decode(block_hash.trim_start_matches("0x")).context("Invalid block hash")?;
-        let hash: [u8; 32] = hash_bytes
-            .try_into()
-            .map_err(|_| anyhow::anyhow!("Invalid hash length"))?;
-        let block_hash = subxt::utils::H256::from(hash);
+        let block_hash = crate::parse_block_hash(block_hash)?;
 
         // Query Timestamp.Now storage
         let storage_address = subxt::dynamic::storage("Timestamp", "Now", ());
 
         let storage_value = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?
-            .context("Timestamp not found")?;
+            .ok_or_else(|| CtcError::NotFound(format!("Timestamp at {:?}", block_hash)))?;
 
         // Decode as u64 (milliseconds)
         let timestamp_ms: u128 = storage_value
             .as_type()
-            .context("Failed to decode timestamp")?;
+            .map_err(|e| CtcError::Decode(format!("Failed to decode timestamp: {}", e)))?;
 
         Ok((timestamp_ms / 1000) as u64)
     }
 
-    /// Get genesis timestamp (from block 1)
+    /// Find the block that includes an extrinsic, scanning back from the head
+    ///
+    /// Nodes keep no transaction index by default, so up to `max_blocks` blocks
+    /// (newest first) are fetched and their extrinsics hashed. `None` if not found.
+    pub async fn block_of_extrinsic(
+        &mut self,
+        tx_hash: &str,
+        max_blocks: u64,
+    ) -> Result<Option<BlockInfo>> {
+        use futures::stream::{self, StreamExt};
+
+        let target = crate::parse_block_hash(tx_hash)?;
+        let latest = self.get_latest_block_number().await?;
+        let first = latest.saturating_sub(max_blocks.saturating_sub(1));
+        let rpc = self.rpc()?.clone();
+
+        let mut blocks = stream::iter((first..=latest).rev())
+            .map(|number| {
+                let rpc = rpc.clone();
+                async move {
+                    let hash = crate::retry!(rpc.chain_get_block_hash(Some(number.into())))?
+                        .ok_or_else(|| CtcError::NotFound(format!("Block {} not found", number)))?;
+                    let block = crate::retry!(rpc.chain_get_block(Some(hash)))?
+                        .ok_or_else(|| CtcError::NotFound(format!("Block {} not found", number)))?;
+                    let found = block
+                        .block
+                        .extrinsics
+                        .iter()
+                        .any(|xt| extrinsic_hash(&xt.0) == target);
+                    Ok::<_, anyhow::Error>(found.then(|| BlockInfo {
+                        block: number,
+                        hash: format!("{:?}", hash),
+                        spec_version: None,
+                    }))
+                }
+            })
+            .buffered(crate::CONCURRENCY_EVENTS);
+
+        while let Some(result) = blocks.next().await {
+            if let Some(info) = result? {
+                return Ok(Some(info));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hash and timestamp of a block, served from the lookup cache when possible
+    async fn block_at(&mut self, block_number: u64) -> Result<(String, u64)> {
+        if let Some(entry) = self.block_lookups.lock().unwrap().get(block_number) {
+            return Ok(entry);
+        }
+        let hash = self.get_block_hash(block_number).await?;
+        let timestamp = self.get_block_timestamp(&hash).await?;
+        self.block_lookups
+            .lock()
+            .unwrap()
+            .insert(block_number, hash.clone(), timestamp);
+        Ok((hash, timestamp))
+    }
+
+    /// Treat `block` as the chain's first block (e.g. on a fork or dev chain)
+    pub fn set_genesis_block(&mut self, block: u64) {
+        if block != self.genesis_block {
+            self.genesis_block = block;
+            self.genesis_timestamp = None;
+        }
+    }
+
+    /// Get genesis timestamp (from block 1, or the `set_genesis_block` override)
     pub async fn get_genesis_timestamp(&mut self) -> Result<u64> {
         if let Some(ts) = self.genesis_timestamp {
             return Ok(ts);
         }
 
-        let hash = self.get_block_hash(1).await?;
+        let hash = self.get_block_hash(self.genesis_block).await?;
         let ts = self.get_block_timestamp(&hash).await?;
         self.genesis_timestamp = Some(ts);
 
         Ok(ts)
     }
 
+    /// Average block time in seconds, measured from the genesis block to the latest block
+    pub async fn average_block_time(&mut self) -> Result<f64> {
+        let latest_block = self.get_latest_block_number().await?;
+        let latest_hash = self.get_block_hash(latest_block).await?;
+        let latest_ts = self.get_block_timestamp(&latest_hash).await?;
+        let genesis_ts = self.get_genesis_timestamp().await?;
+
+        let elapsed = latest_ts.saturating_sub(genesis_ts);
+        if latest_block <= self.genesis_block || elapsed == 0 {
+            return Ok(BLOCK_TIME_SECONDS as f64);
+        }
+        Ok(elapsed as f64 / (latest_block - self.genesis_block) as f64)
+    }
+
     /// Find block at target timestamp using binary search
+    ///
+    /// Fails with `CtcError::NotFound` when no block lies within the configured
+    /// maximum error (`set_max_block_error`), rather than returning a bad match.
     pub async fn find_block_at_timestamp(
         &mut self,
         target_timestamp: u64,
         tolerance_seconds: u64,
+    ) -> Result<BlockInfo> {
+        self.find_block_near(target_timestamp, tolerance_seconds, None)
+            .await
+    }
+
+    /// Find the block at midnight UTC on `date`, as the daily history uses
+    /// by default. A date past the chain head resolves to the latest block.
+    pub async fn find_block_for_date(&mut self, date: NaiveDate) -> Result<BlockInfo> {
+        let midnight = date.and_time(NaiveTime::MIN).and_utc().timestamp();
+        let timestamp = u64::try_from(midnight)
+            .map_err(|_| CtcError::InvalidInput(format!("Date {} is before 1970", date)))?;
+        self.find_block_at_timestamp(timestamp, 60).await
+    }
+
+    /// Find block at target timestamp, seeding the search from a known
+    /// `(block, timestamp)` anchor when one is available.
+    ///
+    /// A nearby anchor (e.g. the previous date's block) shrinks the search window
+    /// from tens of thousands of blocks to a few hundred; a poor anchor only costs
+    /// the usual widen-and-retry.
+    pub async fn find_block_near(
+        &mut self,
+        target_timestamp: u64,
+        tolerance_seconds: u64,
+        anchor: Option<(u64, u64)>,
     ) -> Result<BlockInfo> {
         let latest_block = self.get_latest_block_number().await?;
         let latest_hash = self.get_block_hash(latest_block).await?;
@@ -191,34 +521,149 @@ impl ChainConnector {
 
         // If target is in the future, return latest block
         if target_timestamp >= latest_ts {
-            return Ok(BlockInfo {
-                block: latest_block,
-                hash: latest_hash,
-            });
+            return self.block_info(latest_block, latest_hash).await;
         }
 
         let genesis_ts = self.get_genesis_timestamp().await?;
-        // If target is before genesis, return block 1
+        // A date more than a day before genesis has no block at all
+        if target_timestamp + 86400 < genesis_ts {
+            return Err(CtcError::NotFound(format!(
+                "timestamp {} is before the chain's genesis ({})",
+                target_timestamp, genesis_ts
+            ))
+            .into());
+        }
+        // If target is shortly before genesis (the genesis date), return the genesis block
+        let genesis_block = self.genesis_block;
         if target_timestamp <= genesis_ts {
-            return Ok(BlockInfo {
-                block: 1,
-                hash: self.get_block_hash(1).await?,
-            });
+            let hash = self.get_block_hash(genesis_block).await?;
+            return self.block_info(genesis_block, hash).await;
         }
 
         // Estimate block number
         let total_time = latest_ts.saturating_sub(genesis_ts);
         let block_rate = if total_time > 0 {
-            latest_block as f64 / total_time as f64
+            latest_block.saturating_sub(genesis_block) as f64 / total_time as f64
         } else {
             1.0 / BLOCK_TIME_SECONDS as f64
         };
-        let estimated_block = ((target_timestamp - genesis_ts) as f64 * block_rate) as u64;
+        let (estimated_block, window) = match anchor {
+            Some((anchor_block, anchor_ts)) => {
+                let offset = (target_timestamp as f64 - anchor_ts as f64) * block_rate;
+                let estimate = (anchor_block as f64 + offset).max(genesis_block as f64) as u64;
+                // Allow 2% block time drift over the distance to the anchor
+                let window = ANCHOR_MIN_WINDOW + (offset.abs() * 0.02) as u64;
+                (estimate, window.min(40000))
+            }
+            None => (
+                genesis_block + ((target_timestamp - genesis_ts) as f64 * block_rate) as u64,
+                self.block_lookups.lock().unwrap().initial_window,
+            ),
+        };
+        let (mut best_block, mut best_hash, mut best_diff) = self
+            .search_window(
+                target_timestamp,
+                tolerance_seconds,
+                estimated_block,
+                window,
+                latest_block,
+            )
+            .await?;
+
+        // Block time is not strictly monotonic around runtime upgrades or stalls,
+        // and after irregular block times the true block can lie outside the
+        // window. Double the window (up to the whole chain) until a match is
+        // found, then linear-scan the neighbourhood of the best candidate.
+        let mut expanded = window;
+        while best_diff > tolerance_seconds && expanded < latest_block {
+            expanded = (expanded * 2).min(latest_block);
+            let (block, hash, diff) = self
+                .search_window(
+                    target_timestamp,
+                    tolerance_seconds,
+                    estimated_block,
+                    expanded,
+                    latest_block,
+                )
+                .await?;
+            if diff < best_diff {
+                (best_block, best_hash, best_diff) = (block, hash, diff);
+            }
+        }
+        if expanded > window {
+            tracing::info!(
+                "Block search around {} expanded from ±{} to ±{} blocks (found block {}, {}s off)",
+                estimated_block,
+                window,
+                expanded,
+                best_block,
+                best_diff
+            );
+        }
+
+        // Size the next genesis-based search from how far this estimate missed
+        if anchor.is_none() && best_diff <= tolerance_seconds {
+            let miss = best_block.abs_diff(estimated_block);
+            self.block_lookups.lock().unwrap().initial_window =
+                (miss * 2).clamp(MIN_SEARCH_WINDOW, DEFAULT_SEARCH_WINDOW);
+        }
+
+        if best_diff > tolerance_seconds {
+            let from = best_block.saturating_sub(NEARBY_SCAN_BLOCKS).max(1);
+            let to = std::cmp::min(latest_block, best_block + NEARBY_SCAN_BLOCKS);
+            for n in from..=to {
+                let (block_hash, block_time) = self.block_at(n).await?;
+                let diff = block_time.abs_diff(target_timestamp);
+                if diff < best_diff {
+                    (best_block, best_hash, best_diff) = (n, block_hash, diff);
+                    if diff <= tolerance_seconds {
+                        break;
+                    }
+                }
+            }
+        }
+        if best_diff > tolerance_seconds {
+            let date = chrono::DateTime::from_timestamp(target_timestamp as i64, 0)
+                .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+                .unwrap_or_else(|| target_timestamp.to_string());
+            if best_diff > self.max_error_secs.max(tolerance_seconds) {
+                tracing::warn!(
+                    "No block near {}: best is block {} at {}s off (max {}s); skipping",
+                    date,
+                    best_block,
+                    best_diff,
+                    self.max_error_secs
+                );
+                return Err(CtcError::NotFound(format!(
+                    "no block within {}s of {} (best: block {}, {}s off)",
+                    self.max_error_secs, date, best_block, best_diff
+                ))
+                .into());
+            }
+            tracing::warn!(
+                "Block {} for {} is {}s off target (tolerance {}s); this row may be off by a block",
+                best_block,
+                date,
+                best_diff,
+                tolerance_seconds
+            );
+        }
 
-        // Search window
-        let window = 40000u64; // Doubled window for safety
-        let mut low = estimated_block.saturating_sub(window);
-        let mut high = std::cmp::min(latest_block, estimated_block + window);
+        self.block_info(best_block, best_hash).await
+    }
+
+    /// Bisect `estimate ± window` for the block closest to `target_timestamp`.
+    /// Returns the best (block, hash, diff) seen, stopping early within tolerance.
+    async fn search_window(
+        &mut self,
+        target_timestamp: u64,
+        tolerance_seconds: u64,
+        estimate: u64,
+        window: u64,
+        latest_block: u64,
+    ) -> Result<(u64, String, u64)> {
+        let mut low = estimate.saturating_sub(window);
+        let mut high = std::cmp::min(latest_block, estimate + window);
 
         let mut best_block = 0u64;
         let mut best_hash = String::new();
@@ -230,8 +675,7 @@ impl ChainConnector {
                 low = 1;
                 continue;
             }
-            let block_hash = self.get_block_hash(mid).await?;
-            let block_time = self.get_block_timestamp(&block_hash).await?;
+            let (block_hash, block_time) = self.block_at(mid).await?;
 
             let diff = block_time.abs_diff(target_timestamp);
 
@@ -242,10 +686,7 @@ impl ChainConnector {
             }
 
             if diff <= tolerance_seconds {
-                return Ok(BlockInfo {
-                    block: mid,
-                    hash: block_hash,
-                });
+                break;
             }
 
             if block_time < target_timestamp {
@@ -255,9 +696,16 @@ impl ChainConnector {
             }
         }
 
+        Ok((best_block, best_hash, best_diff))
+    }
+
+    /// Build a BlockInfo, tagging it with the runtime spec version when available
+    async fn block_info(&mut self, block: u64, hash: String) -> Result<BlockInfo> {
+        let spec_version = self.spec_version_at(&hash).await.ok();
         Ok(BlockInfo {
-            block: best_block,
-            hash: best_hash,
+            block,
+            hash,
+            spec_version,
         })
     }
 }
@@ -268,6 +716,21 @@ pub struct ChainInfo {
     pub chain: String,
     pub version: String,
     pub genesis_hash: String,
+    /// Native token decimals (`tokenDecimals`)
+    pub decimals: u32,
+    /// Address prefix (`ss58Format`)
+    pub ss58_format: u16,
+}
+
+/// Read `tokenDecimals` from system properties; multi-token chains report an
+/// array whose first entry is the native token
+fn parse_token_decimals(props: &serde_json::Map<String, serde_json::Value>) -> Option<u32> {
+    let value = props.get("tokenDecimals")?;
+    let value = match value {
+        serde_json::Value::Array(items) => items.first()?,
+        other => other,
+    };
+    value.as_u64().map(|d| d as u32)
 }
 
 impl std::fmt::Display for ChainInfo {
@@ -275,3 +738,22 @@ impl std::fmt::Display for ChainInfo {
         write!(f, "{} v{}", self.chain, self.version)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_lookup_cache_evicts_least_recent() {
+        let mut cache = BlockLookupCache::new(2);
+        cache.insert(1, "0x01".into(), 10);
+        cache.insert(2, "0x02".into(), 20);
+        // Reading block 1 makes block 2 the least recently used
+        assert_eq!(cache.get(1), Some(("0x01".to_string(), 10)));
+        cache.insert(3, "0x03".into(), 30);
+
+        assert_eq!(cache.get(2), None);
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+    }
+}
@@ -1,15 +1,23 @@
 //! CSV output module.
 //!
 //! Generates combined and individual CSV files for balance history.
+//!
+//! Account columns are always written in sorted name order, so the combined
+//! CSV keeps the same layout between runs and diffs cleanly.
 
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fs::{self, File};
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use crate::accounts::{is_active_on, DateWindow};
+use crate::balance::{Balance, BalanceMetric};
+use crate::cache::{BalanceCache, RewardCache, RewardTotals};
+use crate::reward::EraPoints;
 
 /// Balance history entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct HistoryEntry {
     pub date: String,
     pub balances: HashMap<String, f64>,
@@ -21,16 +29,134 @@ pub struct HistoryEntry {
     pub total_reward: f64,
     pub reward_avg10: f64,
     pub total_reward_cumulative: f64,
+    pub reward_cumulatives: HashMap<String, f64>,
+    // Split of `diff` (see `calculate_diffs`): capital moved in or out, and the
+    // running sum of the rest, i.e. reward income reflected in the balance
+    pub principal_flow: f64,
+    pub income_excluding_principal: f64,
+    // APR fields (percent); absent when stake is zero or unknown
+    pub aprs: HashMap<String, f64>,
+    pub total_apr: Option<f64>,
+    // Balance subtotal per account group; empty when accounts aren't grouped
+    pub group_totals: BTreeMap<String, f64>,
+    // Free, reserved and frozen summed over the total's accounts, whatever the
+    // metric; `None` unless requested and every account's full balance is known
+    pub component_totals: Option<Balance>,
+}
+
+/// How values are cut to the CSV's fixed number of decimals
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rounding {
+    /// Nearest, halves away from zero
+    #[default]
+    Round,
+    /// Toward zero
+    Trunc,
+    /// Toward negative infinity
+    Floor,
+    /// Toward positive infinity
+    Ceil,
+}
+
+impl std::str::FromStr for Rounding {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "round" => Ok(Self::Round),
+            "trunc" => Ok(Self::Trunc),
+            "floor" => Ok(Self::Floor),
+            "ceil" => Ok(Self::Ceil),
+            _ => Err(format!(
+                "Invalid rounding '{}': expected round, trunc, floor or ceil",
+                s
+            )),
+        }
+    }
 }
 
-/// Save combined CSV with all accounts
+/// Format `value` with `decimals` places, cutting it as `rounding` says
+///
+/// Works on the scaled integer rather than `{:.N}`, so e.g. truncation is exact.
+/// The scaled value is snapped to the nearest integer first when it is only off
+/// by float noise (2.3 * 10 = 22.999...), so that doesn't truncate to 2.2.
+pub fn format_decimal(value: f64, decimals: u32, rounding: Rounding) -> String {
+    let factor = 10f64.powi(decimals as i32);
+    let mut scaled = value * factor;
+    let nearest = scaled.round();
+    if (scaled - nearest).abs() < 1e-9 * nearest.abs().max(1.0) {
+        scaled = nearest;
+    }
+    let scaled = match rounding {
+        Rounding::Round => scaled.round(),
+        Rounding::Trunc => scaled.trunc(),
+        Rounding::Floor => scaled.floor(),
+        Rounding::Ceil => scaled.ceil(),
+    } as i128;
+
+    let sign = if scaled < 0 { "-" } else { "" };
+    let magnitude = scaled.unsigned_abs();
+    if decimals == 0 {
+        return format!("{}{}", sign, magnitude);
+    }
+    let factor = 10u128.pow(decimals);
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        magnitude / factor,
+        magnitude % factor,
+        width = decimals as usize
+    )
+}
+
+/// Account names in column order: sorted and deduplicated
+pub fn column_order(account_names: &[String]) -> Vec<String> {
+    let mut names = account_names.to_vec();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Account columns of a combined CSV header: everything between `date` and `total`
+fn account_columns(headers: &csv::StringRecord) -> Vec<String> {
+    headers
+        .iter()
+        .skip(1)
+        .take_while(|h| *h != "total")
+        .map(|h| h.to_string())
+        .collect()
+}
+
+/// Account columns of an existing combined CSV, in file order (empty if absent)
+pub fn load_csv_accounts<P: AsRef<Path>>(csv_file: P) -> Result<Vec<String>> {
+    let path = csv_file.as_ref();
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut reader = csv::Reader::from_path(path).context("Failed to open CSV")?;
+    let headers = reader.headers().context("Failed to read headers")?;
+    Ok(account_columns(headers))
+}
+
+/// Save combined CSV with all accounts (columns in `column_order`).
+///
+/// With `include_total_with_rewards`, a `total_with_rewards` column holds
+/// `total + total_reward_cumulative`.
+#[allow(clippy::too_many_arguments)]
 pub fn save_combined_csv<P: AsRef<Path>>(
     output_file: P,
     account_names: &[String],
     entries: &[HistoryEntry],
     include_rewards: bool,
+    include_total_with_rewards: bool,
+    include_apr: bool,
+    rounding: Rounding,
+    checksum: bool,
 ) -> Result<()> {
     let path = output_file.as_ref();
+    let account_names = &column_order(account_names)[..];
 
     // Ensure parent directory exists
     if let Some(parent) = path.parent() {
@@ -49,6 +175,18 @@ pub fn save_combined_csv<P: AsRef<Path>>(
         "diff".to_string(),
         "diff_avg10".to_string(),
     ]);
+    let groups: BTreeSet<&String> = entries.iter().flat_map(|e| e.group_totals.keys()).collect();
+    for group in &groups {
+        header.push(format!("group_{}_total", group));
+    }
+    let include_components = entries.iter().any(|e| e.component_totals.is_some());
+    if include_components {
+        header.extend([
+            "total_free".to_string(),
+            "total_reserved".to_string(),
+            "total_frozen".to_string(),
+        ]);
+    }
 
     // Add reward columns if enabled
     if include_rewards {
@@ -60,54 +198,544 @@ pub fn save_combined_csv<P: AsRef<Path>>(
             "reward_avg10".to_string(),
             "total_reward_cumulative".to_string(),
         ]);
+        if include_total_with_rewards {
+            header.push("total_with_rewards".to_string());
+        }
+        for name in account_names {
+            header.push(format!("{}_reward_cumulative", name));
+        }
+        header.extend([
+            "principal_flow".to_string(),
+            "income_excluding_principal".to_string(),
+        ]);
+    }
+    if include_apr {
+        for name in account_names {
+            header.push(format!("{}_apr", name));
+        }
+        header.push("apr".to_string());
     }
     writeln!(file, "{}", header.join(","))?;
+    let fmt = |value: f64, decimals: u32| format_decimal(value, decimals, rounding);
 
     // Write data rows
     for entry in entries {
         let mut row = vec![entry.date.clone()];
 
+        // Accounts absent from an entry (outside their date window) get empty cells
         for name in account_names {
-            let balance = entry.balances.get(name).unwrap_or(&0.0);
-            row.push(format!("{:.1}", balance));
+            row.push(
+                entry
+                    .balances
+                    .get(name)
+                    .map(|&b| fmt(b, 1))
+                    .unwrap_or_default(),
+            );
         }
 
-        row.push(format!("{:.1}", entry.total));
-        row.push(format!("{:.1}", entry.diff));
-        row.push(format!("{:.1}", entry.diff_avg10));
+        row.push(fmt(entry.total, 1));
+        row.push(fmt(entry.diff, 1));
+        row.push(fmt(entry.diff_avg10, 1));
+        for group in &groups {
+            row.push(
+                entry
+                    .group_totals
+                    .get(*group)
+                    .map(|&t| fmt(t, 1))
+                    .unwrap_or_default(),
+            );
+        }
+        if include_components {
+            match &entry.component_totals {
+                Some(b) => row.extend([fmt(b.free, 1), fmt(b.reserved, 1), fmt(b.frozen, 1)]),
+                None => row.extend([String::new(), String::new(), String::new()]),
+            }
+        }
 
         // Add reward data if enabled
         if include_rewards {
             for name in account_names {
-                let reward = entry.rewards.get(name).unwrap_or(&0.0);
-                row.push(format!("{:.4}", reward));
+                row.push(
+                    entry
+                        .rewards
+                        .get(name)
+                        .map(|&r| fmt(r, 4))
+                        .unwrap_or_default(),
+                );
+            }
+            row.push(fmt(entry.total_reward, 4));
+            row.push(fmt(entry.reward_avg10, 4));
+            row.push(fmt(entry.total_reward_cumulative, 4));
+            if include_total_with_rewards {
+                row.push(fmt(entry.total + entry.total_reward_cumulative, 4));
             }
-            row.push(format!("{:.4}", entry.total_reward));
-            row.push(format!("{:.4}", entry.reward_avg10));
-            row.push(format!("{:.4}", entry.total_reward_cumulative));
+            for name in account_names {
+                row.push(
+                    entry
+                        .reward_cumulatives
+                        .get(name)
+                        .filter(|_| entry.balances.contains_key(name))
+                        .map(|&c| fmt(c, 4))
+                        .unwrap_or_default(),
+                );
+            }
+            row.push(fmt(entry.principal_flow, 4));
+            row.push(fmt(entry.income_excluding_principal, 4));
+        }
+        if include_apr {
+            for name in account_names {
+                row.push(entry.aprs.get(name).map(|&a| fmt(a, 2)).unwrap_or_default());
+            }
+            row.push(entry.total_apr.map(|a| fmt(a, 2)).unwrap_or_default());
         }
 
         writeln!(file, "{}", row.join(","))?;
     }
+    drop(file);
+
+    // A stale sidecar would flag the new file as corrupt on the next load
+    let sidecar = checksum_path(path);
+    if checksum {
+        let (rows, digest) = data_rows_digest(&fs::read_to_string(path)?);
+        fs::write(&sidecar, format!("rows {}\nsha256 {}\n", rows, digest))
+            .context("Failed to write checksum file")?;
+    } else if sidecar.exists() {
+        fs::remove_file(&sidecar).context("Failed to remove stale checksum file")?;
+    }
+
+    Ok(())
+}
+
+/// Save the totals view of the combined CSV: the same rows without the
+/// per-account columns (`date,total,diff,diff_avg10,...,total_reward,...`)
+pub fn save_totals_csv<P: AsRef<Path>>(
+    output_file: P,
+    entries: &[HistoryEntry],
+    include_rewards: bool,
+    include_total_with_rewards: bool,
+    include_apr: bool,
+    rounding: Rounding,
+) -> Result<()> {
+    save_combined_csv(
+        output_file,
+        &[],
+        entries,
+        include_rewards,
+        include_total_with_rewards,
+        include_apr,
+        rounding,
+        false,
+    )
+}
+
+/// Checksum sidecar of a CSV (`<file>.sha256`), written with `--checksum`
+pub fn checksum_path(csv_file: &Path) -> PathBuf {
+    let mut name = csv_file.as_os_str().to_os_string();
+    name.push(".sha256");
+    PathBuf::from(name)
+}
+
+/// Row count and SHA-256 (hex) of a CSV's data rows, header excluded
+fn data_rows_digest(content: &str) -> (usize, String) {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    let mut rows = 0;
+    for line in content.lines().skip(1) {
+        hasher.update(line.as_bytes());
+        hasher.update(b"\n");
+        rows += 1;
+    }
+    (rows, hex::encode(hasher.finalize()))
+}
+
+/// Check a CSV against its checksum sidecar: `None` when there is no sidecar,
+/// otherwise a description of each mismatch (empty when intact)
+pub fn verify_checksum<P: AsRef<Path>>(csv_file: P) -> Result<Option<Vec<String>>> {
+    let path = csv_file.as_ref();
+    let sidecar = checksum_path(path);
+    if !sidecar.exists() {
+        return Ok(None);
+    }
+    let expected = fs::read_to_string(&sidecar).context("Failed to read checksum file")?;
+    let field = |key: &str| {
+        expected
+            .lines()
+            .find_map(|line| line.strip_prefix(key))
+            .map(|value| value.trim().to_string())
+    };
+    let (rows, digest) = data_rows_digest(&fs::read_to_string(path)?);
+
+    let mut issues = Vec::new();
+    if field("rows ") != Some(rows.to_string()) {
+        issues.push(format!(
+            "{} data rows, checksum file says {}",
+            rows,
+            field("rows ").unwrap_or_else(|| "?".to_string())
+        ));
+    }
+    if field("sha256 ") != Some(digest) {
+        issues.push("SHA-256 of the data rows does not match".to_string());
+    }
+    Ok(Some(issues))
+}
+
+/// Balances (and rewards) at one explicitly requested block
+#[derive(Debug, Clone)]
+pub struct BlockEntry {
+    pub block: u64,
+    pub hash: String,
+    /// Block timestamp (UTC, RFC 3339)
+    pub time: String,
+    pub balances: HashMap<String, f64>,
+    pub total: f64,
+    pub rewards: HashMap<String, f64>,
+    pub total_reward: f64,
+}
+
+/// Save a CSV keyed by block number (`block,hash,time,<accounts>,total,...`)
+pub fn save_block_csv<P: AsRef<Path>>(
+    output_file: P,
+    account_names: &[String],
+    entries: &[BlockEntry],
+    include_rewards: bool,
+) -> Result<()> {
+    let path = output_file.as_ref();
+    let account_names = &column_order(account_names)[..];
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "{}", block_csv_header(account_names, include_rewards))?;
+    for entry in entries {
+        writeln!(
+            file,
+            "{}",
+            block_csv_row(account_names, entry, include_rewards)
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Append one row to a block CSV as written by `save_block_csv` (without
+/// rewards), creating it with its header first if needed
+pub fn append_block_csv<P: AsRef<Path>>(
+    output_file: P,
+    account_names: &[String],
+    entry: &BlockEntry,
+) -> Result<()> {
+    let path = output_file.as_ref();
+    let account_names = &column_order(account_names)[..];
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let is_new = fs::metadata(path).map_or(true, |m| m.len() == 0);
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Failed to open CSV file")?;
+    if is_new {
+        writeln!(file, "{}", block_csv_header(account_names, false))?;
+    }
+    writeln!(file, "{}", block_csv_row(account_names, entry, false))?;
+    Ok(())
+}
+
+fn block_csv_header(account_names: &[String], include_rewards: bool) -> String {
+    let mut header = vec!["block".to_string(), "hash".to_string(), "time".to_string()];
+    header.extend(account_names.iter().cloned());
+    header.push("total".to_string());
+    if include_rewards {
+        header.extend(account_names.iter().map(|name| format!("{}_reward", name)));
+        header.push("total_reward".to_string());
+    }
+    header.join(",")
+}
+
+fn block_csv_row(account_names: &[String], entry: &BlockEntry, include_rewards: bool) -> String {
+    let mut row = vec![
+        entry.block.to_string(),
+        entry.hash.clone(),
+        entry.time.clone(),
+    ];
+    for name in account_names {
+        row.push(format!(
+            "{:.4}",
+            entry.balances.get(name).copied().unwrap_or(0.0)
+        ));
+    }
+    row.push(format!("{:.4}", entry.total));
+    if include_rewards {
+        for name in account_names {
+            row.push(format!(
+                "{:.4}",
+                entry.rewards.get(name).copied().unwrap_or(0.0)
+            ));
+        }
+        row.push(format!("{:.4}", entry.total_reward));
+    }
+    row.join(",")
+}
+
+/// Save the per-validator reward breakdown as `date,account,validator,amount`.
+///
+/// Days without a breakdown (claimed basis, or cached before breakdowns were
+/// recorded) have no rows.
+pub fn save_reward_breakdown_csv<P: AsRef<Path>>(
+    output_file: P,
+    rewards: &RewardCache,
+) -> Result<()> {
+    let path = output_file.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut rows: Vec<(&String, &String, &String, f64)> = rewards
+        .iter()
+        .flat_map(|(account, dates)| {
+            dates.iter().flat_map(move |(date, reward)| {
+                reward
+                    .by_validator
+                    .iter()
+                    .map(move |(validator, amount)| (date, account, validator, *amount))
+            })
+        })
+        .collect();
+    rows.sort_by(|a, b| (a.0, a.1, a.2).cmp(&(b.0, b.1, b.2)));
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "date,account,validator,amount")?;
+    for (date, account, validator, amount) in rows {
+        writeln!(file, "{},{},{},{:.6}", date, account, validator, amount)?;
+    }
+    Ok(())
+}
+
+/// Save each account's nonce per date, one column per account like the
+/// combined CSV. Cells without a known nonce are left empty.
+pub fn save_nonce_csv<P: AsRef<Path>>(
+    output_file: P,
+    account_names: &[String],
+    dates: &[String],
+    balances: &BalanceCache,
+) -> Result<()> {
+    let path = output_file.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let columns = column_order(account_names);
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "date,{}", columns.join(","))?;
+    for date in dates {
+        let cells: Vec<String> = columns
+            .iter()
+            .map(|name| {
+                balances
+                    .get(name)
+                    .and_then(|h| h.get(date))
+                    .and_then(|b| b.nonce)
+                    .map(|n| n.to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+        writeln!(file, "{},{}", date, cells.join(","))?;
+    }
+    Ok(())
+}
+
+/// An account whose latest balance is past an alert threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThresholdAlert {
+    pub account: String,
+    /// "below" or "above"
+    pub kind: &'static str,
+    pub threshold: f64,
+    /// Latest balance in the previous output, if the account had one
+    pub previous: Option<f64>,
+    pub current: f64,
+}
+
+impl ThresholdAlert {
+    /// Whether the threshold was crossed since the previous output (rather
+    /// than already breached then)
+    pub fn is_new(&self) -> bool {
+        match (self.previous, self.kind) {
+            (None, _) => true,
+            (Some(prev), "below") => prev >= self.threshold,
+            (Some(prev), _) => prev <= self.threshold,
+        }
+    }
+}
+
+/// Accounts whose `current` balance is below `below` or above `above`, with
+/// their `previous` value; sorted by account
+pub fn threshold_alerts(
+    previous: &HashMap<String, f64>,
+    current: &HashMap<String, f64>,
+    below: Option<f64>,
+    above: Option<f64>,
+) -> Vec<ThresholdAlert> {
+    let mut alerts = Vec::new();
+    for name in column_order(&current.keys().cloned().collect::<Vec<_>>()) {
+        let value = current[&name];
+        let breaches = [
+            ("below", below.filter(|&t| value < t)),
+            ("above", above.filter(|&t| value > t)),
+        ];
+        for (kind, threshold) in breaches {
+            if let Some(threshold) = threshold {
+                alerts.push(ThresholdAlert {
+                    account: name.clone(),
+                    kind,
+                    threshold,
+                    previous: previous.get(&name).copied(),
+                    current: value,
+                });
+            }
+        }
+    }
+    alerts
+}
+
+/// Save threshold alerts (`account,kind,threshold,previous,current,new`)
+pub fn save_alerts_csv<P: AsRef<Path>>(output_file: P, alerts: &[ThresholdAlert]) -> Result<()> {
+    let path = output_file.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "account,kind,threshold,previous,current,new")?;
+    for alert in alerts {
+        writeln!(
+            file,
+            "{},{},{},{},{:.4},{}",
+            alert.account,
+            alert.kind,
+            alert.threshold,
+            alert
+                .previous
+                .map(|p| format!("{:.4}", p))
+                .unwrap_or_default(),
+            alert.current,
+            alert.is_new()
+        )?;
+    }
+    Ok(())
+}
+
+/// Save era reward points, one row per era and validator
+/// (`era,validator,points,total_points,validator_reward`)
+pub fn save_era_points_csv<P: AsRef<Path>>(output_file: P, eras: &[EraPoints]) -> Result<()> {
+    let path = output_file.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "era,validator,points,total_points,validator_reward")?;
+    for era in eras {
+        for (validator, points) in &era.validators {
+            writeln!(
+                file,
+                "{},{},{},{},{:.6}",
+                era.era, validator, points, era.total_points, era.validator_reward
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Save a long-format CSV (`date,account,metric,value`) for data tools
+pub fn save_long_csv<P: AsRef<Path>>(
+    output_file: P,
+    account_names: &[String],
+    entries: &[HistoryEntry],
+    include_rewards: bool,
+) -> Result<()> {
+    let path = output_file.as_ref();
+    let account_names = &column_order(account_names)[..];
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "date,account,metric,value")?;
+
+    for entry in entries {
+        // Accounts outside their date window have no rows
+        for name in account_names {
+            let Some(balance) = entry.balances.get(name) else {
+                continue;
+            };
+            writeln!(file, "{},{},balance,{:.1}", entry.date, name, balance)?;
+
+            if include_rewards {
+                if let Some(reward) = entry.rewards.get(name) {
+                    writeln!(file, "{},{},reward,{:.4}", entry.date, name, reward)?;
+                }
+                if let Some(cumulative) = entry.reward_cumulatives.get(name) {
+                    writeln!(
+                        file,
+                        "{},{},reward_cumulative,{:.4}",
+                        entry.date, name, cumulative
+                    )?;
+                }
+            }
+            if let Some(apr) = entry.aprs.get(name) {
+                writeln!(file, "{},{},apr,{:.2}", entry.date, name, apr)?;
+            }
+        }
+    }
 
     Ok(())
 }
 
 /// Save individual CSV files for each account
+///
+/// `all_history` holds values of `metric`, and the diff columns are computed on
+/// it. Free balances keep the `<name>.csv` / `balance` layout; other metrics are
+/// written to `<name>_<metric>.csv` with a column named after the metric.
 pub fn save_individual_csvs<P: AsRef<Path>>(
     output_dir: P,
     account_names: &[String],
     all_history: &HashMap<String, HashMap<String, f64>>,
     sorted_dates: &[String],
     reward_history: Option<&HashMap<String, HashMap<String, f64>>>, // account_name -> date -> reward
+    metric: BalanceMetric,
+    rounding: Rounding,
 ) -> Result<()> {
     let dir = output_dir.as_ref();
     fs::create_dir_all(dir).context("Failed to create individual directory")?;
 
     let include_rewards = reward_history.is_some();
+    let fmt = |value: f64, decimals: u32| format_decimal(value, decimals, rounding);
+    let (suffix, column) = match metric {
+        BalanceMetric::Free => (String::new(), "balance"),
+        other => (format!("_{}", other.name()), other.name()),
+    };
 
     for name in account_names {
-        let csv_path = dir.join(format!("{}.csv", name));
+        let csv_path = dir.join(format!("{}{}.csv", name, suffix));
         let mut file =
             File::create(&csv_path).context(format!("Failed to create {:?}", csv_path))?;
 
@@ -115,10 +743,11 @@ pub fn save_individual_csvs<P: AsRef<Path>>(
         if include_rewards {
             writeln!(
                 file,
-                "date,balance,diff,diff_avg10,reward,reward_avg10,reward_cumulative"
+                "date,{},diff,diff_avg10,reward,reward_avg10,reward_cumulative",
+                column
             )?;
         } else {
-            writeln!(file, "date,balance,diff,diff_avg10")?;
+            writeln!(file, "date,{},diff,diff_avg10", column)?;
         }
 
         let account_history = all_history.get(name);
@@ -174,14 +803,23 @@ pub fn save_individual_csvs<P: AsRef<Path>>(
 
                 writeln!(
                     file,
-                    "{},{:.1},{:.1},{:.1},{:.4},{:.4},{:.4}",
-                    date, balance, diff, diff_avg10, reward, reward_avg10, reward_cumulative
+                    "{},{},{},{},{},{},{}",
+                    date,
+                    fmt(balance, 1),
+                    fmt(diff, 1),
+                    fmt(diff_avg10, 1),
+                    fmt(reward, 4),
+                    fmt(reward_avg10, 4),
+                    fmt(reward_cumulative, 4)
                 )?;
             } else {
                 writeln!(
                     file,
-                    "{},{:.1},{:.1},{:.1}",
-                    date, balance, diff, diff_avg10
+                    "{},{},{},{}",
+                    date,
+                    fmt(balance, 1),
+                    fmt(diff, 1),
+                    fmt(diff_avg10, 1)
                 )?;
             }
 
@@ -202,13 +840,16 @@ pub fn load_existing_csv<P: AsRef<Path>>(
         return Ok(HashMap::new());
     }
 
+    if let Some(issues) = verify_checksum(path)? {
+        for issue in issues {
+            tracing::warn!("{:?} failed its checksum: {}", path, issue);
+        }
+    }
+
     let mut reader = csv::Reader::from_path(path).context("Failed to open CSV")?;
-    let headers: Vec<String> = reader
-        .headers()
-        .context("Failed to read headers")?
-        .iter()
-        .map(|s| s.to_string())
-        .collect();
+    // Only account columns are loaded, matched by header name, so neither column
+    // order nor reward/APR columns affect the result
+    let accounts = account_columns(reader.headers().context("Failed to read headers")?);
 
     let mut existing_data: HashMap<String, HashMap<String, f64>> = HashMap::new();
 
@@ -220,13 +861,8 @@ pub fn load_existing_csv<P: AsRef<Path>>(
             continue;
         }
 
-        for (i, header) in headers.iter().enumerate().skip(1) {
-            // Skip date, total, diff, diff_avg10
-            if header == "total" || header == "diff" || header == "diff_avg10" {
-                continue;
-            }
-
-            if let Some(value_str) = record.get(i) {
+        for (i, header) in accounts.iter().enumerate() {
+            if let Some(value_str) = record.get(i + 1) {
                 if let Ok(value) = value_str.parse::<f64>() {
                     existing_data
                         .entry(header.clone())
@@ -240,10 +876,427 @@ pub fn load_existing_csv<P: AsRef<Path>>(
     Ok(existing_data)
 }
 
+/// Load the daily `<account>_reward` columns of a combined CSV
+/// (account -> date -> reward); empty if the file has no reward columns
+pub fn load_csv_rewards<P: AsRef<Path>>(
+    csv_file: P,
+) -> Result<HashMap<String, HashMap<String, f64>>> {
+    let mut reader = csv::Reader::from_path(csv_file.as_ref()).context("Failed to open CSV")?;
+    let headers = reader.headers().context("Failed to read headers")?.clone();
+    let columns: Vec<(usize, String)> = account_columns(&headers)
+        .into_iter()
+        .filter_map(|name| {
+            let header = format!("{}_reward", name);
+            headers.iter().position(|h| h == header).map(|i| (i, name))
+        })
+        .collect();
+
+    let mut rewards: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    for result in reader.records() {
+        let record = result?;
+        let date = record.get(0).unwrap_or("");
+        if date.is_empty() {
+            continue;
+        }
+        for (i, name) in &columns {
+            if let Some(value) = record.get(*i).and_then(|v| v.parse::<f64>().ok()) {
+                rewards
+                    .entry(name.clone())
+                    .or_default()
+                    .insert(date.to_string(), value);
+            }
+        }
+    }
+    Ok(rewards)
+}
+
+/// A day where an account's balance change and its rewards disagree
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardMismatch {
+    pub account: String,
+    /// Date whose balance shows the change (the later date of the pair)
+    pub date: String,
+    pub balance_change: f64,
+    /// Rewards of the previous date, paid before this date's block
+    pub reward: f64,
+    /// `balance_change - reward`: transfers, unbonding, or a reward parsing bug
+    pub residual: f64,
+}
+
+/// Compare each account's balance change between consecutive dates with the
+/// rewards of the earlier date and return the days whose residual exceeds
+/// `tolerance`, by account then date.
+pub fn reward_balance_mismatches(
+    balances: &HashMap<String, HashMap<String, f64>>,
+    rewards: &HashMap<String, HashMap<String, f64>>,
+    tolerance: f64,
+) -> Vec<RewardMismatch> {
+    let mut mismatches = Vec::new();
+    let mut names: Vec<&String> = balances.keys().collect();
+    names.sort();
+    for name in names {
+        let mut history: Vec<(&String, f64)> =
+            balances[name].iter().map(|(d, v)| (d, *v)).collect();
+        history.sort_by(|a, b| a.0.cmp(b.0));
+        for pair in history.windows(2) {
+            let (prev_date, prev_balance) = pair[0];
+            let (date, balance) = pair[1];
+            let reward = rewards
+                .get(name)
+                .and_then(|h| h.get(prev_date))
+                .copied()
+                .unwrap_or(0.0);
+            let balance_change = balance - prev_balance;
+            let residual = balance_change - reward;
+            if residual.abs() > tolerance {
+                mismatches.push(RewardMismatch {
+                    account: name.clone(),
+                    date: date.clone(),
+                    balance_change,
+                    reward,
+                    residual,
+                });
+            }
+        }
+    }
+    mismatches
+}
+
+/// Save reward/balance mismatches (`date,account,balance_change,reward,residual`)
+pub fn save_reward_check_csv<P: AsRef<Path>>(
+    output_file: P,
+    mismatches: &[RewardMismatch],
+) -> Result<()> {
+    let mut file = File::create(output_file.as_ref()).context("Failed to create CSV file")?;
+    writeln!(file, "date,account,balance_change,reward,residual")?;
+    for m in mismatches {
+        writeln!(
+            file,
+            "{},{},{:.4},{:.4},{:.4}",
+            m.date, m.account, m.balance_change, m.reward, m.residual
+        )?;
+    }
+    Ok(())
+}
+
+/// Recompute `total` and `diff` from the account columns of a saved CSV and
+/// describe every row where the stored value disagrees beyond `tolerance`.
+///
+/// Cells are written rounded to 0.1, so the total check allows that much
+/// rounding slack per account on top of `tolerance`.
+pub fn check_csv_consistency<P: AsRef<Path>>(
+    csv_file: P,
+    account_names: &[String],
+    tolerance: f64,
+) -> Result<Vec<String>> {
+    let mut reader = csv::Reader::from_path(csv_file.as_ref()).context("Failed to open CSV")?;
+    let headers = reader.headers().context("Failed to read headers")?.clone();
+    let column = |name: &str| headers.iter().position(|h| h == name);
+
+    let total_col = column("total").context("CSV has no total column")?;
+    let diff_col = column("diff").context("CSV has no diff column")?;
+    let account_cols: Vec<usize> = account_names.iter().filter_map(|n| column(n)).collect();
+    let total_slack = tolerance + 0.05 * account_cols.len() as f64;
+
+    let parse =
+        |record: &csv::StringRecord, i: usize| record.get(i).and_then(|v| v.parse::<f64>().ok());
+
+    let mut issues = Vec::new();
+    let mut prev_total: Option<f64> = None;
+    for result in reader.records() {
+        let record = result?;
+        let date = record.get(0).unwrap_or("");
+        let stored_total = parse(&record, total_col).unwrap_or(0.0);
+        let stored_diff = parse(&record, diff_col).unwrap_or(0.0);
+
+        let total: f64 = account_cols.iter().filter_map(|&i| parse(&record, i)).sum();
+        if (total - stored_total).abs() > total_slack {
+            issues.push(format!(
+                "{}: total {:.1} but accounts sum to {:.1}",
+                date, stored_total, total
+            ));
+        }
+
+        let diff = prev_total.map(|p| stored_total - p).unwrap_or(0.0);
+        if (diff - stored_diff).abs() > tolerance + 0.1 {
+            issues.push(format!(
+                "{}: diff {:.1} but totals give {:.1}",
+                date, stored_diff, diff
+            ));
+        }
+        prev_total = Some(stored_total);
+    }
+
+    Ok(issues)
+}
+
+/// One account value that differs between two CSV outputs; `None` means the
+/// cell was empty or missing on that side
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueChange {
+    pub date: String,
+    pub account: String,
+    pub old: Option<f64>,
+    pub new: Option<f64>,
+}
+
+/// Differences between two combined CSV outputs
+#[derive(Debug, Clone, Default)]
+pub struct CsvComparison {
+    pub added_dates: Vec<String>,
+    pub removed_dates: Vec<String>,
+    pub added_accounts: Vec<String>,
+    pub removed_accounts: Vec<String>,
+    /// Values on dates and accounts present in both files
+    pub changes: Vec<ValueChange>,
+}
+
+impl CsvComparison {
+    pub fn is_empty(&self) -> bool {
+        self.added_dates.is_empty()
+            && self.removed_dates.is_empty()
+            && self.added_accounts.is_empty()
+            && self.removed_accounts.is_empty()
+            && self.changes.is_empty()
+    }
+}
+
+/// Compare the account columns of two combined CSVs (see `load_existing_csv`),
+/// reporting values that differ by more than `tolerance`
+pub fn compare_csvs<P: AsRef<Path>, Q: AsRef<Path>>(
+    old_file: P,
+    new_file: Q,
+    tolerance: f64,
+) -> Result<CsvComparison> {
+    let load = |path: &Path| {
+        anyhow::ensure!(path.exists(), "CSV not found: {:?}", path);
+        load_existing_csv(path)
+    };
+    let old = load(old_file.as_ref())?;
+    let new = load(new_file.as_ref())?;
+    Ok(compare_histories(&old, &new, tolerance))
+}
+
+fn compare_histories(
+    old: &HashMap<String, HashMap<String, f64>>,
+    new: &HashMap<String, HashMap<String, f64>>,
+    tolerance: f64,
+) -> CsvComparison {
+    let dates = |h: &HashMap<String, HashMap<String, f64>>| -> BTreeSet<String> {
+        h.values().flat_map(|d| d.keys().cloned()).collect()
+    };
+    let (old_dates, new_dates) = (dates(old), dates(new));
+    let old_accounts: BTreeSet<&String> = old.keys().collect();
+    let new_accounts: BTreeSet<&String> = new.keys().collect();
+
+    let mut changes = Vec::new();
+    for date in old_dates.intersection(&new_dates) {
+        for account in old_accounts.intersection(&new_accounts) {
+            let old_value = old[*account].get(date).copied();
+            let new_value = new[*account].get(date).copied();
+            let differs = match (old_value, new_value) {
+                (Some(a), Some(b)) => (a - b).abs() > tolerance,
+                (None, None) => false,
+                _ => true,
+            };
+            if differs {
+                changes.push(ValueChange {
+                    date: date.clone(),
+                    account: (*account).clone(),
+                    old: old_value,
+                    new: new_value,
+                });
+            }
+        }
+    }
+
+    CsvComparison {
+        added_dates: new_dates.difference(&old_dates).cloned().collect(),
+        removed_dates: old_dates.difference(&new_dates).cloned().collect(),
+        added_accounts: new_accounts
+            .difference(&old_accounts)
+            .map(|a| (*a).clone())
+            .collect(),
+        removed_accounts: old_accounts
+            .difference(&new_accounts)
+            .map(|a| (*a).clone())
+            .collect(),
+        changes,
+    }
+}
+
+/// Save the value changes of a comparison as `date,account,old,new,diff`
+pub fn save_comparison_csv<P: AsRef<Path>>(
+    output_file: P,
+    comparison: &CsvComparison,
+) -> Result<()> {
+    let path = output_file.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let mut file = File::create(path).context("Failed to create CSV file")?;
+    writeln!(file, "date,account,old,new,diff")?;
+    let cell = |v: Option<f64>| v.map_or(String::new(), |v| format!("{:.1}", v));
+    for change in &comparison.changes {
+        let diff = change.new.unwrap_or(0.0) - change.old.unwrap_or(0.0);
+        writeln!(
+            file,
+            "{},{},{},{},{:.1}",
+            change.date,
+            change.account,
+            cell(change.old),
+            cell(change.new),
+            diff
+        )?;
+    }
+    Ok(())
+}
+
+/// Fill each entry's `component_totals` from the full cached balances of its
+/// accounts, leaving out `excluded` ones as the total does. An entry stays
+/// `None` when any of its accounts has no cached balance for the date.
+pub fn calculate_component_totals(
+    entries: &mut [HistoryEntry],
+    balances: &BalanceCache,
+    excluded: &[String],
+) {
+    for entry in entries.iter_mut() {
+        let mut sum = Balance::zero();
+        let complete = entry
+            .balances
+            .keys()
+            .filter(|name| !excluded.contains(name))
+            .all(
+                |name| match balances.get(name).and_then(|h| h.get(&entry.date)) {
+                    Some(b) => {
+                        sum.free += b.free;
+                        sum.reserved += b.reserved;
+                        sum.frozen += b.frozen;
+                        true
+                    }
+                    None => false,
+                },
+            );
+        entry.component_totals = complete.then_some(sum);
+    }
+}
+
+/// One history row per date from balances and daily rewards. Accounts outside
+/// their window on a date, or without a balance for it, are left out of that
+/// row's balances and totals and written as empty cells.
+pub fn build_history_entries(
+    dates: &[String],
+    account_names: &[String],
+    account_windows: &HashMap<String, DateWindow>,
+    account_groups: Option<&HashMap<String, String>>,
+    balance_history: &HashMap<String, HashMap<String, f64>>,
+    reward_history: &RewardTotals,
+    excluded_from_total: &[String],
+) -> Vec<HistoryEntry> {
+    let mut reward_cumulative = 0.0;
+    let mut account_reward_cumulatives: HashMap<String, f64> = HashMap::new();
+    let mut reward_history_for_avg: Vec<f64> = Vec::new();
+
+    dates
+        .iter()
+        .map(|date| {
+            let mut balances = HashMap::new();
+            let mut rewards = HashMap::new();
+            let mut total = 0.0;
+            let mut total_reward = 0.0;
+            let mut group_totals = BTreeMap::new();
+
+            for name in account_names {
+                // Out-of-window cells are left empty in the CSV
+                if !is_active_on(account_windows, name, date) {
+                    continue;
+                }
+
+                // A balance that was never fetched stays empty too, so the next
+                // run still sees it as missing
+                let in_total = !excluded_from_total.contains(name);
+                if let Some(&balance) = balance_history.get(name).and_then(|h| h.get(date)) {
+                    balances.insert(name.clone(), balance);
+                    if in_total {
+                        total += balance;
+                    }
+                    if let Some(group) = account_groups.and_then(|g| g.get(name)) {
+                        *group_totals.entry(group.clone()).or_insert(0.0) += balance;
+                    }
+                }
+
+                let reward = reward_history
+                    .get(name)
+                    .and_then(|h| h.get(date))
+                    .copied()
+                    .unwrap_or(0.0);
+                rewards.insert(name.clone(), reward);
+                if in_total {
+                    total_reward += reward;
+                }
+                *account_reward_cumulatives
+                    .entry(name.clone())
+                    .or_insert(0.0) += reward;
+            }
+
+            reward_cumulative += total_reward;
+            reward_history_for_avg.push(total_reward);
+
+            let reward_avg10 = if reward_history_for_avg.len() >= 10 {
+                reward_history_for_avg.iter().rev().take(10).sum::<f64>() / 10.0
+            } else if !reward_history_for_avg.is_empty() {
+                reward_history_for_avg.iter().sum::<f64>() / reward_history_for_avg.len() as f64
+            } else {
+                0.0
+            };
+
+            HistoryEntry {
+                date: date.clone(),
+                balances,
+                total,
+                rewards,
+                total_reward,
+                reward_avg10,
+                total_reward_cumulative: reward_cumulative,
+                reward_cumulatives: account_reward_cumulatives.clone(),
+                group_totals,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Whether any of `names` has no balance for `date`, so the date still needs fetching
+pub fn balance_missing(
+    balance_history: &HashMap<String, HashMap<String, f64>>,
+    names: &[&String],
+    date: &str,
+) -> bool {
+    names.iter().any(|&name| {
+        balance_history
+            .get(name)
+            .and_then(|h| h.get(date))
+            .is_none()
+    })
+}
+
 /// Calculate diff and diff_avg10 for entries
+///
+/// Diffs are taken on `total`, which holds the sum of the selected
+/// `BalanceMetric` (free by default), so they follow the same metric.
+///
+/// Each diff is also split into reward income and principal flow. A date's
+/// rewards are paid between its block and the next date's, so they show up in
+/// the next entry's diff; whatever that diff holds beyond them (transfers,
+/// unbonded stake leaving, rewards paid elsewhere) is `principal_flow`.
 pub fn calculate_diffs(entries: &mut [HistoryEntry]) {
     let mut diffs: Vec<f64> = Vec::new();
     let mut prev_total: Option<f64> = None;
+    let mut prev_reward = 0.0;
+    let mut income = 0.0;
 
     for entry in entries.iter_mut() {
         // Calculate diff
@@ -253,6 +1306,13 @@ pub fn calculate_diffs(entries: &mut [HistoryEntry]) {
         };
         diffs.push(entry.diff);
 
+        if prev_total.is_some() {
+            entry.principal_flow = entry.diff - prev_reward;
+            income += prev_reward;
+        }
+        entry.income_excluding_principal = income;
+        prev_reward = entry.total_reward;
+
         // Calculate 10-day average
         entry.diff_avg10 = if diffs.len() >= 10 {
             let last_10: f64 = diffs.iter().rev().take(10).sum();
@@ -266,3 +1326,424 @@ pub fn calculate_diffs(entries: &mut [HistoryEntry]) {
         prev_total = Some(entry.total);
     }
 }
+
+/// Distribution of daily reward amounts
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewardStats {
+    pub days: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub p90: f64,
+}
+
+impl RewardStats {
+    /// Stats of `values`, zero days included; `None` when empty. Percentiles
+    /// interpolate linearly between the nearest ranks.
+    pub fn from_values(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let rank = p * (sorted.len() - 1) as f64;
+            let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+            sorted[lo] + (sorted[hi] - sorted[lo]) * (rank - lo as f64)
+        };
+        Some(Self {
+            days: sorted.len(),
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean: sorted.iter().sum::<f64>() / sorted.len() as f64,
+            median: percentile(0.5),
+            p90: percentile(0.9),
+        })
+    }
+}
+
+/// Daily reward stats per account (over the days it is active) and for
+/// `total_reward`, keyed `"total"`
+pub fn reward_stats(
+    account_names: &[String],
+    entries: &[HistoryEntry],
+) -> Vec<(String, RewardStats)> {
+    let mut stats: Vec<(String, RewardStats)> = column_order(account_names)
+        .into_iter()
+        .filter_map(|name| {
+            let values: Vec<f64> = entries
+                .iter()
+                .filter(|e| e.balances.contains_key(&name))
+                .map(|e| e.rewards.get(&name).copied().unwrap_or(0.0))
+                .collect();
+            RewardStats::from_values(&values).map(|s| (name, s))
+        })
+        .collect();
+    let totals: Vec<f64> = entries.iter().map(|e| e.total_reward).collect();
+    if let Some(s) = RewardStats::from_values(&totals) {
+        stats.push(("total".to_string(), s));
+    }
+    stats
+}
+
+/// Save reward stats (`account,days,min,max,mean,median,p90`)
+pub fn save_reward_stats_csv<P: AsRef<Path>>(
+    output_file: P,
+    stats: &[(String, RewardStats)],
+) -> Result<()> {
+    let mut file = File::create(output_file.as_ref()).context("Failed to create CSV file")?;
+    writeln!(file, "account,days,min,max,mean,median,p90")?;
+    for (name, s) in stats {
+        writeln!(
+            file,
+            "{},{},{:.4},{:.4},{:.4},{:.4},{:.4}",
+            name, s.days, s.min, s.max, s.mean, s.median, s.p90
+        )?;
+    }
+    Ok(())
+}
+
+/// Calculate annualized APR (%) per account and for the total.
+///
+/// Rewards and stakes are summed over the trailing `window` entries to smooth out
+/// lumpy era payouts. Days whose own stake is zero or unknown stay blank.
+pub fn calculate_aprs(
+    entries: &mut [HistoryEntry],
+    account_names: &[String],
+    stakes: &HashMap<String, HashMap<String, f64>>,
+    window: usize,
+) {
+    let window = window.max(1);
+    let stake_on = |name: &str, date: &str| {
+        stakes
+            .get(name)
+            .and_then(|h| h.get(date))
+            .copied()
+            .unwrap_or(0.0)
+    };
+
+    for i in 0..entries.len() {
+        let from = (i + 1).saturating_sub(window);
+        let span = &entries[from..=i];
+        let days = span.len() as f64;
+
+        let mut aprs = HashMap::new();
+        let mut total_reward = 0.0;
+        let mut total_stake = 0.0;
+        for name in account_names {
+            if stake_on(name, &entries[i].date) <= 0.0 {
+                continue;
+            }
+            let reward: f64 = span.iter().filter_map(|e| e.rewards.get(name)).sum();
+            let stake: f64 = span.iter().map(|e| stake_on(name, &e.date)).sum::<f64>() / days;
+            aprs.insert(name.clone(), reward / stake * 365.0 / days * 100.0);
+            total_reward += reward;
+            total_stake += stake;
+        }
+
+        entries[i].total_apr = if total_stake > 0.0 {
+            Some(total_reward / total_stake * 365.0 / days * 100.0)
+        } else {
+            None
+        };
+        entries[i].aprs = aprs;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threshold_alerts() {
+        let previous = HashMap::from([("A".to_string(), 50.0), ("B".to_string(), 5.0)]);
+        let current = HashMap::from([
+            ("A".to_string(), 8.0),
+            ("B".to_string(), 4.0),
+            ("C".to_string(), 500.0),
+        ]);
+
+        let alerts = threshold_alerts(&previous, &current, Some(10.0), Some(100.0));
+        let summary: Vec<(&str, &str, bool)> = alerts
+            .iter()
+            .map(|a| (a.account.as_str(), a.kind, a.is_new()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("A", "below", true),
+                ("B", "below", false),
+                ("C", "above", true)
+            ]
+        );
+        assert!(threshold_alerts(&previous, &current, None, None).is_empty());
+    }
+
+    #[test]
+    fn test_totals_csv_has_no_account_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totals.csv");
+        let entry = HistoryEntry {
+            date: "2024-09-01".to_string(),
+            balances: HashMap::from([("A".to_string(), 10.0)]),
+            total: 10.0,
+            rewards: HashMap::from([("A".to_string(), 0.5)]),
+            total_reward: 0.5,
+            reward_avg10: 0.5,
+            total_reward_cumulative: 0.5,
+            reward_cumulatives: HashMap::from([("A".to_string(), 0.5)]),
+            ..Default::default()
+        };
+        save_totals_csv(&path, &[entry], true, false, false, Rounding::Round).unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        let header = content.lines().next().unwrap();
+        assert_eq!(
+            header,
+            "date,total,diff,diff_avg10,total_reward,reward_avg10,total_reward_cumulative,principal_flow,income_excluding_principal"
+        );
+    }
+
+    #[test]
+    fn test_total_with_rewards_column() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("totals.csv");
+        let entry = HistoryEntry {
+            date: "2024-09-01".to_string(),
+            total: 10.0,
+            total_reward: 0.5,
+            reward_avg10: 0.5,
+            total_reward_cumulative: 2.5,
+            ..Default::default()
+        };
+        save_totals_csv(&path, &[entry], true, true, false, Rounding::Round).unwrap();
+
+        let mut reader = csv::Reader::from_path(&path).unwrap();
+        let column = reader
+            .headers()
+            .unwrap()
+            .iter()
+            .position(|h| h == "total_with_rewards")
+            .unwrap();
+        let row = reader.records().next().unwrap().unwrap();
+        assert_eq!(&row[column], "12.5000");
+    }
+
+    #[test]
+    fn test_reward_stats_from_values() {
+        let stats = RewardStats::from_values(&[4.0, 0.0, 2.0, 10.0, 4.0]).unwrap();
+        assert_eq!(stats.days, 5);
+        assert_eq!((stats.min, stats.max), (0.0, 10.0));
+        assert_eq!(stats.mean, 4.0);
+        assert_eq!(stats.median, 4.0);
+        // Rank 3.6 of 0,2,4,4,10
+        assert!((stats.p90 - 7.6).abs() < 1e-9);
+        assert!(RewardStats::from_values(&[]).is_none());
+    }
+
+    #[test]
+    fn test_reward_balance_mismatches() {
+        let series = |values: &[(&str, f64)]| -> HashMap<String, f64> {
+            values.iter().map(|(d, v)| (d.to_string(), *v)).collect()
+        };
+        let balances = HashMap::from([(
+            "A".to_string(),
+            series(&[
+                ("2024-09-01", 100.0),
+                ("2024-09-02", 102.0),
+                ("2024-09-03", 54.0),
+            ]),
+        )]);
+        // The 2024-09-02 reward was paid, then 50 CTC left the account
+        let rewards = HashMap::from([(
+            "A".to_string(),
+            series(&[("2024-09-01", 2.0), ("2024-09-02", 2.0)]),
+        )]);
+
+        let mismatches = reward_balance_mismatches(&balances, &rewards, 0.5);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].date, "2024-09-03");
+        assert_eq!(mismatches[0].reward, 2.0);
+        assert_eq!(mismatches[0].residual, -50.0);
+    }
+
+    #[test]
+    fn test_diffs_split_income_from_principal() {
+        let entry = |date: &str, total: f64, total_reward: f64| HistoryEntry {
+            date: date.to_string(),
+            total,
+            total_reward,
+            ..Default::default()
+        };
+        // 2 CTC of rewards each day; 500 withdrawn on the third day
+        let mut entries = vec![
+            entry("2024-09-01", 1000.0, 2.0),
+            entry("2024-09-02", 1002.0, 2.0),
+            entry("2024-09-03", 504.0, 2.0),
+        ];
+        calculate_diffs(&mut entries);
+
+        let split: Vec<(f64, f64)> = entries
+            .iter()
+            .map(|e| (e.principal_flow, e.income_excluding_principal))
+            .collect();
+        assert_eq!(split, vec![(0.0, 0.0), (0.0, 2.0), (-500.0, 4.0)]);
+    }
+
+    #[test]
+    fn test_reward_only_date_stays_missing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.csv");
+        let names = vec!["A".to_string(), "B".to_string()];
+        let dates = vec!["2024-09-01".to_string(), "2024-09-02".to_string()];
+        // 2024-09-02 only has a cached reward, no fetched balance
+        let balances = HashMap::from([
+            ("A".to_string(), HashMap::from([(dates[0].clone(), 10.0)])),
+            ("B".to_string(), HashMap::from([(dates[0].clone(), 5.0)])),
+        ]);
+        let rewards = HashMap::from([("A".to_string(), HashMap::from([(dates[1].clone(), 0.5)]))]);
+
+        let entries = build_history_entries(
+            &dates,
+            &names,
+            &HashMap::new(),
+            None,
+            &balances,
+            &rewards,
+            &[],
+        );
+        assert!(entries[1].balances.is_empty());
+        assert_eq!(entries[1].total, 0.0);
+        assert_eq!(entries[1].rewards["A"], 0.5);
+        save_combined_csv(
+            &path,
+            &names,
+            &entries,
+            true,
+            false,
+            false,
+            Rounding::Round,
+            false,
+        )
+        .unwrap();
+
+        let loaded = load_existing_csv(&path).unwrap();
+        assert_eq!(loaded["A"][&dates[0]], 10.0);
+        assert!(!loaded["A"].contains_key(&dates[1]));
+        let active: Vec<&String> = names.iter().collect();
+        assert!(!balance_missing(&loaded, &active, &dates[0]));
+        assert!(balance_missing(&loaded, &active, &dates[1]));
+    }
+
+    #[test]
+    fn test_checksum_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.csv");
+        let names = vec!["A".to_string()];
+        let entry = HistoryEntry {
+            date: "2024-09-01".to_string(),
+            balances: HashMap::from([("A".to_string(), 1.5)]),
+            total: 1.5,
+            ..Default::default()
+        };
+        save_combined_csv(
+            &path,
+            &names,
+            &[entry],
+            false,
+            false,
+            false,
+            Rounding::Round,
+            true,
+        )
+        .unwrap();
+        assert_eq!(verify_checksum(&path).unwrap(), Some(vec![]));
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, content.replace("1.5", "9.5")).unwrap();
+        assert_eq!(verify_checksum(&path).unwrap().unwrap().len(), 1);
+
+        fs::write(&path, format!("{}2024-09-02,1.5,1.5,0.0,0.0\n", content)).unwrap();
+        assert_eq!(verify_checksum(&path).unwrap().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_load_csv_rewards() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.csv");
+        fs::write(
+            &path,
+            "date,A,B,total,diff,diff_avg10,A_reward,B_reward,total_reward\n\
+             2024-09-01,1.0,,1.0,0.0,0.0,0.5000,,0.5000\n\
+             2024-09-02,2.0,3.0,5.0,4.0,2.0,0.2500,0.1000,0.3500\n",
+        )
+        .unwrap();
+
+        let rewards = load_csv_rewards(&path).unwrap();
+        assert_eq!(rewards["A"].len(), 2);
+        assert_eq!(rewards["A"]["2024-09-02"], 0.25);
+        assert_eq!(rewards["B"].len(), 1);
+        assert_eq!(load_existing_csv(&path).unwrap()["B"]["2024-09-02"], 3.0);
+    }
+
+    #[test]
+    fn test_format_decimal_modes() {
+        assert_eq!(format_decimal(1.05, 1, Rounding::Round), "1.1");
+        assert_eq!(format_decimal(1.05, 1, Rounding::Trunc), "1.0");
+        assert_eq!(format_decimal(1.05, 1, Rounding::Floor), "1.0");
+        assert_eq!(format_decimal(1.05, 1, Rounding::Ceil), "1.1");
+
+        assert_eq!(format_decimal(-1.05, 1, Rounding::Round), "-1.1");
+        assert_eq!(format_decimal(-1.05, 1, Rounding::Trunc), "-1.0");
+        assert_eq!(format_decimal(-1.05, 1, Rounding::Floor), "-1.1");
+        assert_eq!(format_decimal(-1.05, 1, Rounding::Ceil), "-1.0");
+
+        // Float noise doesn't push exact values across a boundary
+        assert_eq!(format_decimal(2.3, 1, Rounding::Trunc), "2.3");
+        assert_eq!(format_decimal(0.0007, 4, Rounding::Ceil), "0.0007");
+        assert_eq!(format_decimal(-0.04, 1, Rounding::Round), "0.0");
+        assert_eq!(format_decimal(12.0, 4, Rounding::Round), "12.0000");
+    }
+
+    #[test]
+    fn test_compare_histories() {
+        let history = |rows: &[(&str, &str, f64)]| {
+            let mut h: HashMap<String, HashMap<String, f64>> = HashMap::new();
+            for (account, date, value) in rows {
+                h.entry(account.to_string())
+                    .or_default()
+                    .insert(date.to_string(), *value);
+            }
+            h
+        };
+        let old = history(&[
+            ("Alice", "2024-09-01", 100.0),
+            ("Alice", "2024-09-02", 100.0),
+            ("Bob", "2024-09-01", 5.0),
+        ]);
+        let new = history(&[
+            ("Alice", "2024-09-02", 100.05),
+            ("Alice", "2024-09-03", 110.0),
+            ("Carol", "2024-09-02", 1.0),
+        ]);
+
+        let cmp = compare_histories(&old, &new, 0.1);
+        assert_eq!(cmp.added_dates, vec!["2024-09-03"]);
+        assert_eq!(cmp.removed_dates, vec!["2024-09-01"]);
+        assert_eq!(cmp.added_accounts, vec!["Carol"]);
+        assert_eq!(cmp.removed_accounts, vec!["Bob"]);
+        assert!(cmp.changes.is_empty());
+
+        let cmp = compare_histories(&old, &new, 0.01);
+        assert_eq!(
+            cmp.changes,
+            vec![ValueChange {
+                date: "2024-09-02".to_string(),
+                account: "Alice".to_string(),
+                old: Some(100.0),
+                new: Some(100.05),
+            }]
+        );
+    }
+}
@@ -0,0 +1,411 @@
+//! SQLite storage backend (feature `sqlite`).
+//!
+//! An alternative to the JSON caches: blocks, balances and rewards live in one
+//! database and are saved with upserts instead of whole-file rewrites. Load and
+//! save take and return the same cache maps as the functions in `cache`; the
+//! `*_rows` savers write only the entries that changed.
+//!
+//! The stake cache and the reward-accounts sidecar stay in JSON files.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::balance::Balance;
+use crate::cache::{BalanceCache, BlockCache, CachedReward, RewardCache};
+use crate::chain::BlockInfo;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS blocks (
+    key          TEXT PRIMARY KEY,
+    block        INTEGER NOT NULL,
+    hash         TEXT NOT NULL,
+    spec_version INTEGER
+);
+CREATE TABLE IF NOT EXISTS balances (
+    scope    TEXT NOT NULL,
+    account  TEXT NOT NULL,
+    date     TEXT NOT NULL,
+    free     REAL NOT NULL,
+    reserved REAL NOT NULL,
+    frozen   REAL NOT NULL,
+    nonce    INTEGER,
+    PRIMARY KEY (scope, account, date)
+);
+CREATE TABLE IF NOT EXISTS rewards (
+    scope   TEXT NOT NULL,
+    account TEXT NOT NULL,
+    date    TEXT NOT NULL,
+    amount  REAL NOT NULL,
+    by_validator TEXT,
+    PRIMARY KEY (scope, account, date)
+);
+";
+
+/// An `(account, date)` cache entry
+pub type Row = (String, String);
+
+/// SQLite-backed store for block, balance and reward caches
+///
+/// `scope` separates data that the JSON backend keeps in different files,
+/// e.g. snapshot-time variants or claimed vs earned rewards; it is the cache
+/// file stem (`balance_cache_1200`, `reward_cache_claimed`, ...).
+pub struct SqliteStore {
+    conn: Connection,
+}
+
+impl SqliteStore {
+    /// Open (or create) the database and ensure the schema exists
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        // Ensure parent directory exists
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).context("Failed to create database directory")?;
+        }
+
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open database {:?}", path))?;
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create database schema")?;
+
+        // Databases created before reward breakdowns or nonces lack the columns
+        for (table, column, kind) in [
+            ("rewards", "by_validator", "TEXT"),
+            ("balances", "nonce", "INTEGER"),
+        ] {
+            let exists: bool = conn.query_row(
+                "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+                [table, column],
+                |row| row.get(0),
+            )?;
+            if !exists {
+                conn.execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, kind),
+                    [],
+                )
+                .with_context(|| format!("Failed to migrate {} table", table))?;
+            }
+        }
+
+        Ok(Self { conn })
+    }
+
+    /// Load all cached blocks
+    pub fn load_block_cache(&self) -> Result<BlockCache> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key, block, hash, spec_version FROM blocks")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                BlockInfo {
+                    block: row.get::<_, i64>(1)? as u64,
+                    hash: row.get(2)?,
+                    spec_version: row.get(3)?,
+                },
+            ))
+        })?;
+
+        let cache = rows
+            .collect::<rusqlite::Result<BlockCache>>()
+            .context("Failed to read blocks")?;
+        Ok(cache)
+    }
+
+    /// Upsert every entry of the block cache
+    pub fn save_block_cache(&mut self, cache: &BlockCache) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO blocks (key, block, hash, spec_version)
+                 VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (key, info) in cache {
+                stmt.execute(params![
+                    key,
+                    info.block as i64,
+                    info.hash,
+                    info.spec_version
+                ])?;
+            }
+        }
+        tx.commit().context("Failed to write blocks")?;
+        Ok(())
+    }
+
+    /// Load the balance cache for `scope`
+    pub fn load_balance_cache(&self, scope: &str) -> Result<BalanceCache> {
+        let mut stmt = self.conn.prepare(
+            "SELECT account, date, free, reserved, frozen, nonce FROM balances WHERE scope = ?1",
+        )?;
+        let rows = stmt.query_map([scope], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                Balance {
+                    free: row.get(2)?,
+                    reserved: row.get(3)?,
+                    frozen: row.get(4)?,
+                    nonce: row.get(5)?,
+                },
+            ))
+        })?;
+
+        let mut cache: BalanceCache = HashMap::new();
+        for row in rows {
+            let (account, date, balance) = row.context("Failed to read balances")?;
+            cache.entry(account).or_default().insert(date, balance);
+        }
+        Ok(cache)
+    }
+
+    /// Upsert every entry of the balance cache under `scope`
+    pub fn save_balance_cache(&mut self, scope: &str, cache: &BalanceCache) -> Result<()> {
+        let rows: Vec<Row> = cache
+            .iter()
+            .flat_map(|(account, dates)| {
+                dates
+                    .keys()
+                    .map(move |date| (account.clone(), date.clone()))
+            })
+            .collect();
+        self.save_balance_rows(scope, cache, &rows)
+    }
+
+    /// Upsert only the `(account, date)` entries of the balance cache listed in
+    /// `rows`; listed entries missing from the cache are skipped
+    pub fn save_balance_rows<'a>(
+        &mut self,
+        scope: &str,
+        cache: &BalanceCache,
+        rows: impl IntoIterator<Item = &'a Row>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO balances
+                 (scope, account, date, free, reserved, frozen, nonce)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for (account, date) in rows {
+                if let Some(b) = cache.get(account).and_then(|h| h.get(date)) {
+                    stmt.execute(params![
+                        scope, account, date, b.free, b.reserved, b.frozen, b.nonce
+                    ])?;
+                }
+            }
+        }
+        tx.commit().context("Failed to write balances")?;
+        Ok(())
+    }
+
+    /// Load the reward cache for `scope`
+    pub fn load_reward_cache(&self, scope: &str) -> Result<RewardCache> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT account, date, amount, by_validator FROM rewards WHERE scope = ?1")?;
+        let rows = stmt.query_map([scope], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, f64>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })?;
+
+        let mut cache: RewardCache = HashMap::new();
+        for row in rows {
+            let (account, date, total, by_validator) = row.context("Failed to read rewards")?;
+            let by_validator = match by_validator {
+                Some(json) => serde_json::from_str(&json).context("Invalid reward breakdown")?,
+                None => Default::default(),
+            };
+            cache.entry(account).or_default().insert(
+                date,
+                CachedReward {
+                    total,
+                    by_validator,
+                },
+            );
+        }
+        Ok(cache)
+    }
+
+    /// Upsert every entry of the reward cache under `scope`
+    pub fn save_reward_cache(&mut self, scope: &str, cache: &RewardCache) -> Result<()> {
+        let rows: Vec<Row> = cache
+            .iter()
+            .flat_map(|(account, dates)| {
+                dates
+                    .keys()
+                    .map(move |date| (account.clone(), date.clone()))
+            })
+            .collect();
+        self.save_reward_rows(scope, cache, &rows)
+    }
+
+    /// Upsert only the `(account, date)` entries of the reward cache listed in
+    /// `rows`; listed entries missing from the cache are skipped
+    pub fn save_reward_rows<'a>(
+        &mut self,
+        scope: &str,
+        cache: &RewardCache,
+        rows: impl IntoIterator<Item = &'a Row>,
+    ) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO rewards (scope, account, date, amount, by_validator)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for (account, date) in rows {
+                let Some(reward) = cache.get(account).and_then(|h| h.get(date)) else {
+                    continue;
+                };
+                let by_validator = if reward.by_validator.is_empty() {
+                    None
+                } else {
+                    Some(serde_json::to_string(&reward.by_validator)?)
+                };
+                stmt.execute(params![scope, account, date, reward.total, by_validator])?;
+            }
+        }
+        tx.commit().context("Failed to write rewards")?;
+        Ok(())
+    }
+
+    /// Delete every cached reward of `account` under `scope`
+    pub fn delete_rewards(&mut self, scope: &str, account: &str) -> Result<()> {
+        self.conn
+            .execute(
+                "DELETE FROM rewards WHERE scope = ?1 AND account = ?2",
+                [scope, account],
+            )
+            .context("Failed to delete rewards")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SqliteStore::open(dir.path().join("ctc.sqlite")).unwrap();
+
+        let mut blocks = BlockCache::new();
+        blocks.insert(
+            "2024-09-01".to_string(),
+            BlockInfo {
+                block: 1234,
+                hash: "0xabc".to_string(),
+                spec_version: Some(7),
+            },
+        );
+        store.save_block_cache(&blocks).unwrap();
+        assert_eq!(store.load_block_cache().unwrap()["2024-09-01"].block, 1234);
+
+        let mut balances = BalanceCache::new();
+        let balance = Balance {
+            free: 10.0,
+            reserved: 1.0,
+            frozen: 0.0,
+            nonce: Some(5),
+        };
+        balances
+            .entry("A".to_string())
+            .or_default()
+            .insert("2024-09-01".to_string(), balance.clone());
+        store
+            .save_balance_cache("balance_cache", &balances)
+            .unwrap();
+        let loaded = &store.load_balance_cache("balance_cache").unwrap()["A"]["2024-09-01"];
+        assert_eq!((loaded.free, loaded.nonce), (10.0, Some(5)));
+
+        let mut rewards = RewardCache::new();
+        rewards
+            .entry("A".to_string())
+            .or_default()
+            .insert("2024-09-01".to_string(), 1.5.into());
+        store.save_reward_cache("reward_cache", &rewards).unwrap();
+
+        // Upserts replace existing rows and scopes stay separate
+        let reward = CachedReward {
+            total: 2.5,
+            by_validator: [("V".to_string(), 2.5)].into(),
+        };
+        rewards
+            .get_mut("A")
+            .unwrap()
+            .insert("2024-09-01".to_string(), reward.clone());
+        store.save_reward_cache("reward_cache", &rewards).unwrap();
+        assert_eq!(
+            store.load_reward_cache("reward_cache").unwrap()["A"]["2024-09-01"],
+            reward
+        );
+        assert!(store
+            .load_reward_cache("reward_cache_claimed")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sqlite_save_rows_writes_only_listed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SqliteStore::open(dir.path().join("ctc.sqlite")).unwrap();
+
+        let mut balances = BalanceCache::new();
+        for date in ["2024-09-01", "2024-09-02"] {
+            balances.entry("A".to_string()).or_default().insert(
+                date.to_string(),
+                Balance {
+                    free: 1.0,
+                    ..Balance::zero()
+                },
+            );
+        }
+        let rows = vec![
+            ("A".to_string(), "2024-09-02".to_string()),
+            ("B".to_string(), "2024-09-02".to_string()),
+        ];
+        store
+            .save_balance_rows("balance_cache", &balances, &rows)
+            .unwrap();
+        let loaded = store.load_balance_cache("balance_cache").unwrap();
+        assert_eq!(loaded["A"].len(), 1);
+        assert!(loaded["A"].contains_key("2024-09-02"));
+        assert!(!loaded.contains_key("B"));
+    }
+
+    #[test]
+    fn test_sqlite_delete_rewards() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut store = SqliteStore::open(dir.path().join("ctc.sqlite")).unwrap();
+
+        let mut rewards = RewardCache::new();
+        for name in ["A", "B"] {
+            rewards
+                .entry(name.to_string())
+                .or_default()
+                .insert("2024-09-01".to_string(), 1.0.into());
+        }
+        store.save_reward_cache("reward_cache", &rewards).unwrap();
+        store
+            .save_reward_cache("reward_cache_claimed", &rewards)
+            .unwrap();
+
+        // A moved to another address: its rows must not load again
+        store.delete_rewards("reward_cache", "A").unwrap();
+        let loaded = store.load_reward_cache("reward_cache").unwrap();
+        assert!(!loaded.contains_key("A"));
+        assert!(loaded.contains_key("B"));
+        assert!(store
+            .load_reward_cache("reward_cache_claimed")
+            .unwrap()
+            .contains_key("A"));
+    }
+}
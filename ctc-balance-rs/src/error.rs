@@ -0,0 +1,146 @@
+//! Error classification.
+//!
+//! `CtcError` separates transient failures (worth retrying) from permanent ones
+//! such as pruned state, decode errors or bad input. Functions still return
+//! `anyhow::Result`; the typed error travels inside and can be recovered with
+//! `err.downcast_ref::<CtcError>()`.
+
+use thiserror::Error;
+
+/// Classified failure from the chain, balance and reward paths
+#[derive(Debug, Clone, Error)]
+pub enum CtcError {
+    /// Network or RPC transport failure
+    #[error("Connection error: {0}")]
+    Connection(String),
+    /// The node no longer has state for the requested block
+    #[error("State pruned: {0}")]
+    PrunedState(String),
+    /// Storage or event data could not be decoded
+    #[error("Decode error: {0}")]
+    Decode(String),
+    /// Requested block or storage item does not exist
+    #[error("Not found: {0}")]
+    NotFound(String),
+    /// The node is throttling requests
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    /// Bad address, hash or other user input
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+}
+
+impl CtcError {
+    /// Whether retrying the same request could succeed
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Connection(_) | Self::RateLimited(_))
+    }
+
+    /// Whether the connection itself is gone (closed or reset), so retrying on
+    /// the same client can't succeed and it has to be reopened
+    pub fn is_disconnect(&self) -> bool {
+        let Self::Connection(message) = self else {
+            return false;
+        };
+        let lower = message.to_lowercase();
+        [
+            "restart required",
+            "restart needed",
+            "background task closed",
+            "connection closed",
+            "connection reset",
+            "websocket closed",
+            "broken pipe",
+        ]
+        .iter()
+        .any(|n| lower.contains(n))
+    }
+
+    /// Classify an error by its message.
+    ///
+    /// subxt and jsonrpsee report most node-side failures as strings, so this
+    /// matches the wording nodes use. Unrecognised errors count as connection
+    /// errors, keeping the previous retry-everything behaviour for them.
+    pub fn from_message(message: &str) -> Self {
+        let lower = message.to_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|n| lower.contains(n));
+
+        if has(&[
+            "state already discarded",
+            "pruned",
+            "unknown block",
+            "unknownblock",
+        ]) {
+            Self::PrunedState(message.to_string())
+        } else if has(&["429", "too many requests", "rate limit"]) {
+            Self::RateLimited(message.to_string())
+        } else if has(&["decode", "codec"]) {
+            Self::Decode(message.to_string())
+        } else if has(&["not found"]) {
+            Self::NotFound(message.to_string())
+        } else {
+            Self::Connection(message.to_string())
+        }
+    }
+}
+
+/// Errors that can be classified into a `CtcError`
+pub trait Classify {
+    fn classify(&self) -> CtcError;
+}
+
+impl Classify for CtcError {
+    fn classify(&self) -> CtcError {
+        self.clone()
+    }
+}
+
+impl Classify for subxt::Error {
+    fn classify(&self) -> CtcError {
+        CtcError::from_message(&self.to_string())
+    }
+}
+
+impl Classify for anyhow::Error {
+    fn classify(&self) -> CtcError {
+        match self.downcast_ref::<CtcError>() {
+            Some(e) => e.clone(),
+            None => CtcError::from_message(&format!("{:#}", self)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_messages() {
+        let pruned = CtcError::from_message("State already discarded for 0xabc");
+        assert!(matches!(pruned, CtcError::PrunedState(_)));
+        assert!(!pruned.is_transient());
+
+        let limited = CtcError::from_message("HTTP 429 Too Many Requests");
+        assert!(matches!(limited, CtcError::RateLimited(_)));
+        assert!(limited.is_transient());
+
+        let unknown = CtcError::from_message("connection reset by peer");
+        assert!(matches!(unknown, CtcError::Connection(_)));
+        assert!(unknown.is_transient());
+
+        let closed = CtcError::from_message(
+            "The background task closed connection closed; restart required",
+        );
+        assert!(closed.is_disconnect());
+        assert!(unknown.is_disconnect());
+        assert!(!CtcError::from_message("request timeout").is_disconnect());
+        assert!(!pruned.is_disconnect());
+    }
+
+    #[test]
+    fn test_classify_keeps_typed_error() {
+        let err = anyhow::Error::new(CtcError::InvalidInput("bad address".into()));
+        assert!(matches!(err.classify(), CtcError::InvalidInput(_)));
+        assert!(!err.classify().is_transient());
+    }
+}
@@ -0,0 +1,304 @@
+//! Interactive HTML report.
+//!
+//! Writes a single self-contained HTML file with the history embedded as JSON
+//! and drawn on canvases by a small inline script: hover for values, drag to
+//! zoom into a date range, double-click to reset.
+
+use crate::csv_output::{column_order, HistoryEntry};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+/// Page template; `__TITLE__` and `__DATA__` are filled in by `save_html_report`
+const TEMPLATE: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>__TITLE__</title>
+<style>
+  body { font-family: sans-serif; margin: 20px; color: #222; }
+  h1 { font-size: 20px; }
+  .chart { position: relative; margin-bottom: 24px; }
+  canvas { width: 100%; height: 360px; border: 1px solid #ddd; cursor: crosshair; }
+  #tooltip { position: absolute; pointer-events: none; background: rgba(255,255,255,0.95);
+             border: 1px solid #aaa; padding: 6px 8px; font-size: 12px; display: none;
+             white-space: nowrap; z-index: 1; }
+  .legend span { display: inline-block; margin-right: 14px; font-size: 13px; }
+  .legend i { display: inline-block; width: 12px; height: 12px; margin-right: 4px;
+              vertical-align: middle; }
+  .hint { color: #777; font-size: 12px; }
+</style>
+</head>
+<body>
+<h1>__TITLE__</h1>
+<p class="hint">Hover for values &middot; drag to zoom into a date range &middot;
+  double-click or <button id="reset">Reset zoom</button></p>
+<div class="chart"><div class="legend" id="legend-balance"></div><canvas id="balance"></canvas></div>
+<div class="chart" id="reward-chart"><div class="legend" id="legend-reward"></div><canvas id="reward"></canvas></div>
+<div id="tooltip"></div>
+<script id="data" type="application/json">__DATA__</script>
+<script>
+(function () {
+  const data = JSON.parse(document.getElementById("data").textContent);
+  const colors = ["#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd",
+                  "#8c564b", "#e377c2", "#7f7f7f", "#bcbd22", "#17becf"];
+  const n = data.dates.length;
+  let view = [0, n - 1];
+
+  const balanceSeries = data.accounts.map((a, i) => ({
+    name: a.name, values: a.balances, color: colors[i % colors.length], width: 1.5 }));
+  balanceSeries.push({ name: "Total", values: data.total, color: "#000", width: 2.5 });
+  const rewardSeries = data.rewards ? [
+    { name: "Daily reward", values: data.rewards, color: "#2ca02c", width: 1.5, bars: true },
+    { name: "Cumulative", values: data.cumulative, color: "#d62728", width: 2, right: true }
+  ] : [];
+  if (!data.rewards) document.getElementById("reward-chart").style.display = "none";
+
+  // Names are account labels from the user's files, so they only ever go in as text
+  function legend(id, series) {
+    const el = document.getElementById(id);
+    for (const s of series) {
+      const item = document.createElement("span"), box = document.createElement("i");
+      box.style.background = s.color;
+      item.append(box, s.name);
+      el.append(item);
+    }
+  }
+  legend("legend-balance", balanceSeries);
+  legend("legend-reward", rewardSeries);
+
+  const fmt = v => v == null ? "-" : v.toLocaleString(undefined, { maximumFractionDigits: 2 });
+  const pad = { left: 80, right: 80, top: 10, bottom: 40 };
+
+  function range(series, right) {
+    let lo = Infinity, hi = -Infinity;
+    for (const s of series) {
+      if (!!s.right !== right) continue;
+      for (let i = view[0]; i <= view[1]; i++) {
+        const v = s.values[i];
+        if (v == null) continue;
+        lo = Math.min(lo, v); hi = Math.max(hi, v);
+      }
+    }
+    if (lo === Infinity) return [0, 1];
+    lo = Math.min(lo, 0);
+    if (hi === lo) hi = lo + 1;
+    return [lo, hi + (hi - lo) * 0.05];
+  }
+
+  function setup(canvas) {
+    const ratio = window.devicePixelRatio || 1;
+    const w = canvas.clientWidth, h = canvas.clientHeight;
+    canvas.width = w * ratio; canvas.height = h * ratio;
+    const ctx = canvas.getContext("2d");
+    ctx.setTransform(ratio, 0, 0, ratio, 0, 0);
+    return { ctx, w, h };
+  }
+
+  function xOf(i, w) {
+    const span = Math.max(view[1] - view[0], 1);
+    return pad.left + (i - view[0]) / span * (w - pad.left - pad.right);
+  }
+
+  function indexAt(x, w) {
+    const span = Math.max(view[1] - view[0], 1);
+    const i = Math.round(view[0] + (x - pad.left) / (w - pad.left - pad.right) * span);
+    return Math.max(view[0], Math.min(view[1], i));
+  }
+
+  function draw(canvas, series, selection) {
+    const { ctx, w, h } = setup(canvas);
+    const plotH = h - pad.top - pad.bottom;
+    const ranges = { false: range(series, false), true: range(series, true) };
+    const yOf = (v, right) => {
+      const [lo, hi] = ranges[right];
+      return pad.top + plotH - (v - lo) / (hi - lo) * plotH;
+    };
+    ctx.clearRect(0, 0, w, h);
+    ctx.font = "11px sans-serif";
+    ctx.strokeStyle = "#eee"; ctx.fillStyle = "#555";
+
+    for (let t = 0; t <= 5; t++) {
+      const y = pad.top + plotH * t / 5;
+      ctx.beginPath(); ctx.moveTo(pad.left, y); ctx.lineTo(w - pad.right, y); ctx.stroke();
+      for (const right of [false, true]) {
+        if (!series.some(s => !!s.right === right)) continue;
+        const [lo, hi] = ranges[right];
+        ctx.textAlign = right ? "left" : "right";
+        ctx.fillText(fmt(hi - (hi - lo) * t / 5), right ? w - pad.right + 6 : pad.left - 6, y + 4);
+      }
+    }
+    const labels = Math.min(8, view[1] - view[0] + 1);
+    ctx.textAlign = "center";
+    for (let t = 0; t < labels; t++) {
+      const i = Math.round(view[0] + (view[1] - view[0]) * t / Math.max(labels - 1, 1));
+      ctx.fillText(data.dates[i], xOf(i, w), h - pad.bottom + 16);
+    }
+
+    for (const s of series) {
+      ctx.strokeStyle = s.color; ctx.fillStyle = s.color; ctx.lineWidth = s.width;
+      if (s.bars) {
+        const bw = Math.max(1, (w - pad.left - pad.right) / (view[1] - view[0] + 1) * 0.8);
+        for (let i = view[0]; i <= view[1]; i++) {
+          const v = s.values[i];
+          if (v == null) continue;
+          const y = yOf(v, !!s.right), y0 = yOf(0, !!s.right);
+          ctx.fillRect(xOf(i, w) - bw / 2, Math.min(y, y0), bw, Math.abs(y0 - y));
+        }
+        continue;
+      }
+      ctx.beginPath();
+      let drawing = false;
+      for (let i = view[0]; i <= view[1]; i++) {
+        const v = s.values[i];
+        if (v == null) { drawing = false; continue; }
+        const x = xOf(i, w), y = yOf(v, !!s.right);
+        if (drawing) ctx.lineTo(x, y); else ctx.moveTo(x, y);
+        drawing = true;
+      }
+      ctx.stroke();
+    }
+
+    if (selection) {
+      ctx.fillStyle = "rgba(31,119,180,0.15)";
+      ctx.fillRect(Math.min(selection[0], selection[1]), pad.top,
+                   Math.abs(selection[1] - selection[0]), plotH);
+    }
+  }
+
+  const charts = [
+    { canvas: document.getElementById("balance"), series: balanceSeries },
+    { canvas: document.getElementById("reward"), series: rewardSeries }
+  ].filter(c => c.series.length > 0);
+  const redraw = () => charts.forEach(c => draw(c.canvas, c.series));
+  const tooltip = document.getElementById("tooltip");
+
+  for (const chart of charts) {
+    let dragStart = null;
+    const pos = e => e.clientX - chart.canvas.getBoundingClientRect().left;
+    chart.canvas.addEventListener("mousedown", e => { dragStart = pos(e); });
+    chart.canvas.addEventListener("mousemove", e => {
+      const x = pos(e), w = chart.canvas.clientWidth, i = indexAt(x, w);
+      if (dragStart != null) draw(chart.canvas, chart.series, [dragStart, x]);
+      const date = document.createElement("b");
+      date.textContent = data.dates[i];
+      const rows = chart.series.map(s => {
+        const row = document.createElement("div"), box = document.createElement("i");
+        box.style.color = s.color;
+        box.textContent = "\u25a0";
+        row.append(box, " " + s.name + ": " + fmt(s.values[i]));
+        return row;
+      });
+      tooltip.replaceChildren(date, ...rows);
+      tooltip.style.display = "block";
+      tooltip.style.left = (e.pageX + 14) + "px";
+      tooltip.style.top = (e.pageY + 14) + "px";
+    });
+    chart.canvas.addEventListener("mouseleave", () => { tooltip.style.display = "none"; });
+    chart.canvas.addEventListener("mouseup", e => {
+      if (dragStart == null) return;
+      const w = chart.canvas.clientWidth;
+      const a = indexAt(Math.min(dragStart, pos(e)), w), b = indexAt(Math.max(dragStart, pos(e)), w);
+      dragStart = null;
+      if (b > a) view = [a, b];
+      redraw();
+    });
+    chart.canvas.addEventListener("dblclick", () => { view = [0, n - 1]; redraw(); });
+  }
+  document.getElementById("reset").addEventListener("click", () => { view = [0, n - 1]; redraw(); });
+  window.addEventListener("resize", redraw);
+  redraw();
+})();
+</script>
+</body>
+</html>
+"##;
+
+/// Write a self-contained interactive HTML report of the history.
+///
+/// Accounts without a value on a date (outside their window) are left as gaps.
+pub fn save_html_report<P: AsRef<Path>>(
+    path: P,
+    title: &str,
+    account_names: &[String],
+    entries: &[HistoryEntry],
+    include_rewards: bool,
+) -> Result<()> {
+    let path = path.as_ref();
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let accounts: Vec<Value> = column_order(account_names)
+        .iter()
+        .map(|name| {
+            let balances: Vec<Option<f64>> = entries
+                .iter()
+                .map(|e| e.balances.get(name).copied())
+                .collect();
+            json!({ "name": name, "balances": balances })
+        })
+        .collect();
+    let column = |f: fn(&HistoryEntry) -> f64| entries.iter().map(f).collect::<Vec<f64>>();
+    let data = json!({
+        "dates": entries.iter().map(|e| e.date.as_str()).collect::<Vec<_>>(),
+        "accounts": accounts,
+        "total": column(|e| e.total),
+        "rewards": include_rewards.then(|| column(|e| e.total_reward)),
+        "cumulative": include_rewards.then(|| column(|e| e.total_reward_cumulative)),
+    });
+
+    // "</" would end the script element early
+    let data = serde_json::to_string(&data)?.replace("</", "<\\/");
+    let html = TEMPLATE
+        .replace("__TITLE__", &escape_html(title))
+        .replace("__DATA__", &data);
+    fs::write(path, html).with_context(|| format!("Failed to write {:?}", path))?;
+    Ok(())
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_html_report_embeds_escaped_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("report.html");
+        let name = "<img src=x onerror=alert(1)></script>".to_string();
+        let entry = HistoryEntry {
+            date: "2024-09-01".to_string(),
+            balances: HashMap::from([(name.clone(), 12.5)]),
+            total: 12.5,
+            ..Default::default()
+        };
+
+        save_html_report(&path, "a & b", std::slice::from_ref(&name), &[entry], false).unwrap();
+        let html = fs::read_to_string(&path).unwrap();
+        assert!(html.contains("<title>a &amp; b</title>"));
+        // Scripts never parse data as markup
+        assert!(!html.contains("innerHTML"));
+
+        // The name only appears inside the JSON data block, and round-trips
+        let open = r#"<script id="data" type="application/json">"#;
+        let start = html.find(open).unwrap() + open.len();
+        let end = start + html[start..].find("</script>").unwrap();
+        let data: Value = serde_json::from_str(&html[start..end]).unwrap();
+        assert_eq!(data["accounts"][0]["name"], name.as_str());
+        assert_eq!(data["dates"][0], "2024-09-01");
+        let outside = format!("{}{}", &html[..start], &html[end..]);
+        assert!(!outside.contains("onerror"));
+        assert!(!outside.contains("<img"));
+    }
+}
@@ -3,26 +3,51 @@
 //! Tracks Creditcoin3 wallet balances from genesis to present.
 
 use anyhow::Result;
-use chrono::{Days, NaiveDate, Utc};
-use clap::Parser;
-use std::collections::HashMap;
+use chrono::{Days, NaiveDate, NaiveTime, Utc};
+use chrono_tz::Tz;
+use clap::{Parser, Subcommand};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, debug_span, info, warn, Instrument};
+use tracing_subscriber::EnvFilter;
+
+#[cfg(feature = "sqlite")]
+use ctc_balance::db::SqliteStore;
 
 use ctc_balance::{
-    accounts::load_accounts,
-    balance::BalanceTracker,
+    accounts::{
+        entries_to_colors, entries_to_groups, entries_to_map, entries_to_notes, entries_to_windows,
+        is_active_on, load_account_entries, parse_account_entries, validate_account_entries,
+        AccountEntry, DateWindow,
+    },
+    balance::{Balance, BalanceMetric, BalanceTracker},
     cache::{
-        load_block_cache, load_reward_cache, save_block_cache, save_reward_cache, BlockCache,
-        RewardCache,
+        cache_path, drop_moved_reward_accounts, load_balance_cache, load_block_cache,
+        load_reward_accounts, load_reward_cache, load_stake_cache, merge_snapshot_view,
+        reward_coverage, reward_totals, save_balance_cache, save_block_cache, save_reward_accounts,
+        save_reward_cache, save_stake_cache, snapshot_view, BalanceCache, BlockCache, CachedReward,
+        CoverageKind, RewardCache, Snapshot, StakeCache,
     },
-    chain::ChainConnector,
+    chain::{ChainConnector, ChainInfo, HeadMode, DEFAULT_MAX_BLOCK_ERROR_SECS},
     csv_output::{
-        calculate_diffs, load_existing_csv, save_combined_csv, save_individual_csvs, HistoryEntry,
+        append_block_csv, balance_missing, build_history_entries, calculate_aprs,
+        calculate_component_totals, calculate_diffs, check_csv_consistency, column_order,
+        compare_csvs, load_csv_accounts, load_csv_rewards, load_existing_csv,
+        reward_balance_mismatches, reward_stats, save_alerts_csv, save_block_csv,
+        save_combined_csv, save_comparison_csv, save_era_points_csv, save_individual_csvs,
+        save_long_csv, save_nonce_csv, save_reward_breakdown_csv, save_reward_check_csv,
+        save_reward_stats_csv, save_totals_csv, threshold_alerts, BlockEntry, HistoryEntry,
+        RewardStats, Rounding, ThresholdAlert,
     },
-    fetch_ctc_price,
-    plot::plot_balances,
-    reward::RewardTracker,
-    CONCURRENCY_BALANCES, CONCURRENCY_DATES, CONCURRENCY_REWARDS, GENESIS_DATE, NODE_URL,
+    fetch_ctc_price, fetch_ctc_price_history,
+    html::save_html_report,
+    plot::{plot_balances, plot_diffs, plot_groups, PlotOptions},
+    reward::{AccountRole, EraPoints, RewardBasis, RewardTracker},
+    DateExpr, BLOCK_TIME_SECONDS, CONCURRENCY_BALANCES, CONCURRENCY_DATES, CONCURRENCY_REWARDS,
+    CONCURRENCY_STORAGE, DEFAULT_RPC_TIMEOUT_SECS, GENESIS_DATE, NODE_URL,
 };
 
 /// CTC Balance Tracker - Track Creditcoin3 wallet balances
@@ -30,7 +55,7 @@ use ctc_balance::{
 #[command(name = "ctc-balance")]
 #[command(about = "Track Creditcoin3 wallet balances from genesis to present")]
 struct Args {
-    /// Wallet addresses file
+    /// Wallet addresses file, text or .yaml ("-" reads text from stdin)
     #[arg(short, long)]
     file: Option<PathBuf>,
 
@@ -42,30 +67,121 @@ struct Args {
     #[arg(short, long, default_value = "wallet")]
     name: String,
 
-    /// Start date (YYYY-MM-DD)
-    #[arg(long)]
-    start: Option<String>,
+    /// Start date: YYYY-MM-DD, today, yesterday, genesis, or a span back from
+    /// today such as -30d, -2w, -6m, -1y or P30D
+    #[arg(long, allow_hyphen_values = true)]
+    start: Option<DateExpr>,
+
+    /// End date, in the same forms as --start
+    #[arg(long, allow_hyphen_values = true)]
+    end: Option<DateExpr>,
 
-    /// End date (YYYY-MM-DD)
+    /// Time of day (HH:MM, in --timezone) each date is sampled at [default: 00:00]
+    #[arg(long, value_parser = parse_snapshot_time)]
+    snapshot_time: Option<NaiveTime>,
+
+    /// IANA timezone for date boundaries, e.g. Asia/Seoul [default: UTC].
+    /// The zone is part of the block cache key, so zones never mix.
     #[arg(long)]
-    end: Option<String>,
+    timezone: Option<Tz>,
 
     /// Output CSV file
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Base directory for caches, CSVs and graphs [default: output]. A relative
+    /// --output is then taken inside it.
+    #[arg(long, value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
     /// Generate graph
     #[arg(short, long)]
     graph: bool,
 
-    /// Skip staking rewards fetching
+    /// Also write an interactive HTML report (hover values, date-range zoom)
+    #[arg(long)]
+    html: bool,
+
+    /// Also draw daily balance changes as signed bars (<history>_diff.png)
+    #[arg(long)]
+    diff_plot: bool,
+
+    /// Draw the total panel as stacked per-account areas
+    #[arg(long)]
+    stacked: bool,
+
+    /// Draw graph date labels vertically
+    #[arg(long)]
+    rotate_x_labels: bool,
+
+    /// Add balance + cumulative rewards as a "total value" line and CSV column
+    /// (for rewards paid out of the tracked accounts, e.g. to a separate payee)
+    #[arg(long, conflicts_with = "no_rewards")]
+    total_with_rewards: bool,
+
+    /// Also write the numbers behind the main graph (<history>_plotdata.csv)
     #[arg(long)]
+    plot_data: bool,
+
+    /// Write caches gzip-compressed (*.json.gz); existing *.json caches are still read
+    #[arg(long)]
+    compress_cache: bool,
+
+    /// Keep block, balance and reward caches in this SQLite database instead of
+    /// JSON files (the stake cache and reward-accounts file stay JSON)
+    #[cfg(feature = "sqlite")]
+    #[arg(long, value_name = "PATH")]
+    db: Option<PathBuf>,
+
+    /// Add a panel with the CTC price and total USD value to the graph
+    #[arg(long)]
+    price_panel: bool,
+
+    /// Fail the run when the CTC price can't be fetched, instead of leaving
+    /// USD values out
+    #[arg(long)]
+    require_price: bool,
+
+    /// Balance figure for the account and total columns: free, spendable or owned.
+    /// Non-free metrics are written to their own history file.
+    #[arg(long, default_value = "free")]
+    balance_metric: BalanceMetric,
+
+    /// Also write <history>.csv.sha256 with the row count and SHA-256 of the
+    /// combined CSV's data rows, checked when the CSV is next loaded
+    #[arg(long)]
+    checksum: bool,
+
+    /// How CSV values are cut to their decimals: round, trunc, floor or ceil
+    #[arg(long, default_value = "round")]
+    rounding: Rounding,
+
+    /// Largest gap (seconds) between a date's target time and its block's timestamp;
+    /// dates whose best block is further off are skipped instead of cached
+    #[arg(long, default_value_t = DEFAULT_MAX_BLOCK_ERROR_SECS)]
+    max_block_error_secs: u64,
+
+    /// Skip staking rewards fetching
+    #[arg(long, visible_alias = "balances-only")]
     no_rewards: bool,
 
+    /// Track holdings of this pallet-Assets token instead of native CTC (implies --no-rewards)
+    #[arg(long, value_name = "ID", conflicts_with = "rewards_only")]
+    asset: Option<u128>,
+
+    /// Only fetch staking rewards, reusing balances from the existing CSV
+    #[arg(long, conflicts_with = "no_rewards")]
+    rewards_only: bool,
+
     /// Local RPC URL for recent blocks (faster, may have pruned data)
     #[arg(long)]
     local_rpc: Option<String>,
 
+    /// Search for the local RPC's first archived block again instead of using
+    /// the one saved in chain_meta.json
+    #[arg(long, requires = "local_rpc")]
+    redetect_local: bool,
+
     /// Ignore caches
     #[arg(long)]
     no_cache: bool,
@@ -73,115 +189,997 @@ struct Args {
     /// Re-fetch and overwrite entries with zero balance
     #[arg(long)]
     refetch_zero: bool,
+
+    /// Chain head used as "latest": finalized or best
+    #[arg(long, default_value = "finalized")]
+    head: HeadMode,
+
+    /// Only fetch current balances and lifetime rewards at the latest block
+    #[arg(long)]
+    only_latest: bool,
+
+    /// Fetch balances/rewards at these block numbers instead of dates (e.g. 1000,2000)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 1..,
+        conflicts_with_all = ["start", "end", "only_latest"]
+    )]
+    blocks: Vec<u64>,
+
+    /// Sample balances across the single day given by --start, every
+    /// --granularity-blocks blocks, into <name>_intraday_<date>.csv
+    #[arg(long, requires = "start", conflicts_with_all = ["only_latest", "blocks"])]
+    intraday: bool,
+
+    /// Blocks between --intraday samples (240 is about an hour)
+    #[arg(long, default_value_t = 240, value_parser = clap::value_parser!(u64).range(1..))]
+    granularity_blocks: u64,
+
+    /// Stay connected: write balances at every new --head block to
+    /// <name>_watch.csv and refresh the daily history at each new date, until Ctrl-C
+    #[arg(
+        long,
+        conflicts_with_all = ["only_latest", "blocks", "intraday", "extrinsic", "retry_failed"]
+    )]
+    watch: bool,
+
+    /// Show balances just before and after the block including this extrinsic hash, then exit
+    #[arg(long, conflicts_with_all = ["start", "end", "only_latest", "blocks"])]
+    extrinsic: Option<String>,
+
+    /// Blocks back from the head searched for --extrinsic (default: about a day)
+    #[arg(long, default_value = "14400")]
+    extrinsic_search_blocks: u64,
+
+    /// Only re-fetch the dates listed in this source's failed-dates file
+    /// (<source>_failed_dates.json in the output directory)
+    #[arg(long, conflicts_with_all = ["start", "end", "only_latest", "blocks"])]
+    retry_failed: bool,
+
+    /// Exit with an error as soon as a stage has dates that failed to fetch,
+    /// instead of warning and writing them as 0.0
+    #[arg(long)]
+    strict: bool,
+
+    /// First date of the chain (default range start), for forks and dev chains
+    /// [default: 2024-08-29]
+    #[arg(long, value_name = "DATE")]
+    genesis_date: Option<NaiveDate>,
+
+    /// Block treated as the chain's first when finding blocks for dates
+    /// [default: 1]
+    #[arg(long)]
+    genesis_block: Option<u64>,
+
+    /// Print the raw decoded System.Account entry of every account at this date
+    /// (YYYY-MM-DD) as JSON, then exit
+    #[arg(long, value_name = "DATE", conflicts_with_all = ["only_latest", "blocks"])]
+    debug_account_dump: Option<NaiveDate>,
+
+    /// Check the accounts file (addresses parse, current balances) and exit
+    #[arg(long, requires = "file")]
+    accounts_validate_only: bool,
+
+    /// Print which dates the reward cache covers per account (rewarded, zero, missing) and exit
+    #[arg(long)]
+    reward_coverage: bool,
+
+    /// Reward figure used for output: earned (era share) or claimed (paid out)
+    #[arg(long, default_value = "earned")]
+    reward_basis: RewardBasis,
+
+    /// Blocks within this many of the best head have their reward events read only
+    /// once finalized; unfinalized ones are skipped (0 = off)
+    #[arg(long, default_value_t = 0)]
+    finality_margin: u64,
+
+    /// Largest block range the event-scanning reward fallback may scan (0 = unlimited)
+    #[arg(long, default_value_t = 50000)]
+    max_blocks_scan: u64,
+
+    /// Directory for per-account CSVs and graphs [default: output/<source>/individual]
+    #[arg(long)]
+    individual_dir: Option<PathBuf>,
+
+    /// Also write a long-format CSV (date,account,metric,value)
+    #[arg(long)]
+    long_format: bool,
+
+    /// Accounts left out of the total columns and the total graph; they are
+    /// still fetched, written and plotted on their own (e.g. Exchange,Escrow)
+    #[arg(long, value_delimiter = ',', value_name = "NAME")]
+    exclude_from_total: Vec<String>,
+
+    /// Add total_free, total_reserved and total_frozen columns to the combined
+    /// CSV, whatever --balance-metric the total uses
+    #[arg(long, conflicts_with = "rewards_only")]
+    detailed_totals: bool,
+
+    /// Also write the totals columns alone, without per-account columns
+    /// (<history>_totals.csv)
+    #[arg(long)]
+    totals_csv: bool,
+
+    /// Also write one CSV per account group, holding the group's accounts and
+    /// their total (<history>_group_<GROUP>.csv)
+    #[arg(long)]
+    group_csvs: bool,
+
+    /// Also write each account's transaction nonce per date (<history>_nonce.csv)
+    #[arg(long, conflicts_with = "asset")]
+    include_nonce: bool,
+
+    /// Check each account's daily balance change in the saved history CSV
+    /// against its rewards and report days differing by more than TOLERANCE CTC
+    /// (<history>_reward_check.csv); no RPC is used
+    #[arg(
+        long,
+        value_name = "TOLERANCE",
+        num_args = 0..=1,
+        default_missing_value = "1.0"
+    )]
+    verify_reward_against_balance: Option<f64>,
+
+    /// Also write earned rewards per validator (<history>_reward_breakdown.csv)
+    #[arg(long)]
+    reward_breakdown: bool,
+
+    /// Print the distribution of daily rewards (min, max, mean, median, p90)
+    /// per account and overall, and write it to <history>_reward_stats.csv
+    #[arg(long, conflicts_with = "no_rewards")]
+    reward_stats: bool,
+
+    /// Also write the reward points of every era read by the era path, all
+    /// validators included (<history>_era_points.csv)
+    #[arg(long)]
+    export_era_points: bool,
+
+    /// Flag accounts whose latest balance is below this value
+    #[arg(long, value_name = "CTC")]
+    alert_below: Option<f64>,
+
+    /// Flag accounts whose latest balance is above this value
+    #[arg(long, value_name = "CTC")]
+    alert_above: Option<f64>,
+
+    /// Also write the --alert-below/--alert-above results to this CSV
+    #[arg(long)]
+    alert_file: Option<PathBuf>,
+
+    /// Emit per-account APR columns from daily rewards and active stake
+    #[arg(long)]
+    apr: bool,
+
+    /// Trailing days averaged for APR to smooth lumpy era payouts
+    #[arg(long, default_value_t = 7)]
+    apr_window: usize,
+
+    /// Blocks per day scanned for rewards when the next date's block is unknown
+    /// (default: 86400 / detected block time)
+    #[arg(long)]
+    reward_span_blocks: Option<u64>,
+
+    /// Timeout in seconds for a single RPC call; timed-out calls are retried
+    #[arg(long, default_value_t = DEFAULT_RPC_TIMEOUT_SECS)]
+    rpc_timeout_secs: u64,
+
+    /// Cap on balance/stake storage reads in flight across all dates and accounts
+    #[arg(long, value_name = "N")]
+    max_inflight: Option<usize>,
+
+    /// Batch each date's account reads into `state_queryStorageAt` calls
+    /// instead of one request per account (off until benchmarked; compare
+    /// `balances.elapsed_secs` in run_manifest.json with and without it)
+    #[arg(long)]
+    batch: bool,
+
+    /// Extra header for RPC connections, e.g. "Authorization: Bearer <token>" (repeatable)
+    #[arg(long = "rpc-header", value_name = "KEY: VALUE", value_parser = parse_rpc_header)]
+    rpc_headers: Vec<(String, String)>,
+
+    /// HTTP proxy for RPC and price requests, e.g. "http://proxy:3128"
+    /// (defaults to HTTPS_PROXY)
+    #[arg(long, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Print a per-account summary (staking role, latest balance, total rewards)
+    #[arg(long)]
+    summary: bool,
+
+    /// Log level (error, warn, info, debug, trace); overrides RUST_LOG
+    #[arg(long)]
+    log_level: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Re-query sampled dates and reconcile the saved CSV against the chain
+    Verify {
+        /// Number of dates to re-query
+        #[arg(long, default_value_t = 10)]
+        samples: usize,
+
+        /// Allowed difference in CTC before a value is reported
+        #[arg(long, default_value_t = 0.1)]
+        tolerance: f64,
+    },
+    /// Report what changed between two history CSVs (dates, accounts, values)
+    Compare {
+        /// Earlier CSV output
+        old: PathBuf,
+
+        /// Later CSV output
+        new: PathBuf,
+
+        /// Allowed difference in CTC before a value is reported
+        #[arg(long, default_value_t = 0.1)]
+        tolerance: f64,
+
+        /// Also write the changed values as CSV (date,account,old,new,diff)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Rebuild derived columns and graphs from a saved history CSV, without RPC.
+    /// Writes `<csv stem>_recomputed.csv` beside the input unless --output is
+    /// given, so the input is only replaced on request; --file supplies account
+    /// windows, groups and colors.
+    Recompute {
+        /// History CSV to recompute
+        csv: PathBuf,
+    },
+}
+
+impl Args {
+    /// Snapshot point (time of day and zone) selected on the command line
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            time: self.snapshot_time.unwrap_or(NaiveTime::MIN),
+            tz: self.timezone.unwrap_or(Tz::UTC),
+        }
+    }
+
+    /// History CSV path: `--output`, or one named after the source, snapshot
+    /// point and balance metric so differing runs never share a file
+    fn history_file(&self, output_dir: &std::path::Path, source_name: &str) -> PathBuf {
+        let tag = self.output_tag();
+        self.output_or(output_dir, format!("{}_history{}.csv", source_name, tag))
+    }
+
+    /// `--retry-failed` list for the source, keyed like its history file so
+    /// runs with other settings keep their own failures
+    fn failed_dates_file(&self, output_dir: &std::path::Path, source_name: &str) -> PathBuf {
+        output_dir.join(format!(
+            "{}_failed_dates{}.json",
+            source_name,
+            self.output_tag()
+        ))
+    }
+
+    /// Suffix for the snapshot point, asset and balance metric
+    fn output_tag(&self) -> String {
+        let mut tag = self.snapshot().tag();
+        tag.push_str(&self.asset_tag());
+        if self.balance_metric != BalanceMetric::Free {
+            tag.push('_');
+            tag.push_str(self.balance_metric.name());
+        }
+        tag
+    }
+
+    /// Base directory for all artifacts: `--output-dir`, else `output/`
+    fn output_dir(&self) -> PathBuf {
+        self.output_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("output"))
+    }
+
+    /// `--output` if given (inside `--output-dir` when relative and a directory
+    /// was given), else `name` in `output_dir`
+    fn output_or(&self, output_dir: &std::path::Path, name: String) -> PathBuf {
+        match &self.output {
+            Some(output) if output.is_relative() && self.output_dir.is_some() => {
+                output_dir.join(output)
+            }
+            Some(output) => output.clone(),
+            None => output_dir.join(name),
+        }
+    }
+
+    /// Per-account output directory: `--individual-dir`, or one per source so
+    /// runs on different account files don't overwrite each other
+    fn individual_dir(&self, output_dir: &std::path::Path, source_name: &str) -> PathBuf {
+        self.individual_dir
+            .clone()
+            .unwrap_or_else(|| output_dir.join(source_name).join("individual"))
+    }
+
+    /// `--start` resolved against today (in --timezone) and the genesis date
+    fn start_date(&self) -> Result<Option<NaiveDate>> {
+        self.start
+            .map(|e| e.resolve(self.snapshot().today(), self.genesis_date()))
+            .transpose()
+    }
+
+    /// `--end` resolved like `start_date`
+    fn end_date(&self) -> Result<Option<NaiveDate>> {
+        self.end
+            .map(|e| e.resolve(self.snapshot().today(), self.genesis_date()))
+            .transpose()
+    }
+
+    /// `--start`/`--end` resolved, defaulting to the genesis date and today;
+    /// errors on an inverted range
+    fn date_bounds(&self) -> Result<(NaiveDate, NaiveDate)> {
+        let start = self.start_date()?.unwrap_or_else(|| self.genesis_date());
+        let end = self.end_date()?.unwrap_or_else(|| self.snapshot().today());
+        anyhow::ensure!(
+            start <= end,
+            "Start date {} is after end date {}",
+            start,
+            end
+        );
+        Ok((start, end))
+    }
+
+    /// Chain start date: `--genesis-date`, else the mainnet genesis date
+    fn genesis_date(&self) -> NaiveDate {
+        self.genesis_date
+            .unwrap_or_else(|| NaiveDate::parse_from_str(GENESIS_DATE, "%Y-%m-%d").unwrap())
+    }
+
+    /// Unit shown next to totals
+    fn unit(&self) -> String {
+        match self.asset {
+            Some(id) => format!("(asset {})", id),
+            None => "CTC".to_string(),
+        }
+    }
+
+    /// File name suffix for `--asset` runs, so asset and CTC data never mix
+    fn asset_tag(&self) -> String {
+        self.asset
+            .map(|id| format!("_asset{}", id))
+            .unwrap_or_default()
+    }
+}
+
+/// `(account, date)` cache entries changed since the last save
+type DirtyRows = BTreeSet<(String, String)>;
+
+/// Where block, balance and reward caches are loaded from and saved to:
+/// JSON files in the output directory, or the `--db` SQLite database. The stake
+/// cache and the reward-accounts sidecar are always JSON files (see `path`).
+struct CacheStore {
+    output_dir: PathBuf,
+    compress: bool,
+    #[cfg(feature = "sqlite")]
+    db: Option<SqliteStore>,
+}
+
+impl CacheStore {
+    fn open(args: &Args, output_dir: &std::path::Path) -> Result<Self> {
+        Ok(Self {
+            output_dir: output_dir.to_path_buf(),
+            compress: args.compress_cache,
+            #[cfg(feature = "sqlite")]
+            db: args.db.as_ref().map(SqliteStore::open).transpose()?,
+        })
+    }
+
+    /// JSON cache file for `stem` (also used for caches the database doesn't hold)
+    fn path(&self, stem: &str) -> PathBuf {
+        cache_path(&self.output_dir, stem, self.compress)
+    }
+
+    fn load_blocks(&self) -> Result<BlockCache> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.load_block_cache();
+        }
+        load_block_cache(self.path("block_cache"))
+    }
+
+    fn save_blocks(&mut self, cache: &BlockCache) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &mut self.db {
+            return db.save_block_cache(cache);
+        }
+        save_block_cache(self.path("block_cache"), cache)
+    }
+
+    fn load_balances(&self, stem: &str) -> Result<BalanceCache> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.load_balance_cache(stem);
+        }
+        load_balance_cache(self.path(stem))
+    }
+
+    /// Save the balance cache: the database gets only the `dirty` entries, the
+    /// JSON file is rewritten whole. `dirty` is cleared once saved.
+    fn save_balances(
+        &mut self,
+        stem: &str,
+        cache: &BalanceCache,
+        dirty: &mut DirtyRows,
+    ) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &mut self.db {
+            db.save_balance_rows(stem, cache, dirty.iter())?;
+            dirty.clear();
+            return Ok(());
+        }
+        save_balance_cache(self.path(stem), cache)?;
+        dirty.clear();
+        Ok(())
+    }
+
+    fn load_rewards(&self, stem: &str) -> Result<RewardCache> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &self.db {
+            return db.load_reward_cache(stem);
+        }
+        load_reward_cache(self.path(stem))
+    }
+
+    /// Save the reward cache, like `save_balances`
+    fn save_rewards(
+        &mut self,
+        stem: &str,
+        cache: &RewardCache,
+        dirty: &mut DirtyRows,
+    ) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &mut self.db {
+            db.save_reward_rows(stem, cache, dirty.iter())?;
+            dirty.clear();
+            return Ok(());
+        }
+        save_reward_cache(self.path(stem), cache)?;
+        dirty.clear();
+        Ok(())
+    }
+
+    /// Forget `account`'s cached rewards. The JSON cache is rewritten whole from
+    /// the map on save, so only the database needs the rows deleted.
+    #[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+    fn delete_rewards(&mut self, stem: &str, account: &str) -> Result<()> {
+        #[cfg(feature = "sqlite")]
+        if let Some(db) = &mut self.db {
+            return db.delete_rewards(stem, account);
+        }
+        Ok(())
+    }
+}
+
+/// Per-stage date counts recorded in the run manifest
+#[derive(Debug, Default, Serialize)]
+struct StageCounts {
+    /// Dates served from caches or the existing CSV
+    cached: usize,
+    /// Dates fetched from the chain in this run
+    fetched: usize,
+    /// Dates that failed to fetch
+    failed: Vec<String>,
+    /// Wall-clock seconds spent fetching (0 when everything was cached)
+    elapsed_secs: f64,
+}
+
+/// RPC endpoint and the block range it served
+#[derive(Debug, Serialize)]
+struct RpcEndpoint {
+    url: String,
+    /// First block served (`None` = from genesis)
+    from_block: Option<u64>,
+    /// Last block served (`None` = up to the head)
+    to_block: Option<u64>,
+    used_for: Vec<&'static str>,
+}
+
+/// Machine-readable record of a run, written to `run_manifest.json` in the
+/// output directory
+#[derive(Debug, Default, Serialize)]
+struct RunManifest {
+    tool_version: &'static str,
+    args: Vec<String>,
+    chain: String,
+    spec_version: String,
+    genesis_hash: String,
+    start_date: String,
+    end_date: String,
+    days: usize,
+    rpc_endpoints: Vec<RpcEndpoint>,
+    blocks: StageCounts,
+    balances: StageCounts,
+    rewards: StageCounts,
+    /// Times a dropped RPC connection was reopened during the run
+    reconnects: u64,
+    finished_at: String,
+}
+
+impl RunManifest {
+    fn save(&mut self, output_dir: &std::path::Path) -> Result<PathBuf> {
+        self.finished_at = Utc::now().to_rfc3339();
+        let path = output_dir.join("run_manifest.json");
+        std::fs::create_dir_all(output_dir)?;
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// First archived block found on a local RPC node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LocalRpcMeta {
+    url: String,
+    genesis_hash: String,
+    first_block: u64,
+}
+
+/// Chain facts kept between runs in `chain_meta.json` in the output directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ChainMeta {
+    #[serde(default)]
+    local_rpc: Option<LocalRpcMeta>,
+}
+
+impl ChainMeta {
+    fn path(output_dir: &std::path::Path) -> PathBuf {
+        output_dir.join("chain_meta.json")
+    }
+
+    fn load(output_dir: &std::path::Path) -> Result<Self> {
+        let path = Self::path(output_dir);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    fn save(&self, output_dir: &std::path::Path) -> Result<()> {
+        std::fs::create_dir_all(output_dir)?;
+        std::fs::write(Self::path(output_dir), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Saved first block for this node and chain, if any
+    fn local_first_block(&self, url: &str, genesis_hash: &str) -> Option<u64> {
+        self.local_rpc
+            .as_ref()
+            .filter(|m| m.url == url && m.genesis_hash == genesis_hash)
+            .map(|m| m.first_block)
+    }
+}
+
+/// Dates whose balances or rewards failed to fetch, kept in the source's
+/// failed-dates file (`Args::failed_dates_file`) for `--retry-failed`
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FailedDates {
+    #[serde(default)]
+    balances: Vec<String>,
+    #[serde(default)]
+    rewards: Vec<String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl FailedDates {
+    fn load(path: PathBuf) -> Result<Self> {
+        let mut failed = if path.exists() {
+            serde_json::from_str(&std::fs::read_to_string(&path)?)?
+        } else {
+            Self::default()
+        };
+        failed.path = path;
+        Ok(failed)
+    }
+
+    fn save(&self) -> Result<&std::path::Path> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.path, serde_json::to_string_pretty(self)?)?;
+        Ok(&self.path)
+    }
+
+    /// Replace the entries for the dates covered by this run with its failures;
+    /// dates outside the run are kept
+    fn update(&mut self, dates: &[String], balances: &[String], rewards: &[String]) {
+        for (list, failed) in [(&mut self.balances, balances), (&mut self.rewards, rewards)] {
+            list.retain(|d| !dates.contains(d));
+            list.extend(failed.iter().cloned());
+            list.sort();
+            list.dedup();
+        }
+    }
+
+    /// Every listed date, balances and rewards combined
+    fn dates(&self) -> Vec<NaiveDate> {
+        let mut dates: Vec<NaiveDate> = self
+            .balances
+            .iter()
+            .chain(&self.rewards)
+            .filter_map(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+            .collect();
+        dates.sort();
+        dates.dedup();
+        dates
+    }
+
+    fn is_empty(&self) -> bool {
+        self.balances.is_empty() && self.rewards.is_empty()
+    }
+}
+
+/// Accounts to track, from `--file` or `--address`, with their per-account
+/// settings from the accounts file
+struct AccountSet {
+    accounts: HashMap<String, String>,
+    source_name: String,
+    account_windows: HashMap<String, DateWindow>,
+    account_groups: Option<HashMap<String, String>>,
+    account_colors: HashMap<String, String>,
+    account_notes: HashMap<String, String>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = Args::parse();
+    let mut args = Args::parse();
+    if args.asset.is_some() && !args.no_rewards {
+        // Staking rewards are paid in CTC, not in the tracked asset
+        args.no_rewards = true;
+    }
+    init_logging(args.log_level.as_deref())?;
+    ctc_balance::set_rpc_timeout(args.rpc_timeout_secs);
+    ctc_balance::set_rpc_headers(&args.rpc_headers)?;
+    if let Some(proxy) = &args.proxy {
+        ctc_balance::set_proxy(proxy)?;
+    }
 
     println!("{}", "=".repeat(60));
     println!("CTC Balance Tracker - Rust Version");
     println!("{}", "=".repeat(60));
 
+    if let Some(Command::Compare {
+        old,
+        new,
+        tolerance,
+        output,
+    }) = &args.command
+    {
+        return run_compare(old, new, *tolerance, output.as_deref());
+    }
+
+    if let Some(Command::Recompute { csv }) = &args.command {
+        return run_recompute(&args, csv);
+    }
+    // Catch a bad --start/--end before connecting to anything
+    args.date_bounds()?;
+
     // 1. Load accounts
-    println!("\n[1/6] Loading accounts...");
+    info!("[1/6] Loading accounts...");
+    let mut account_windows: HashMap<String, DateWindow> = HashMap::new();
+    let mut account_groups: Option<HashMap<String, String>> = None;
+    let mut account_colors: HashMap<String, String> = HashMap::new();
+    let mut account_notes: HashMap<String, String> = HashMap::new();
     let (accounts, source_name) = if let Some(file_path) = &args.file {
-        let accts = load_accounts(file_path)?;
-        let name = file_path
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("accounts")
-            .to_string();
-        println!("  Loaded: {} accounts from {:?}", accts.len(), file_path);
+        let from_stdin = file_path.as_os_str() == "-";
+        let (entries, name) = if from_stdin {
+            (
+                parse_account_entries(std::io::stdin().lock())?,
+                "stdin".to_string(),
+            )
+        } else {
+            let name = file_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("accounts")
+                .to_string();
+            (load_account_entries(file_path)?, name)
+        };
+        if args.accounts_validate_only {
+            return run_accounts_validate(&args, &entries).await;
+        }
+        validate_account_entries(&entries)?;
+        account_windows = entries_to_windows(&entries);
+        account_groups = entries_to_groups(&entries);
+        account_colors = entries_to_colors(&entries);
+        account_notes = entries_to_notes(&entries);
+        let accts = entries_to_map(entries);
+        if from_stdin {
+            info!("Loaded: {} accounts from stdin", accts.len());
+        } else {
+            info!("Loaded: {} accounts from {:?}", accts.len(), file_path);
+        }
         (accts, name)
     } else if let Some(address) = &args.address {
+        ctc_balance::parse_ss58_address(address)?;
         let mut accts = HashMap::new();
         accts.insert(args.name.clone(), address.clone());
-        println!("  Single wallet: {}", args.name);
+        info!("Single wallet: {}", args.name);
         (accts, args.name.clone())
     } else {
         anyhow::bail!("Either --file or --address must be specified");
     };
 
+    if let Some(tolerance) = args.verify_reward_against_balance {
+        return run_reward_check(&args, &accounts, &source_name, tolerance);
+    }
+
     // 2. Connect to chain
-    println!("\n[2/6] Connecting to RPC...");
+    info!("[2/6] Connecting to RPC...");
     let mut chain = ChainConnector::new(Some(NODE_URL));
+    chain.set_head_mode(args.head);
     chain.connect().await?;
 
     let info = chain.get_chain_info().await?;
-    println!("  Remote RPC: {} ({})", NODE_URL, info);
+    info!("Remote RPC: {} ({})", NODE_URL, info);
+    if args.genesis_date.is_some() || args.genesis_block.is_some() {
+        check_genesis_override(&args, &mut chain).await?;
+    }
+    if let Some(asset_id) = args.asset {
+        // Fails early if the chain has no Assets pallet or no such asset
+        let latest = chain.get_latest_block_number().await?;
+        let latest_hash = chain.get_block_hash(latest).await?;
+        let mut tracker = BalanceTracker::new(NODE_URL);
+        tracker.set_client((**chain.client()?).clone());
+        let metadata = tracker.get_asset_metadata(asset_id, &latest_hash).await?;
+        info!(
+            "Tracking asset {} ({}, {} decimals) instead of CTC",
+            asset_id, metadata.symbol, metadata.decimals
+        );
+    }
 
     // Connect to local RPC if provided and detect first block
     let local_first_block: Option<u64> = if let Some(local_url) = &args.local_rpc {
         let mut local_chain = ChainConnector::new(Some(local_url));
         if local_chain.connect().await.is_ok() {
-            let latest = chain.get_latest_block_number().await.unwrap_or(0);
-            let first_block = detect_first_block(local_url, latest).await;
+            let output_dir = args.output_dir();
+            let mut meta = ChainMeta::load(&output_dir).unwrap_or_default();
+            // Keyed on the local node's own chain, so a node resynced to another
+            // chain behind the same URL is detected afresh
+            let local_genesis = local_chain.get_block_hash(0).await.ok();
+            let saved = local_genesis
+                .as_deref()
+                .and_then(|genesis_hash| meta.local_first_block(local_url, genesis_hash))
+                .filter(|_| !args.redetect_local);
+            // A pruning node drops old state over time, so the saved block must
+            // still be served; one query instead of a full search
+            let saved = match saved {
+                Some(block) => {
+                    let mut tracker = RewardTracker::new(local_url);
+                    let still_served =
+                        tracker.connect().await.is_ok() && tracker.has_state(block).await;
+                    if !still_served {
+                        info!("Local RPC no longer serves block {}; re-detecting", block);
+                    }
+                    Some(block).filter(|_| still_served)
+                }
+                None => None,
+            };
+            let first_block = match saved {
+                Some(block) => {
+                    debug!("Local RPC first block {} from chain_meta.json", block);
+                    block
+                }
+                None => {
+                    let latest = chain.get_latest_block_number().await.unwrap_or(0);
+                    let block = detect_first_block(local_url, latest).await;
+                    // 0 also means the search couldn't connect; a full-history
+                    // node is found by the fast path anyway
+                    if let Some(genesis_hash) = local_genesis.filter(|_| block > 0) {
+                        meta.local_rpc = Some(LocalRpcMeta {
+                            url: local_url.clone(),
+                            genesis_hash,
+                            first_block: block,
+                        });
+                        if let Err(e) = meta.save(&output_dir) {
+                            warn!("Failed to save chain_meta.json: {}", e);
+                        }
+                    }
+                    block
+                }
+            };
             if first_block > 0 {
-                println!(
-                    "  Local RPC: {} (Archived from block: {})",
+                info!(
+                    "Local RPC: {} (Archived from block: {})",
                     local_url, first_block
                 );
             } else {
-                println!("  Local RPC: {} (Full history detected)", local_url);
+                info!("Local RPC: {} (Full history detected)", local_url);
             }
             Some(first_block)
         } else {
-            println!("  Warning: Failed to connect to local RPC: {}", local_url);
+            warn!("Failed to connect to local RPC: {}", local_url);
             None
         }
     } else {
         None
     };
 
+    if let Some(Command::Verify { samples, tolerance }) = args.command {
+        return run_verify(
+            &args,
+            &accounts,
+            &account_windows,
+            &source_name,
+            &chain,
+            samples,
+            tolerance,
+        )
+        .await;
+    }
+
+    if let Some(date) = args.debug_account_dump {
+        return run_account_dump(&args, &accounts, &mut chain, date).await;
+    }
+
+    if args.only_latest {
+        return run_only_latest(&args, &accounts, &source_name, &mut chain).await;
+    }
+
+    if !args.blocks.is_empty() {
+        return run_blocks(&args, &accounts, &source_name, &mut chain).await;
+    }
+
+    if args.intraday {
+        return run_intraday(&args, &accounts, &source_name, &mut chain).await;
+    }
+
+    if let Some(tx_hash) = &args.extrinsic {
+        return run_extrinsic(&args, &accounts, &mut chain, tx_hash).await;
+    }
+
+    let set = AccountSet {
+        accounts,
+        source_name,
+        account_windows,
+        account_groups,
+        account_colors,
+        account_notes,
+    };
+    if args.watch {
+        return run_watch(&args, &set, &mut chain, &info, local_first_block).await;
+    }
+
+    run_history(&args, &set, &mut chain, &info, local_first_block).await
+}
+
+/// Update the daily history, caches, CSVs and graphs for the date range: the
+/// default pipeline, also run by `--watch` whenever a new date starts
+async fn run_history(
+    args: &Args,
+    set: &AccountSet,
+    chain: &mut ChainConnector,
+    info: &ChainInfo,
+    local_first_block: Option<u64>,
+) -> Result<()> {
+    let AccountSet {
+        accounts,
+        source_name,
+        account_windows,
+        account_groups,
+        account_colors,
+        account_notes,
+    } = set;
+    // Raw-unit divisor from the chain's token decimals, for every tracker below
+    let divisor = chain.divisor();
+    let ss58_format = chain.ss58_format();
     let local_rpc_url = args.local_rpc.clone();
     let latest_block = chain.get_latest_block_number().await.unwrap_or(0);
     let rpc_methods = chain.rpc().ok().cloned();
 
     // 3. Find blocks for dates
-    println!("\n[3/6] Finding blocks for dates...");
-    let start_date = args
-        .start
-        .as_ref()
-        .map(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d"))
-        .transpose()?
-        .unwrap_or_else(|| NaiveDate::parse_from_str(GENESIS_DATE, "%Y-%m-%d").unwrap());
-
-    let end_date = args
-        .end
-        .as_ref()
-        .map(|s| NaiveDate::parse_from_str(s, "%Y-%m-%d"))
-        .transpose()?
-        .unwrap_or_else(|| Utc::now().date_naive());
-
-    let mut dates: Vec<NaiveDate> = Vec::new();
-    let mut current = start_date;
-    while current <= end_date {
-        dates.push(current);
-        current = current.checked_add_days(Days::new(1)).unwrap();
+    info!("[3/6] Finding blocks for dates...");
+    let (start_date, end_date) = args.date_bounds()?;
+
+    let output_dir = args.output_dir();
+    let mut failed = FailedDates::load(args.failed_dates_file(&output_dir, source_name))?;
+
+    let dates: Vec<NaiveDate> = if args.retry_failed {
+        if failed.is_empty() {
+            info!("No failed dates to retry in {:?}", failed.path);
+            return Ok(());
+        }
+        let dates = failed.dates();
+        if dates.is_empty() {
+            anyhow::bail!("No valid dates to retry in {:?}", failed.path);
+        }
+        info!(
+            "Retrying {} failed dates ({} balances, {} rewards)",
+            dates.len(),
+            failed.balances.len(),
+            failed.rewards.len()
+        );
+        dates
+    } else {
+        let dates = ctc_balance::date_range(start_date, end_date)?;
+
+        info!(
+            "Date range: {} ~ {} ({} days)",
+            start_date,
+            end_date,
+            dates.len()
+        );
+        dates
+    };
+    let (start_date, end_date) = (dates[0], dates[dates.len() - 1]);
+
+    // Rewards for ranges at or past the local node's first block are read from it
+    let mut rpc_endpoints = vec![RpcEndpoint {
+        url: NODE_URL.to_string(),
+        from_block: None,
+        to_block: None,
+        used_for: vec!["blocks", "balances", "rewards"],
+    }];
+    if let (Some(url), Some(first)) = (&local_rpc_url, local_first_block) {
+        rpc_endpoints[0].used_for.pop();
+        rpc_endpoints.push(RpcEndpoint {
+            url: NODE_URL.to_string(),
+            from_block: None,
+            to_block: first.checked_sub(1),
+            used_for: vec!["rewards"],
+        });
+        rpc_endpoints.push(RpcEndpoint {
+            url: ctc_balance::redact_url_userinfo(url),
+            from_block: Some(first),
+            to_block: None,
+            used_for: vec!["rewards"],
+        });
     }
+    let mut manifest = RunManifest {
+        tool_version: env!("CARGO_PKG_VERSION"),
+        args: ctc_balance::redact_args(std::env::args()),
+        chain: info.chain.clone(),
+        spec_version: info.version.clone(),
+        genesis_hash: info.genesis_hash.clone(),
+        start_date: start_date.to_string(),
+        end_date: end_date.to_string(),
+        days: dates.len(),
+        rpc_endpoints,
+        ..Default::default()
+    };
 
-    println!(
-        "  Date range: {} ~ {} ({} days)",
-        start_date,
-        end_date,
-        dates.len()
-    );
+    let mut store = CacheStore::open(args, &output_dir)?;
+    let snapshot = args.snapshot();
+    // Non-default snapshot points get their own output and cache files
+    let tag = snapshot.tag();
+    let mut stored_cache: BlockCache = store.load_blocks().unwrap_or_default();
+    let mut cache = snapshot_view(&stored_cache, &snapshot);
 
-    let output_dir = PathBuf::from("output");
-    let cache_file = output_dir.join("block_cache.json");
-    let mut cache: BlockCache = load_block_cache(&cache_file).unwrap_or_default();
+    if args.reward_coverage {
+        return run_reward_coverage(args, &store, &tag, accounts, &dates);
+    }
 
-    let today_str = Utc::now().date_naive().format("%Y-%m-%d").to_string();
-    let yesterday_str = Utc::now()
-        .date_naive()
+    let today_str = snapshot.today().format("%Y-%m-%d").to_string();
+    let yesterday_str = snapshot
+        .today()
         .checked_sub_days(chrono::Days::new(1))
         .unwrap()
         .format("%Y-%m-%d")
         .to_string();
     let last_cached_date = cache.keys().max().cloned();
 
-    let dates_to_find: Vec<NaiveDate> = dates
+    // The reward phase ends each day at the next date's block, so those are needed
+    // too; the day after the last date is found once it has passed
+    let mut block_dates: Vec<NaiveDate> = dates.clone();
+    if !args.no_rewards {
+        let now = Utc::now().timestamp() as u64;
+        for next in dates
+            .iter()
+            .filter_map(|d| d.checked_add_days(Days::new(1)))
+        {
+            if !block_dates.contains(&next) && snapshot.timestamp(next) <= now {
+                block_dates.push(next);
+            }
+        }
+    }
+
+    let dates_to_find: Vec<NaiveDate> = block_dates
         .iter()
         .filter(|d| {
             let d_str = d.format("%Y-%m-%d").to_string();
@@ -193,33 +1191,64 @@ async fn main() -> Result<()> {
         })
         .cloned()
         .collect();
+    manifest.blocks.cached = block_dates.len() - dates_to_find.len();
 
     if !dates_to_find.is_empty() {
-        println!(
-            "  Finding blocks for {} uncached dates...",
+        info!(
+            "Finding blocks for {} uncached dates...",
             dates_to_find.len()
         );
+        let started = Instant::now();
         use futures::stream::{self, StreamExt};
         let client = chain.client().ok().cloned();
+        let lookups = chain.block_lookups();
+
+        // Known (timestamp -> block) points used to seed each search; filled from
+        // the cache and from results as they arrive, since dates run roughly in order
+        let anchors: Arc<Mutex<BTreeMap<u64, u64>>> = Arc::new(Mutex::new(BTreeMap::new()));
+        if !args.no_cache {
+            let mut seeded = anchors.lock().unwrap();
+            for (date_str, info) in &cache {
+                if let Ok(d) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+                    seeded.insert(snapshot.timestamp(d), info.block);
+                }
+            }
+        }
 
         let mut stream = stream::iter(dates_to_find.iter())
             .map(|&d| {
                 let client = client.clone();
                 let rpc = rpc_methods.clone();
+                let lookups = lookups.clone();
+                let head = args.head;
+                let max_block_error = args.max_block_error_secs;
+                let genesis_block = args.genesis_block;
                 let date_str = d.format("%Y-%m-%d").to_string();
-                let timestamp = d.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64;
+                let timestamp = snapshot.timestamp(d);
+                let anchors = anchors.clone();
+                let span = debug_span!("find_block", date = %date_str);
                 async move {
+                    let anchor = nearest_anchor(&anchors.lock().unwrap(), timestamp);
                     // Create a temporary connector that reuses the client and rpc
                     let mut temp_chain = ChainConnector::new(Some(NODE_URL));
+                    temp_chain.set_head_mode(head);
+                    temp_chain.set_max_block_error(max_block_error);
+                    if let Some(block) = genesis_block {
+                        temp_chain.set_genesis_block(block);
+                    }
+                    temp_chain.set_block_lookups(lookups);
                     if let Some(c) = client {
                         temp_chain.set_client(c);
                     }
                     if let Some(r) = rpc {
                         temp_chain.set_rpc(r);
                     }
-                    let res = temp_chain.find_block_at_timestamp(timestamp, 60).await;
+                    let started = Instant::now();
+                    let res = temp_chain.find_block_near(timestamp, 60, anchor).await;
+                    debug!("Searched in {:?}", started.elapsed());
                     (date_str, res)
                 }
+                .instrument(span)
             })
             .buffer_unordered(CONCURRENCY_DATES);
 
@@ -228,46 +1257,137 @@ async fn main() -> Result<()> {
         while let Some((date_str, res)) = stream.next().await {
             match res {
                 Ok(block_info) => {
+                    if let Ok(d) = NaiveDate::parse_from_str(&date_str, "%Y-%m-%d") {
+                        anchors
+                            .lock()
+                            .unwrap()
+                            .insert(snapshot.timestamp(d), block_info.block);
+                    }
                     cache.insert(date_str, block_info);
                     found += 1;
                 }
                 Err(e) => {
-                    eprintln!("    Warning: Failed to find block for {}: {}", date_str, e);
+                    warn!("Failed to find block for {}: {}", date_str, e);
+                    manifest.blocks.failed.push(date_str);
                 }
             }
             count += 1;
             if count % 10 == 0 || count == dates_to_find.len() {
-                println!(
-                    "    {}/{} dates processed ({} blocks found)...",
+                info!(
+                    "{}/{} dates processed ({} blocks found)...",
                     count,
                     dates_to_find.len(),
                     found
                 );
-                save_block_cache(&cache_file, &cache)?;
+                merge_snapshot_view(&mut stored_cache, &cache, &snapshot);
+                store.save_blocks(&stored_cache)?;
             }
         }
-        save_block_cache(&cache_file, &cache)?;
+        merge_snapshot_view(&mut stored_cache, &cache, &snapshot);
+        store.save_blocks(&stored_cache)?;
+        manifest.blocks.fetched = found;
+        manifest.blocks.elapsed_secs = started.elapsed().as_secs_f64();
+    }
+    if args.strict && !manifest.blocks.failed.is_empty() {
+        let missing = manifest.blocks.failed.join(", ");
+        finish_run(args, &mut manifest, &mut failed, &dates, &output_dir)?;
+        anyhow::bail!("--strict: no block found for {}", missing);
     }
 
+    report_runtime_upgrades(&cache, &dates);
+
     // 4. Fetch balances
-    println!("\n[4/6] Fetching balances...");
-    let output_file = args
-        .output
-        .clone()
-        .unwrap_or_else(|| output_dir.join(format!("{}_history.csv", source_name)));
+    let output_file = args.history_file(&output_dir, source_name);
     let mut existing_data = load_existing_csv(&output_file).unwrap_or_default();
-    let account_names: Vec<String> = {
-        let mut names: Vec<_> = accounts.keys().cloned().collect();
-        names.sort();
-        names
-    };
+    // Each account's latest value in the previous output, for --alert-below/--alert-above
+    let previous_latest: HashMap<String, f64> = existing_data
+        .iter()
+        .filter_map(|(name, history)| {
+            history
+                .iter()
+                .max_by(|a, b| a.0.cmp(b.0))
+                .map(|(_, &v)| (name.clone(), v))
+        })
+        .collect();
+    if args.rewards_only {
+        info!("[4/6] Skipping balances (--rewards-only)...");
+        if existing_data.is_empty() {
+            warn!(
+                "No existing balances in {:?}; balance columns will be empty",
+                output_file
+            );
+        }
+    } else {
+        info!("[4/6] Fetching balances...");
+    }
+    let balance_cache_stem = format!("balance_cache{}{}", tag, args.asset_tag());
+    let mut balance_cache: BalanceCache =
+        store.load_balances(&balance_cache_stem).unwrap_or_default();
+    // The cache is flushed during the fetch loop, so balances from an interrupted
+    // run are picked up here even though they never reached the CSV
+    if !args.no_cache {
+        for (name, history) in &balance_cache {
+            let data = existing_data.entry(name.clone()).or_default();
+            for (date, balance) in history {
+                data.entry(date.clone())
+                    .or_insert_with(|| balance.metric(args.balance_metric));
+            }
+        }
+    }
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+    for name in &args.exclude_from_total {
+        if !account_names.contains(name) {
+            warn!("--exclude-from-total: no account named {:?}", name);
+        }
+    }
+
+    // Reconcile the account set with the columns of the existing CSV
+    let csv_accounts = load_csv_accounts(&output_file).unwrap_or_default();
+    if !csv_accounts.is_empty() {
+        let added: Vec<&String> = account_names
+            .iter()
+            .filter(|name| !csv_accounts.contains(name))
+            .collect();
+        let removed: Vec<&String> = csv_accounts
+            .iter()
+            .filter(|name| !account_names.contains(name))
+            .collect();
+        if !added.is_empty() {
+            info!(
+                "New account columns (no history in {:?} yet): {:?}",
+                output_file, added
+            );
+        }
+        if !removed.is_empty() {
+            warn!(
+                "Accounts no longer in the account list will be dropped from {:?}: {:?}",
+                output_file, removed
+            );
+        }
+    }
 
     let last_existing_date = existing_data.values().flat_map(|h| h.keys().cloned()).max();
 
+    // With --max-inflight, one pool bounds the storage reads of every date in
+    // flight; it is taken per read, never per date, so a date can't hold a slot
+    // its own accounts are waiting for. Enough dates run to keep the pool busy.
+    let inflight = args
+        .max_inflight
+        .map(|n| Arc::new(tokio::sync::Semaphore::new(n.max(1))));
+    let date_concurrency = args.max_inflight.map_or(CONCURRENCY_BALANCES, |n| {
+        n.div_ceil(CONCURRENCY_STORAGE).max(1)
+    });
+
     let dates_to_fetch: Vec<String> = dates
         .iter()
         .map(|d| d.format("%Y-%m-%d").to_string())
         .filter(|date_str| {
+            if args.rewards_only {
+                return false;
+            }
+            if args.retry_failed {
+                return failed.balances.contains(date_str);
+            }
             if args.no_cache
                 || date_str == &today_str
                 || date_str == &yesterday_str
@@ -276,26 +1396,35 @@ async fn main() -> Result<()> {
                 return true;
             }
 
+            let active_names: Vec<&String> = account_names
+                .iter()
+                .filter(|name| is_active_on(account_windows, name, date_str))
+                .collect();
+            if active_names.is_empty() {
+                return false;
+            }
+
             // 1. Always fetch if any account is missing data for this date
-            let any_missing = account_names.iter().any(|name| {
-                existing_data
-                    .get(name)
-                    .and_then(|h| h.get(date_str))
-                    .is_none()
-            });
-            if any_missing {
+            if balance_missing(&existing_data, &active_names, date_str) {
                 return true;
             }
 
             // 2. If refetch_zero is enabled, fetch if ALL accounts have 0.0 balance
-            // This avoids re-fetching dates where some accounts legitimately have 0.0
+            // This avoids re-fetching dates where some accounts legitimately have 0.0.
+            // A zero whose cached full balance is reserved/frozen (fully bonded) is
+            // a real balance, not a failed fetch, so it never triggers a re-fetch.
             if args.refetch_zero {
-                let all_zero = account_names.iter().all(|name| {
-                    existing_data
+                let all_zero = active_names.iter().all(|&name| {
+                    let reserved_only = balance_cache
                         .get(name)
                         .and_then(|h| h.get(date_str))
-                        .map(|&v| v == 0.0)
-                        .unwrap_or(true)
+                        .is_some_and(|b| b.is_reserved_only());
+                    !reserved_only
+                        && existing_data
+                            .get(name)
+                            .and_then(|h| h.get(date_str))
+                            .map(|&v| v == 0.0)
+                            .unwrap_or(true)
                 });
                 if all_zero {
                     return true;
@@ -305,78 +1434,162 @@ async fn main() -> Result<()> {
             false
         })
         .collect();
+    manifest.balances.cached = dates.len() - dates_to_fetch.len();
 
     if !dates_to_fetch.is_empty() {
-        println!("  Fetching {} new dates...", dates_to_fetch.len());
+        info!("Fetching {} new dates...", dates_to_fetch.len());
+        let started = Instant::now();
         use futures::stream::{self, StreamExt};
         let client = chain.client().ok().cloned();
         let mut stream = stream::iter(dates_to_fetch.iter())
             .map(|date_str| {
                 let client = client.clone();
+                let rpc = rpc_methods.clone();
+                let inflight = inflight.clone();
                 let date_str = date_str.clone();
-                let accounts = accounts.clone();
+                let accounts = active_accounts(accounts, account_windows, &date_str);
                 let block_info = cache.get(&date_str).cloned();
+                let asset = args.asset;
+                let batch = args.batch;
+                let span = debug_span!("balances", date = %date_str);
                 async move {
                     if let Some(block_info) = block_info {
                         let mut tracker = BalanceTracker::new(NODE_URL);
                         if let Some(c) = client {
                             tracker.set_client((*c).clone());
                         }
+                        if let Some(r) = rpc {
+                            tracker.set_rpc((*r).clone());
+                        }
+                        tracker.set_batch(batch);
+                        tracker.set_asset(asset);
+                        tracker.set_divisor(divisor);
+                        if let Some(semaphore) = inflight {
+                            tracker.set_inflight_limit(semaphore);
+                        }
+                        let started = Instant::now();
                         let res = tracker.get_all_balances(&accounts, &block_info.hash).await;
+                        debug!("Fetched in {:?}", started.elapsed());
                         (date_str, Some(res))
                     } else {
                         (date_str, None)
                     }
                 }
+                .instrument(span)
             })
-            .buffer_unordered(CONCURRENCY_BALANCES);
+            .buffer_unordered(date_concurrency);
 
         let mut count = 0;
         let mut failed_dates = Vec::new();
+        let mut balance_dirty = DirtyRows::new();
         while let Some((date_str, res_opt)) = stream.next().await {
             match res_opt {
-                Some(Ok(balances)) => {
-                    for (name, balance) in balances {
-                        existing_data
-                            .entry(name)
-                            .or_insert_with(HashMap::new)
-                            .insert(date_str.clone(), balance.free);
+                Some(Ok(partial)) => {
+                    // Only the failed accounts' cells are missing; the date is
+                    // still listed for --retry-failed
+                    if !partial.failed.is_empty() {
+                        let names: Vec<&String> = partial.failed.keys().collect();
+                        warn!(
+                            "Failed to fetch balances for {} on {}: {:?}",
+                            names.len(),
+                            date_str,
+                            names
+                        );
+                        for (name, e) in &partial.failed {
+                            debug!("{} on {}: {}", name, date_str, e);
+                        }
+                        failed_dates.push(date_str.clone());
                     }
+                    balance_dirty.extend(
+                        partial
+                            .balances
+                            .keys()
+                            .map(|name| (name.clone(), date_str.clone())),
+                    );
+                    partial.merge_into(
+                        &date_str,
+                        args.balance_metric,
+                        &mut existing_data,
+                        &mut balance_cache,
+                    );
                 }
                 Some(Err(e)) => {
-                    println!(
-                        "    Warning: Failed to fetch balances for {}: {}",
-                        date_str, e
-                    );
+                    warn!("Failed to fetch balances for {}: {}", date_str, e);
                     failed_dates.push(date_str);
                 }
                 None => {
-                    println!("    Warning: Missing block info for {}", date_str);
+                    warn!("Missing block info for {}", date_str);
                     failed_dates.push(date_str);
                 }
             }
             count += 1;
             if count % 10 == 0 || count == dates_to_fetch.len() {
-                println!("  [{}/{}] completed", count, dates_to_fetch.len());
+                info!("[{}/{}] completed", count, dates_to_fetch.len());
+                store
+                    .save_balances(&balance_cache_stem, &balance_cache, &mut balance_dirty)
+                    .ok();
             }
         }
+        store
+            .save_balances(&balance_cache_stem, &balance_cache, &mut balance_dirty)
+            .ok();
 
         if !failed_dates.is_empty() {
-            println!(
-                "\n  Caution: {} dates failed to fetch. These will appear as 0.0 in the output.",
+            warn!(
+                "{} dates failed to fetch. Their balances will be empty in the output.",
                 failed_dates.len()
             );
-            println!("  Try running again to retry these dates.");
+            warn!("Run with --retry-failed to retry these dates.");
+        }
+        manifest.balances.fetched = dates_to_fetch.len() - failed_dates.len();
+        manifest.balances.failed = failed_dates;
+        manifest.balances.elapsed_secs = started.elapsed().as_secs_f64();
+    }
+    if args.strict && !manifest.balances.failed.is_empty() {
+        let missing = manifest.balances.failed.join(", ");
+        finish_run(args, &mut manifest, &mut failed, &dates, &output_dir)?;
+        anyhow::bail!("--strict: balances failed for {}", missing);
+    }
+
+    if args.balance_metric == BalanceMetric::Free {
+        // Fully bonded accounts read as zero free; say so rather than look like lost funds
+        let last_date = dates[dates.len() - 1].format("%Y-%m-%d").to_string();
+        for name in &account_names {
+            if let Some(b) = balance_cache
+                .get(name)
+                .and_then(|h| h.get(&last_date))
+                .filter(|b| b.is_reserved_only())
+            {
+                warn!(
+                    "{}: free balance is 0 but {:.1} reserved / {:.1} frozen on {} (see --balance-metric owned)",
+                    name, b.reserved, b.frozen, last_date
+                );
+            }
         }
     }
 
     // 5. Fetch staking rewards - BLOCK SCANNING
     let mut full_reward_history: RewardCache = HashMap::new();
+    let mut era_points: BTreeMap<u32, EraPoints> = BTreeMap::new();
     if !args.no_rewards {
-        let reward_cache_file = output_dir.join("reward_cache.json");
-        let mut reward_cache = load_reward_cache(&reward_cache_file).unwrap_or_default();
+        let reward_cache_stem = reward_cache_stem(args.reward_basis, &tag);
+        let mut reward_cache = store.load_rewards(&reward_cache_stem).unwrap_or_default();
+        // Presence is checked by account name, so a name now pointing at another
+        // address must not reuse the rewards scanned for the old one
+        let reward_accounts_path = store.path(&format!("{}_accounts", reward_cache_stem));
+        let mut reward_accounts = load_reward_accounts(&reward_accounts_path).unwrap_or_default();
+        let moved = drop_moved_reward_accounts(&mut reward_cache, &reward_accounts, accounts);
+        if !moved.is_empty() {
+            warn!(
+                "Address changed for {:?} since their rewards were cached; re-scanning them",
+                moved
+            );
+        }
+        for name in &moved {
+            store.delete_rewards(&reward_cache_stem, name)?;
+        }
 
-        println!("\n[5/6] Fetching staking rewards (block scanning)...");
+        info!("[5/6] Fetching staking rewards (block scanning)...");
         let date_strings: Vec<String> = dates
             .iter()
             .map(|d| d.format("%Y-%m-%d").to_string())
@@ -384,11 +1597,28 @@ async fn main() -> Result<()> {
 
         let last_reward_date = reward_cache.values().flat_map(|h| h.keys().cloned()).max();
 
+        let reward_span_blocks = match args.reward_span_blocks {
+            Some(span) => span,
+            None => {
+                let block_time = chain
+                    .average_block_time()
+                    .await
+                    .unwrap_or(BLOCK_TIME_SECONDS as f64);
+                (86400.0 / block_time).ceil() as u64
+            }
+        };
+        debug!("Reward span fallback: {} blocks/day", reward_span_blocks);
+
         let mut missing_date_block_ranges = Vec::new();
 
-        for (i, date_str) in date_strings.iter().enumerate() {
+        for date_str in &date_strings {
+            let date_accounts = active_accounts(accounts, account_windows, date_str);
+            if date_accounts.is_empty() {
+                continue;
+            }
+
             let mut all_present = true;
-            for name in accounts.keys() {
+            for name in date_accounts.keys() {
                 let present = reward_cache
                     .get(name)
                     .map(|h| h.contains_key(date_str))
@@ -400,18 +1630,25 @@ async fn main() -> Result<()> {
                 }
             }
 
-            if !all_present
-                || args.no_cache
-                || date_str == &today_str
-                || date_str == &yesterday_str
-                || Some(date_str) == last_reward_date.as_ref()
-            {
+            let fetch = if args.retry_failed {
+                failed.rewards.contains(date_str)
+            } else {
+                !all_present
+                    || args.no_cache
+                    || date_str == &today_str
+                    || date_str == &yesterday_str
+                    || Some(date_str) == last_reward_date.as_ref()
+            };
+            if fetch {
                 if let Some(start_info) = cache.get(date_str) {
-                    let next_block = date_strings
-                        .get(i + 1)
-                        .and_then(|next_date| cache.get(next_date))
+                    // The day ends at the next calendar date's block, which may be
+                    // outside this run's dates (e.g. with --retry-failed)
+                    let next_block = NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
+                        .ok()
+                        .and_then(|d| d.checked_add_days(Days::new(1)))
+                        .and_then(|next| cache.get(&next.format("%Y-%m-%d").to_string()))
                         .map(|b| b.block)
-                        .unwrap_or(start_info.block + 5760);
+                        .unwrap_or(start_info.block + reward_span_blocks);
 
                     // Cap end_block to current latest block to prevent scanning future blocks
                     let end_block = std::cmp::min(next_block, latest_block);
@@ -427,26 +1664,36 @@ async fn main() -> Result<()> {
             }
         }
 
+        manifest.rewards.cached = dates.len() - missing_date_block_ranges.len();
         if !missing_date_block_ranges.is_empty() {
-            print!(
-                "  Fetching rewards for {} uncached dates",
-                missing_date_block_ranges.len()
-            );
             if missing_date_block_ranges.len() <= 5 {
                 let dates_list: Vec<_> = missing_date_block_ranges
                     .iter()
                     .map(|(d, _, _)| d.as_str())
                     .collect();
-                print!(" ({})", dates_list.join(", "));
+                info!(
+                    "Fetching rewards for {} uncached dates ({})...",
+                    missing_date_block_ranges.len(),
+                    dates_list.join(", ")
+                );
+            } else {
+                info!(
+                    "Fetching rewards for {} uncached dates...",
+                    missing_date_block_ranges.len()
+                );
             }
-            println!("...");
 
+            let started = Instant::now();
             use futures::stream::{self, StreamExt};
             let local_first = local_first_block;
             let local_url = local_rpc_url.clone();
 
             let client = chain.client().ok().cloned();
             let rpc = chain.rpc().ok().cloned();
+            let known_specs: Vec<(u64, u32)> = cache
+                .values()
+                .filter_map(|b| b.spec_version.map(|v| (b.block, v)))
+                .collect();
             let mut stream = stream::iter(missing_date_block_ranges.iter())
                 .map(|(date_str, start_block, end_block)| {
                     let rpc_url = match (&local_url, local_first) {
@@ -454,6 +1701,14 @@ async fn main() -> Result<()> {
                         _ => NODE_URL.to_string(),
                     };
                     let mut tracker = RewardTracker::new(&rpc_url);
+                    tracker.set_divisor(divisor);
+                    tracker.set_ss58_format(ss58_format);
+                    tracker.set_finality_margin(args.finality_margin);
+                    tracker.set_record_era_points(args.export_era_points);
+                    tracker.set_spec_versions(known_specs.iter().copied());
+                    tracker.set_max_scan_blocks(
+                        Some(args.max_blocks_scan).filter(|&max| max > 0),
+                    );
                     if rpc_url == NODE_URL {
                         if let Some(ref c) = client {
                             tracker.set_client((**c).clone());
@@ -463,58 +1718,182 @@ async fn main() -> Result<()> {
                         }
                     }
                     let date_str = date_str.clone();
+                    let basis = args.reward_basis;
                     let start = *start_block;
                     let end = *end_block;
-                    let accounts = accounts.clone();
+                    let accounts = active_accounts(accounts, account_windows, &date_str);
+                    let span = debug_span!("rewards", date = %date_str);
                     async move {
-                        if tracker.connect().await.is_ok() {
+                        let rewards = if tracker.connect().await.is_ok() {
                             match tracker
-                                .get_rewards_via_eras(&accounts, start, end)
+                                .get_rewards(&accounts, start, end, basis)
                                 .await
                             {
-                                Ok(rewards) => (date_str, Some(rewards)),
-                                Err(e) => {
-                                    println!("    Warning: Era-based query failed for {}: {}. Falling back to scanning...", date_str, e);
+                                Ok(rewards) => Some(rewards),
+                                Err(e) if basis == RewardBasis::Earned => {
+                                    warn!("Era-based query failed for {}: {}. Falling back to scanning...", date_str, e);
                                     match tracker.get_all_rewards_in_range(&accounts, start, end).await {
-                                        Ok(r) => (date_str, Some(r)),
-                                        Err(_) => (date_str, None),
+                                        // Paid amounts stand in for earned when era data is unavailable
+                                        Ok(mut r) => {
+                                            for reward in r.values_mut() {
+                                                reward.earned = reward.claimed;
+                                            }
+                                            Some(r)
+                                        }
+                                        Err(_) => None,
                                     }
                                 },
+                                Err(e) => {
+                                    warn!("Reward scan failed for {}: {}", date_str, e);
+                                    None
+                                }
                             }
                         } else {
-                            (date_str, None)
-                        }
+                            None
+                        };
+                        (date_str, rewards, tracker.take_era_points())
                     }
+                    .instrument(span)
                 })
                 .buffer_unordered(CONCURRENCY_REWARDS);
 
             let mut count = 0;
-            while let Some((date_str, rewards_opt)) = stream.next().await {
+            let mut payee_notes: HashMap<String, String> = HashMap::new();
+            let mut reward_dirty = DirtyRows::new();
+            while let Some((date_str, rewards_opt, points)) = stream.next().await {
+                // Neighbouring dates share eras; keyed by era, each is kept once
+                era_points.extend(points.into_iter().map(|p| (p.era, p)));
                 if let Some(rewards) = rewards_opt {
                     for (name, reward) in rewards {
-                        reward_cache
-                            .entry(name)
-                            .or_insert_with(HashMap::new)
-                            .insert(date_str.clone(), reward.claimed);
+                        let amount = reward.amount(args.reward_basis);
+                        if let Some(address) = accounts.get(&name) {
+                            reward_accounts.insert(name.clone(), address.clone());
+                        }
+                        if let Some(note) = reward.payee {
+                            payee_notes.insert(name.clone(), note);
+                        }
+                        // The breakdown is of earned rewards, so claimed totals go without it
+                        let by_validator = match args.reward_basis {
+                            RewardBasis::Earned => reward.by_validator,
+                            RewardBasis::Claimed => Default::default(),
+                        };
+                        reward_dirty.insert((name.clone(), date_str.clone()));
+                        reward_cache.entry(name).or_default().insert(
+                            date_str.clone(),
+                            CachedReward {
+                                total: amount,
+                                by_validator,
+                            },
+                        );
                     }
+                    manifest.rewards.fetched += 1;
+                } else {
+                    manifest.rewards.failed.push(date_str.clone());
                 }
                 count += 1;
-                println!(
-                    "    [{}/{}] dates processed",
+                info!(
+                    "[{}/{}] dates processed",
                     count,
                     missing_date_block_ranges.len()
                 );
-                save_reward_cache(&reward_cache_file, &reward_cache).ok();
+                store
+                    .save_rewards(&reward_cache_stem, &reward_cache, &mut reward_dirty)
+                    .ok();
+                save_reward_accounts(&reward_accounts_path, &reward_accounts).ok();
+            }
+            store
+                .save_rewards(&reward_cache_stem, &reward_cache, &mut reward_dirty)
+                .ok();
+            save_reward_accounts(&reward_accounts_path, &reward_accounts).ok();
+            manifest.rewards.elapsed_secs = started.elapsed().as_secs_f64();
+
+            let mut notes: Vec<_> = payee_notes.into_iter().collect();
+            notes.sort();
+            for (name, note) in notes {
+                info!("Rewards for {}: {}", name, note);
             }
-            save_reward_cache(&reward_cache_file, &reward_cache).ok();
         } else {
-            println!("  All rewards found in cache!");
+            info!("All rewards found in cache!");
         }
         full_reward_history = reward_cache;
     }
+    if args.strict && !manifest.rewards.failed.is_empty() {
+        let missing = manifest.rewards.failed.join(", ");
+        finish_run(args, &mut manifest, &mut failed, &dates, &output_dir)?;
+        anyhow::bail!("--strict: rewards failed for {}", missing);
+    }
+
+    // Active stake per date, needed for APR
+    let include_apr = args.apr && !args.no_rewards;
+    let stake_cache_file = store.path(&format!("stake_cache{}", tag));
+    let mut stake_cache: StakeCache = if args.no_cache || !include_apr {
+        HashMap::new()
+    } else {
+        load_stake_cache(&stake_cache_file).unwrap_or_default()
+    };
+    if include_apr {
+        let dates_to_stake: Vec<String> = dates
+            .iter()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .filter(|date_str| {
+                cache.contains_key(date_str)
+                    && active_accounts(accounts, account_windows, date_str)
+                        .keys()
+                        .any(|name| {
+                            stake_cache
+                                .get(name)
+                                .and_then(|h| h.get(date_str))
+                                .is_none()
+                        })
+            })
+            .collect();
+
+        if !dates_to_stake.is_empty() {
+            info!(
+                "Fetching active stake for {} dates...",
+                dates_to_stake.len()
+            );
+            use futures::stream::{self, StreamExt};
+            let client = chain.client().ok().cloned();
+            let mut stream = stream::iter(dates_to_stake.iter())
+                .map(|date_str| {
+                    let mut tracker = BalanceTracker::new(NODE_URL);
+                    if let Some(c) = &client {
+                        tracker.set_client((**c).clone());
+                    }
+                    tracker.set_divisor(divisor);
+                    if let Some(semaphore) = &inflight {
+                        tracker.set_inflight_limit(semaphore.clone());
+                    }
+                    let date_str = date_str.clone();
+                    let accounts = active_accounts(accounts, account_windows, &date_str);
+                    let hash = cache[&date_str].hash.clone();
+                    async move {
+                        let res = tracker.get_all_active_stakes(&accounts, &hash).await;
+                        (date_str, res)
+                    }
+                })
+                .buffer_unordered(date_concurrency);
+
+            while let Some((date_str, res)) = stream.next().await {
+                match res {
+                    Ok(stakes) => {
+                        for (name, stake) in stakes {
+                            stake_cache
+                                .entry(name)
+                                .or_default()
+                                .insert(date_str.clone(), stake);
+                        }
+                    }
+                    Err(e) => warn!("Failed to fetch active stake for {}: {}", date_str, e),
+                }
+            }
+            save_stake_cache(&stake_cache_file, &stake_cache).ok();
+        }
+    }
 
     // 6. Save results
-    println!("\n[6/6] Saving results...");
+    info!("[6/6] Saving results...");
     let all_dates: Vec<String> = {
         let mut dates_set: std::collections::HashSet<String> = dates
             .iter()
@@ -525,153 +1904,1658 @@ async fn main() -> Result<()> {
                 dates_set.insert(date.clone());
             }
         }
+        // Dates with a cached reward but no balance row (e.g. --rewards-only over a
+        // short CSV) still get a row; their balance cells stay empty until fetched
+        for (name, history) in &full_reward_history {
+            if !accounts.contains_key(name) {
+                continue;
+            }
+            for date in history.keys() {
+                if is_active_on(account_windows, name, date) {
+                    dates_set.insert(date.clone());
+                }
+            }
+        }
         let mut dv: Vec<String> = dates_set.into_iter().collect();
         dv.sort();
         dv
     };
 
-    let mut reward_cumulative = 0.0;
-    let mut reward_history_for_avg: Vec<f64> = Vec::new();
-    let mut daily_total_rewards: HashMap<String, f64> = HashMap::new();
-
-    let mut entries: Vec<HistoryEntry> = all_dates
+    let account_rewards = reward_totals(&full_reward_history);
+    let mut entries = build_history_entries(
+        &all_dates,
+        &account_names,
+        account_windows,
+        account_groups.as_ref(),
+        &existing_data,
+        &account_rewards,
+        &args.exclude_from_total,
+    );
+    let daily_total_rewards: HashMap<String, f64> = entries
         .iter()
-        .map(|date| {
-            let mut balances = HashMap::new();
-            let mut rewards = HashMap::new();
-            let mut total = 0.0;
-            let mut total_reward = 0.0;
-
-            for name in &account_names {
-                let balance = existing_data
-                    .get(name)
-                    .and_then(|h| h.get(date))
-                    .copied()
-                    .unwrap_or(0.0);
-                balances.insert(name.clone(), balance);
-                total += balance;
+        .map(|e| (e.date.clone(), e.total_reward))
+        .collect();
 
-                let reward = full_reward_history
-                    .get(name)
-                    .and_then(|h| h.get(date))
-                    .copied()
-                    .unwrap_or(0.0);
-                rewards.insert(name.clone(), reward);
-                total_reward += reward;
+    calculate_diffs(&mut entries);
+    if args.detailed_totals {
+        calculate_component_totals(&mut entries, &balance_cache, &args.exclude_from_total);
+        let incomplete = entries
+            .iter()
+            .filter(|e| e.component_totals.is_none())
+            .count();
+        if incomplete > 0 {
+            warn!(
+                "{} dates have no cached full balances; their detailed totals are left empty (re-fetch with --no-cache)",
+                incomplete
+            );
+        }
+    }
+    if include_apr {
+        calculate_aprs(&mut entries, &account_names, &stake_cache, args.apr_window);
+    }
+    save_combined_csv(
+        &output_file,
+        &account_names,
+        &entries,
+        !args.no_rewards,
+        args.total_with_rewards,
+        include_apr,
+        args.rounding,
+        args.checksum,
+    )?;
+    if args.totals_csv {
+        let totals_file = ctc_balance::sidecar_file(&output_file, "totals.csv");
+        save_totals_csv(
+            &totals_file,
+            &entries,
+            !args.no_rewards,
+            args.total_with_rewards,
+            include_apr,
+            args.rounding,
+        )?;
+        info!("Saved totals CSV: {:?}", totals_file);
+    }
+    if args.group_csvs {
+        match &account_groups {
+            Some(groups) => {
+                let mut members: BTreeMap<&String, Vec<String>> = BTreeMap::new();
+                for name in &account_names {
+                    if let Some(group) = groups.get(name) {
+                        members.entry(group).or_default().push(name.clone());
+                    }
+                }
+                for (group, names) in members {
+                    // Same accounts as the combined CSV's group_<GROUP>_total column
+                    let mut group_entries = build_history_entries(
+                        &all_dates,
+                        &names,
+                        account_windows,
+                        None,
+                        &existing_data,
+                        &account_rewards,
+                        &[],
+                    );
+                    calculate_diffs(&mut group_entries);
+                    if include_apr {
+                        calculate_aprs(&mut group_entries, &names, &stake_cache, args.apr_window);
+                    }
+                    let group_file = ctc_balance::sidecar_file(
+                        &output_file,
+                        &format!("group_{}.csv", ctc_balance::file_safe(group)),
+                    );
+                    save_combined_csv(
+                        &group_file,
+                        &names,
+                        &group_entries,
+                        !args.no_rewards,
+                        args.total_with_rewards,
+                        include_apr,
+                        args.rounding,
+                        false,
+                    )?;
+                    info!("Saved group CSV: {:?}", group_file);
+                }
             }
+            None => warn!("--group-csvs: no account groups in the accounts file"),
+        }
+    }
+    if args.long_format {
+        let long_file = ctc_balance::sidecar_file(&output_file, "long.csv");
+        save_long_csv(&long_file, &account_names, &entries, !args.no_rewards)?;
+        info!("Saved long format: {:?}", long_file);
+    }
+    if args.html {
+        let report_file = ctc_balance::sidecar_file(&output_file, "report.html");
+        save_html_report(
+            &report_file,
+            &format!("{} balance history", source_name),
+            &account_names,
+            &entries,
+            !args.no_rewards,
+        )?;
+        info!("Saved HTML report: {:?}", report_file);
+    }
 
-            daily_total_rewards.insert(date.clone(), total_reward);
-            reward_cumulative += total_reward;
-            reward_history_for_avg.push(total_reward);
+    if args.export_era_points && !args.no_rewards {
+        let points_file = ctc_balance::sidecar_file(&output_file, "era_points.csv");
+        let eras: Vec<EraPoints> = era_points.into_values().collect();
+        if eras.is_empty() {
+            warn!(
+                "No era points recorded; only dates fetched in this run are read (try --no-cache)"
+            );
+        }
+        save_era_points_csv(&points_file, &eras)?;
+        info!("Saved era points ({} eras): {:?}", eras.len(), points_file);
+    }
 
-            let reward_avg10 = if reward_history_for_avg.len() >= 10 {
-                reward_history_for_avg.iter().rev().take(10).sum::<f64>() / 10.0
-            } else if !reward_history_for_avg.is_empty() {
-                reward_history_for_avg.iter().sum::<f64>() / reward_history_for_avg.len() as f64
-            } else {
-                0.0
-            };
+    if args.include_nonce {
+        let nonce_file = ctc_balance::sidecar_file(&output_file, "nonce.csv");
+        let date_strings: Vec<String> = dates
+            .iter()
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .collect();
+        let unknown = date_strings
+            .iter()
+            .filter(|date| {
+                balance_cache
+                    .values()
+                    .any(|h| h.get(*date).is_some_and(|b| b.nonce.is_none()))
+            })
+            .count();
+        if unknown > 0 {
+            warn!(
+                "{} dates have cached balances without a nonce; re-fetch them with --no-cache",
+                unknown
+            );
+        }
+        save_nonce_csv(&nonce_file, &account_names, &date_strings, &balance_cache)?;
+        info!("Saved nonces: {:?}", nonce_file);
+    }
 
-            HistoryEntry {
-                date: date.clone(),
-                balances,
-                total,
-                diff: 0.0,
-                diff_avg10: 0.0,
-                rewards,
-                total_reward,
-                reward_avg10,
-                total_reward_cumulative: reward_cumulative,
-            }
-        })
-        .collect();
+    if args.reward_breakdown && !args.no_rewards {
+        let breakdown_file = ctc_balance::sidecar_file(&output_file, "reward_breakdown.csv");
+        save_reward_breakdown_csv(&breakdown_file, &full_reward_history)?;
+        info!("Saved reward breakdown: {:?}", breakdown_file);
+    }
 
-    calculate_diffs(&mut entries);
-    save_combined_csv(&output_file, &account_names, &entries, !args.no_rewards)?;
+    if args.reward_stats {
+        let stats = reward_stats(&account_names, &entries);
+        print_reward_stats(&stats, &args.unit());
+        let stats_file = ctc_balance::sidecar_file(&output_file, "reward_stats.csv");
+        save_reward_stats_csv(&stats_file, &stats)?;
+        info!("Saved reward stats: {:?}", stats_file);
+    }
 
-    let individual_dir = output_dir.join("individual");
+    let individual_dir = args.individual_dir(&output_dir, source_name);
     save_individual_csvs(
         &individual_dir,
         &account_names,
         &existing_data,
         &all_dates,
         if !args.no_rewards {
-            Some(&full_reward_history)
+            Some(&account_rewards)
         } else {
             None
         },
+        args.balance_metric,
+        args.rounding,
     )?;
 
+    let plot_options = PlotOptions {
+        stacked: args.stacked,
+        individual_dir: Some(individual_dir.clone()),
+        plot_data: args.plot_data,
+        account_colors: account_colors.clone(),
+        excluded_from_total: args.exclude_from_total.clone(),
+        rotate_x_labels: args.rotate_x_labels,
+        total_with_rewards: args.total_with_rewards,
+    };
     if args.graph && !entries.is_empty() {
-        println!("  Generating graphs...");
+        // Price history (CTC only) is best-effort: the graph is still drawn without it
+        let price_history = if args.price_panel && args.asset.is_none() {
+            let days = all_dates
+                .first()
+                .and_then(|d| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok())
+                .map(|first| (Utc::now().date_naive() - first).num_days() + 1)
+                .unwrap_or(1);
+            match fetch_ctc_price_history(days.max(1) as u32).await {
+                Ok(prices) => Some(prices),
+                Err(e) if args.require_price => {
+                    return Err(e.context("Price history fetch failed (--require-price)"));
+                }
+                Err(e) => {
+                    warn!("Price history fetch failed, skipping price panel: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        info!("Generating graphs...");
         plot_balances(
             &output_file,
             &all_dates,
             &existing_data,
             &account_names,
-            &source_name,
+            source_name,
             if !args.no_rewards {
                 Some(&daily_total_rewards)
             } else {
                 None
             },
             if !args.no_rewards {
-                Some(&full_reward_history)
+                Some(&account_rewards)
             } else {
                 None
             },
+            price_history.as_ref(),
+            &plot_options,
         )?;
+        if let Some(groups_file) = plot_groups(&output_file, &entries, source_name, &plot_options)?
+        {
+            info!("Saved group graph: {:?}", groups_file);
+        }
+    }
+
+    if args.diff_plot {
+        if let Some(diff_file) = plot_diffs(&output_file, &entries, source_name, &plot_options)? {
+            info!("Saved diff graph: {:?}", diff_file);
+        }
     }
 
     if let Some(latest) = entries.last() {
-        print!("\n  Latest ({}): {:.1} CTC", latest.date, latest.total);
+        print!(
+            "\n  Latest ({}): {:.1} {}",
+            latest.date,
+            latest.total,
+            args.unit()
+        );
 
-        // Fetch and display price
-        match fetch_ctc_price().await {
-            Ok(price) => {
+        // Fetch and display price (CTC only)
+        if args.asset.is_none() {
+            if let Some(price) = current_price(args).await? {
                 let value = latest.total * price;
                 print!(" (Value: ${:.2} @ ${:.4})", value, price);
             }
+        }
+        println!();
+
+        if args.alert_below.is_some() || args.alert_above.is_some() {
+            let alerts = threshold_alerts(
+                &previous_latest,
+                &latest.balances,
+                args.alert_below,
+                args.alert_above,
+            );
+            report_alerts(&alerts, &latest.date, &args.unit());
+            if let Some(alert_file) = &args.alert_file {
+                save_alerts_csv(alert_file, &alerts)?;
+                info!("Saved alerts: {:?}", alert_file);
+            }
+        }
+    }
+
+    if args.summary {
+        // Roles are looked up once, at the latest block
+        let mut reward_tracker = RewardTracker::new(NODE_URL);
+        reward_tracker.set_client((**chain.client()?).clone());
+        reward_tracker.set_rpc((**chain.rpc()?).clone());
+        reward_tracker.set_ss58_format(ss58_format);
+        let latest_hash =
+            ctc_balance::parse_block_hash(&chain.get_block_hash(latest_block).await?)?;
+        let roles = reward_tracker
+            .get_account_roles(accounts, latest_hash)
+            .await
+            .unwrap_or_else(|e| {
+                warn!("Failed to look up staking roles: {}", e);
+                HashMap::new()
+            });
+        let mut notes = account_notes.clone();
+        for name in &args.exclude_from_total {
+            let note = notes.entry(name.clone()).or_default();
+            if !note.is_empty() {
+                note.push_str("; ");
+            }
+            note.push_str("not in total");
+        }
+        print_summary(&account_names, &entries, &roles, &notes, &args.unit());
+    }
+
+    finish_run(args, &mut manifest, &mut failed, &dates, &output_dir)?;
+
+    println!("\n{}\nCOMPLETED!\n{}", "=".repeat(60), "=".repeat(60));
+    Ok(())
+}
+
+/// Save the run manifest and fold this run's failures into the failed-dates file
+fn finish_run(
+    args: &Args,
+    manifest: &mut RunManifest,
+    failed: &mut FailedDates,
+    dates: &[NaiveDate],
+    output_dir: &std::path::Path,
+) -> Result<()> {
+    manifest.blocks.failed.sort();
+    manifest.balances.failed.sort();
+    manifest.rewards.failed.sort();
+    manifest.reconnects = ctc_balance::rpc::reconnect_count();
+    if manifest.reconnects > 0 {
+        info!(
+            "RPC connection was reopened {} time(s) during the run",
+            manifest.reconnects
+        );
+    }
+    let manifest_file = manifest.save(output_dir)?;
+    info!("Saved run manifest: {:?}", manifest_file);
+
+    // Dates without a block failed every later stage too
+    let mut failed_rewards = manifest.rewards.failed.clone();
+    if !args.no_rewards {
+        failed_rewards.extend(manifest.blocks.failed.iter().cloned());
+    }
+    let mut failed_balances = manifest.balances.failed.clone();
+    if !args.rewards_only {
+        failed_balances.extend(manifest.blocks.failed.iter().cloned());
+    }
+    let run_dates: Vec<String> = dates
+        .iter()
+        .map(|d| d.format("%Y-%m-%d").to_string())
+        .collect();
+    failed.update(&run_dates, &failed_balances, &failed_rewards);
+    let failed_file = failed.save()?;
+    if !failed.is_empty() {
+        warn!(
+            "{} balance and {} reward dates failed; see {:?} and run with --retry-failed",
+            failed.balances.len(),
+            failed.rewards.len(),
+            failed_file
+        );
+    }
+    Ok(())
+}
+
+/// Follow the chain head until Ctrl-C: balances at every new head are appended
+/// to `<source>_watch.csv` in the output directory, and the daily history is brought up to date
+/// at the start and whenever a head passes the next date's snapshot time
+async fn run_watch(
+    args: &Args,
+    set: &AccountSet,
+    chain: &mut ChainConnector,
+    info: &ChainInfo,
+    local_first_block: Option<u64>,
+) -> Result<()> {
+    use futures::StreamExt;
+
+    let accounts = &set.accounts;
+    let snapshot = args.snapshot();
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+    let output_file =
+        args.output_dir()
+            .join(format!("{}_watch{}.csv", set.source_name, args.asset_tag()));
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_rpc((**chain.rpc()?).clone());
+    tracker.set_batch(args.batch);
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+
+    let mut ctrl_c = std::pin::pin!(tokio::signal::ctrl_c());
+    // Caches are saved between awaits, so stopping mid-refresh leaves no file half written
+    macro_rules! refresh {
+        () => {
+            tokio::select! {
+                _ = &mut ctrl_c => {
+                    info!("Stopped during the daily history refresh");
+                    return Ok(());
+                }
+                result = run_history(args, set, chain, info, local_first_block) => {
+                    if let Err(e) = result {
+                        warn!("Daily history refresh failed: {:#}", e);
+                    }
+                }
+            }
+        };
+    }
+    // Wait out `backoff`; true if Ctrl-C came first
+    macro_rules! interrupted {
+        ($backoff:expr) => {
+            tokio::select! {
+                _ = &mut ctrl_c => true,
+                _ = tokio::time::sleep($backoff) => false,
+            }
+        };
+    }
+
+    refresh!();
+    // Snapshot date the previous head fell on
+    let mut current_date: Option<NaiveDate> = None;
+    let mut rows = 0;
+    // Wait before retrying after an error, doubled on each consecutive one
+    let mut backoff = Duration::ZERO;
+
+    info!("Watching new {:?} heads (Ctrl-C to stop)...", args.head);
+    'watch: loop {
+        if interrupted!(backoff) {
+            break;
+        }
+        let mut heads = match chain.subscribe_new_heads().await {
+            Ok(heads) => heads,
             Err(e) => {
-                print!(" (Price fetch failed: {})", e);
+                backoff = next_backoff(backoff);
+                warn!(
+                    "Failed to subscribe to heads: {}; retrying in {:?}",
+                    e, backoff
+                );
+                continue;
+            }
+        };
+        loop {
+            let head = tokio::select! {
+                _ = &mut ctrl_c => break 'watch,
+                head = heads.next() => head,
+            };
+            let block = match head {
+                Some(Ok(block)) => block,
+                Some(Err(e)) => {
+                    backoff = next_backoff(backoff);
+                    warn!(
+                        "Head subscription error: {}; subscribing again in {:?}",
+                        e, backoff
+                    );
+                    continue 'watch;
+                }
+                None => {
+                    backoff = next_backoff(backoff);
+                    warn!(
+                        "Head subscription closed; subscribing again in {:?}",
+                        backoff
+                    );
+                    continue 'watch;
+                }
+            };
+
+            let fetched = async {
+                let hash = chain.get_block_hash(block).await?;
+                let timestamp = chain.get_block_timestamp(&hash).await?;
+                let balances = tracker
+                    .get_all_balances(accounts, &hash)
+                    .await?
+                    .complete()?;
+                anyhow::Ok((hash, timestamp, balances))
+            };
+            let (hash, timestamp, balances) = match fetched.await {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    backoff = next_backoff(backoff);
+                    warn!("Skipping block {}: {}; waiting {:?}", block, e, backoff);
+                    if interrupted!(backoff) {
+                        break 'watch;
+                    }
+                    continue;
+                }
+            };
+            backoff = Duration::ZERO;
+            let balances: HashMap<String, f64> = balances
+                .into_iter()
+                .map(|(name, b)| (name, b.metric(args.balance_metric)))
+                .collect();
+            let total = balances
+                .iter()
+                .filter(|(name, _)| !args.exclude_from_total.contains(name))
+                .map(|(_, b)| b)
+                .sum();
+            let time = chrono::DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default();
+            append_block_csv(
+                &output_file,
+                &account_names,
+                &BlockEntry {
+                    block,
+                    hash,
+                    time: time.to_rfc3339(),
+                    balances,
+                    total,
+                    rewards: HashMap::new(),
+                    total_reward: 0.0,
+                },
+            )?;
+            rows += 1;
+            info!("Block {}: total {:.4} {}", block, total, args.unit());
+
+            // The snapshot date this head belongs to: its local date, or the day
+            // before when it comes ahead of that day's snapshot time
+            let local = time.with_timezone(&snapshot.tz);
+            let mut date = local.date_naive();
+            if local.time() < snapshot.time {
+                date = date.pred_opt().unwrap_or(date);
+            }
+            if current_date.is_some_and(|d| date > d) {
+                info!("New date {}; refreshing the daily history...", date);
+                refresh!();
+            }
+            current_date = Some(date);
+        }
+    }
+
+    info!(
+        "Stopped watching; {} rows written to {:?}",
+        rows, output_file
+    );
+    Ok(())
+}
+
+/// Longest wait between retries in `--watch`
+const MAX_WATCH_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Wait after another consecutive `--watch` error: 1s, doubling up to `MAX_WATCH_BACKOFF`
+fn next_backoff(previous: Duration) -> Duration {
+    (previous * 2).clamp(Duration::from_secs(1), MAX_WATCH_BACKOFF)
+}
+
+/// Print one line per account: staking role, latest balance and total rewards
+fn print_summary(
+    account_names: &[String],
+    entries: &[HistoryEntry],
+    roles: &HashMap<String, AccountRole>,
+    notes: &HashMap<String, String>,
+    unit: &str,
+) {
+    let Some(latest) = entries.last() else {
+        return;
+    };
+    println!("\n  Summary ({}):", latest.date);
+    for name in account_names {
+        let role = roles
+            .get(name)
+            .map(|r| r.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let note = notes
+            .get(name)
+            .map(|n| format!("  ({})", n))
+            .unwrap_or_default();
+        println!(
+            "  {:<20} {:<10} {:>16.1} {}  rewards: {:.4}{}",
+            name,
+            role,
+            latest.balances.get(name).copied().unwrap_or(0.0),
+            unit,
+            latest.reward_cumulatives.get(name).copied().unwrap_or(0.0),
+            note
+        );
+    }
+
+    // Yield vs capital moved, split as in `calculate_diffs`
+    let flows: f64 = entries.iter().map(|e| e.principal_flow).sum();
+    println!(
+        "\n  Change since {}: {:.1} {} = {:.4} reward income + {:.1} principal flow",
+        entries[0].date,
+        latest.total - entries[0].total,
+        unit,
+        latest.income_excluding_principal,
+        flows
+    );
+}
+
+/// Print the daily reward distribution table from `reward_stats`
+fn print_reward_stats(stats: &[(String, RewardStats)], unit: &str) {
+    println!("\n  Daily rewards ({}):", unit);
+    println!(
+        "  {:<20} {:>6} {:>12} {:>12} {:>12} {:>12} {:>12}",
+        "account", "days", "min", "max", "mean", "median", "p90"
+    );
+    for (name, s) in stats {
+        println!(
+            "  {:<20} {:>6} {:>12.4} {:>12.4} {:>12.4} {:>12.4} {:>12.4}",
+            name, s.days, s.min, s.max, s.mean, s.median, s.p90
+        );
+    }
+}
+
+/// Print threshold alerts, newly crossed ones first
+fn report_alerts(alerts: &[ThresholdAlert], date: &str, unit: &str) {
+    if alerts.is_empty() {
+        info!("No accounts past the alert thresholds on {}", date);
+        return;
+    }
+    println!("\n  ALERTS ({}):", date);
+    let (new, ongoing): (Vec<_>, Vec<_>) = alerts.iter().partition(|a| a.is_new());
+    for alert in new.iter().chain(ongoing.iter()) {
+        let previous = alert
+            .previous
+            .map(|p| format!("{:.1}", p))
+            .unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {} {:<20} {} {} {}: {} -> {:.1} {}",
+            if alert.is_new() { "NEW" } else { "   " },
+            alert.account,
+            alert.kind,
+            alert.threshold,
+            unit,
+            previous,
+            alert.current,
+            unit
+        );
+    }
+}
+
+/// Subset of accounts whose window includes the given date
+fn active_accounts(
+    accounts: &HashMap<String, String>,
+    windows: &HashMap<String, DateWindow>,
+    date_str: &str,
+) -> HashMap<String, String> {
+    accounts
+        .iter()
+        .filter(|(name, _)| is_active_on(windows, name, date_str))
+        .map(|(name, address)| (name.clone(), address.clone()))
+        .collect()
+}
+
+/// `--blocks`: balances (and rewards) at explicit block numbers, one row per block.
+///
+/// A block's rewards cover the span up to the next listed block; the last
+/// block's rewards cover only that block.
+async fn run_blocks(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    source_name: &str,
+    chain: &mut ChainConnector,
+) -> Result<()> {
+    let mut blocks = args.blocks.clone();
+    blocks.sort();
+    blocks.dedup();
+    info!(
+        "Fetching balances at {} explicit blocks (--blocks)...",
+        blocks.len()
+    );
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+
+    let mut reward_tracker = RewardTracker::new(NODE_URL);
+    reward_tracker.set_client((**chain.client()?).clone());
+    reward_tracker.set_rpc((**chain.rpc()?).clone());
+    reward_tracker.set_divisor(chain.divisor());
+    reward_tracker.set_ss58_format(chain.ss58_format());
+    reward_tracker.set_max_scan_blocks(Some(args.max_blocks_scan).filter(|&max| max > 0));
+
+    let mut entries = Vec::new();
+    for (i, &block) in blocks.iter().enumerate() {
+        let hash = chain.get_block_hash(block).await?;
+        let time = chain
+            .get_block_timestamp(&hash)
+            .await
+            .ok()
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts as i64, 0))
+            .map(|t| t.to_rfc3339())
+            .unwrap_or_default();
+
+        let balances: HashMap<String, f64> = tracker
+            .get_all_balances(accounts, &hash)
+            .await?
+            .complete()?
+            .into_iter()
+            .map(|(name, b)| (name, b.metric(args.balance_metric)))
+            .collect();
+
+        let mut rewards = HashMap::new();
+        if !args.no_rewards {
+            let end = blocks.get(i + 1).map(|next| next - 1).unwrap_or(block);
+            match reward_tracker
+                .get_rewards(accounts, block, end, args.reward_basis)
+                .await
+            {
+                Ok(found) => {
+                    for (name, reward) in found {
+                        rewards.insert(name, reward.amount(args.reward_basis));
+                    }
+                }
+                Err(e) => warn!("Reward query failed for blocks {}-{}: {}", block, end, e),
+            }
+        }
+
+        info!(
+            "Block {} ({}): {:.1} {}",
+            block,
+            time,
+            balances.values().sum::<f64>(),
+            args.unit()
+        );
+        entries.push(BlockEntry {
+            block,
+            hash,
+            time,
+            total: balances.values().sum(),
+            balances,
+            total_reward: rewards.values().sum(),
+            rewards,
+        });
+    }
+
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+    let output_file = args.output_or(
+        &args.output_dir(),
+        format!("{}_blocks{}.csv", source_name, args.asset_tag()),
+    );
+    save_block_csv(&output_file, &account_names, &entries, !args.no_rewards)?;
+    info!("Saved: {:?}", output_file);
+
+    println!("\n{}\nCOMPLETED!\n{}", "=".repeat(60), "=".repeat(60));
+    Ok(())
+}
+
+/// Balances sampled every `--granularity-blocks` blocks within one date, with
+/// rows keyed by time of day (in the snapshot timezone)
+async fn run_intraday(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    source_name: &str,
+    chain: &mut ChainConnector,
+) -> Result<()> {
+    let date = args
+        .start_date()?
+        .ok_or_else(|| anyhow::anyhow!("--intraday needs --start (the date to sample)"))?;
+    if let Some(end) = args.end_date()? {
+        anyhow::ensure!(
+            end == date,
+            "--intraday covers a single date; drop --end or set it to --start"
+        );
+    }
+    let snapshot = args.snapshot();
+    chain.set_max_block_error(args.max_block_error_secs);
+    let output_dir = args.output_dir();
+    let cache = snapshot_view(
+        &CacheStore::open(args, &output_dir)?
+            .load_blocks()
+            .unwrap_or_default(),
+        &snapshot,
+    );
+
+    // The day runs from this date's block up to the next date's (or the head)
+    let latest = chain.get_latest_block_number().await?;
+    let mut bounds = Vec::new();
+    for d in [date, date + Days::new(1)] {
+        let block = match cache.get(&d.format("%Y-%m-%d").to_string()) {
+            Some(info) => info.block,
+            // A date still in the future resolves to the head
+            None => {
+                chain
+                    .find_block_near(snapshot.timestamp(d), 60, None)
+                    .await?
+                    .block
+            }
+        };
+        bounds.push(block.min(latest));
+    }
+    let (first, last) = (bounds[0], bounds[1]);
+    anyhow::ensure!(last > first, "No blocks found within {}", date);
+    info!(
+        "Sampling {} blocks {}-{} every {} blocks (--intraday)...",
+        date, first, last, args.granularity_blocks
+    );
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+
+    let mut entries = Vec::new();
+    for block in (first..last).step_by(args.granularity_blocks as usize) {
+        let hash = chain.get_block_hash(block).await?;
+        let time = chain
+            .get_block_timestamp(&hash)
+            .await
+            .ok()
+            .and_then(|ts| chrono::DateTime::from_timestamp(ts as i64, 0))
+            .map(|t| t.with_timezone(&snapshot.tz).format("%H:%M:%S").to_string())
+            .unwrap_or_default();
+        let balances: HashMap<String, f64> = tracker
+            .get_all_balances(accounts, &hash)
+            .await?
+            .complete()?
+            .into_iter()
+            .map(|(name, b)| (name, b.metric(args.balance_metric)))
+            .collect();
+        let total = balances.values().sum();
+        debug!("{} (block {}): {:.1}", time, block, total);
+        entries.push(BlockEntry {
+            block,
+            hash,
+            time,
+            balances,
+            total,
+            rewards: HashMap::new(),
+            total_reward: 0.0,
+        });
+    }
+
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+    let output_file = args.output_or(
+        &output_dir,
+        format!("{}_intraday_{}{}.csv", source_name, date, args.asset_tag()),
+    );
+    save_block_csv(&output_file, &account_names, &entries, false)?;
+    info!("Saved {} samples: {:?}", entries.len(), output_file);
+    Ok(())
+}
+
+/// Balances at the parent of the block that includes `tx_hash` and at that block
+async fn run_extrinsic(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    chain: &mut ChainConnector,
+    tx_hash: &str,
+) -> Result<()> {
+    info!(
+        "Searching the last {} blocks for extrinsic {}...",
+        args.extrinsic_search_blocks, tx_hash
+    );
+    let found = chain
+        .block_of_extrinsic(tx_hash, args.extrinsic_search_blocks)
+        .await?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "Extrinsic {} not found in the last {} blocks (raise --extrinsic-search-blocks)",
+                tx_hash,
+                args.extrinsic_search_blocks
+            )
+        })?;
+    anyhow::ensure!(found.block > 0, "Extrinsic is in the genesis block");
+    let parent_hash = chain.get_block_hash(found.block - 1).await?;
+    info!("Included in block {} ({})", found.block, found.hash);
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+    let before = tracker
+        .get_all_balances(accounts, &parent_hash)
+        .await?
+        .complete()?;
+    let after = tracker
+        .get_all_balances(accounts, &found.hash)
+        .await?
+        .complete()?;
+
+    println!(
+        "\n  {:<20} {:>18} {:>18} {:>18}",
+        "account",
+        format!("block {}", found.block - 1),
+        format!("block {}", found.block),
+        "change"
+    );
+    for name in column_order(&accounts.keys().cloned().collect::<Vec<_>>()) {
+        let metric = |b: &HashMap<String, Balance>| {
+            b.get(&name)
+                .map(|b| b.metric(args.balance_metric))
+                .unwrap_or(0.0)
+        };
+        let (old, new) = (metric(&before), metric(&after));
+        println!(
+            "  {:<20} {:>18.4} {:>18.4} {:>+18.4}",
+            name,
+            old,
+            new,
+            new - old
+        );
+    }
+    Ok(())
+}
+
+/// Fast path: balances and lifetime rewards at the latest block only
+async fn run_only_latest(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    source_name: &str,
+    chain: &mut ChainConnector,
+) -> Result<()> {
+    info!("Fetching latest balances and lifetime rewards (--only-latest)...");
+    let latest_block = chain.get_latest_block_number().await?;
+    let latest_hash = chain.get_block_hash(latest_block).await?;
+    info!("Latest block: {}", latest_block);
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+    let balances = tracker
+        .get_all_balances(accounts, &latest_hash)
+        .await?
+        .complete()?;
+
+    let mut rewards: HashMap<String, f64> = HashMap::new();
+    let mut payee_notes: HashMap<String, String> = HashMap::new();
+    if !args.no_rewards {
+        anyhow::ensure!(
+            args.reward_basis == RewardBasis::Earned,
+            "--reward-basis claimed scans every block and is not supported with --only-latest"
+        );
+        let mut reward_tracker = RewardTracker::new(NODE_URL);
+        reward_tracker.set_client((**chain.client()?).clone());
+        reward_tracker.set_divisor(chain.divisor());
+        reward_tracker.set_ss58_format(chain.ss58_format());
+        reward_tracker.set_rpc((**chain.rpc()?).clone());
+        for (name, reward) in reward_tracker
+            .get_rewards_via_eras(accounts, 1, latest_block)
+            .await?
+        {
+            if let Some(note) = reward.payee {
+                payee_notes.insert(name.clone(), note);
+            }
+            rewards.insert(name, reward.earned);
+        }
+    }
+
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+
+    let in_total_rewards: f64 = rewards
+        .iter()
+        .filter(|(name, _)| !args.exclude_from_total.contains(name))
+        .map(|(_, r)| r)
+        .sum();
+    let today = args.snapshot().today().format("%Y-%m-%d").to_string();
+    let entry = HistoryEntry {
+        date: today,
+        balances: balances
+            .iter()
+            .map(|(name, b)| (name.clone(), b.metric(args.balance_metric)))
+            .collect(),
+        total: balances
+            .iter()
+            .filter(|(name, _)| !args.exclude_from_total.contains(name))
+            .map(|(_, b)| b.metric(args.balance_metric))
+            .sum(),
+        total_reward: in_total_rewards,
+        total_reward_cumulative: in_total_rewards,
+        reward_cumulatives: rewards.clone(),
+        rewards,
+        ..Default::default()
+    };
+
+    for name in &account_names {
+        print!(
+            "  {}: {:.1} CTC",
+            name,
+            entry.balances.get(name).copied().unwrap_or(0.0)
+        );
+        if !args.no_rewards {
+            print!(
+                " (lifetime rewards: {:.4})",
+                entry.rewards.get(name).copied().unwrap_or(0.0)
+            );
+            if let Some(note) = payee_notes.get(name) {
+                print!(" [{}]", note);
             }
         }
         println!();
     }
 
+    let output_file = args.output_or(&args.output_dir(), format!("{}_latest.csv", source_name));
+    save_combined_csv(
+        &output_file,
+        &account_names,
+        std::slice::from_ref(&entry),
+        !args.no_rewards,
+        args.total_with_rewards,
+        false,
+        args.rounding,
+        args.checksum,
+    )?;
+    info!("Saved: {:?}", output_file);
+
+    print!("\n  Latest: {:.1} {}", entry.total, args.unit());
+    if args.asset.is_none() {
+        if let Some(price) = current_price(args).await? {
+            print!(" (Value: ${:.2} @ ${:.4})", entry.total * price, price);
+        }
+    }
+    println!();
+
     println!("\n{}\nCOMPLETED!\n{}", "=".repeat(60), "=".repeat(60));
     Ok(())
 }
 
+/// Earned and claimed figures are cached separately so runs don't mix them
+fn reward_cache_stem(basis: RewardBasis, tag: &str) -> String {
+    match basis {
+        RewardBasis::Earned => format!("reward_cache{}", tag),
+        RewardBasis::Claimed => format!("reward_cache_claimed{}", tag),
+    }
+}
+
+/// Print the reward cache's coverage of `dates` per account as a table of spans
+fn run_reward_coverage(
+    args: &Args,
+    store: &CacheStore,
+    tag: &str,
+    accounts: &HashMap<String, String>,
+    dates: &[NaiveDate],
+) -> Result<()> {
+    let stem = reward_cache_stem(args.reward_basis, tag);
+    let reward_cache = store.load_rewards(&stem).unwrap_or_default();
+    info!(
+        "Reward coverage from {} ({} accounts cached)",
+        stem,
+        reward_cache.len()
+    );
+
+    println!(
+        "\n  {:<20} {:<10} {:<12} {:<12} {:>6}",
+        "account", "kind", "from", "to", "days"
+    );
+    for name in column_order(&accounts.keys().cloned().collect::<Vec<_>>()) {
+        let spans = reward_coverage(&reward_cache, &name, dates);
+        for span in &spans {
+            let kind = match span.kind {
+                CoverageKind::Rewarded => "rewarded",
+                CoverageKind::Zero => "zero",
+                CoverageKind::Missing => "MISSING",
+            };
+            println!(
+                "  {:<20} {:<10} {:<12} {:<12} {:>6}",
+                name, kind, span.start, span.end, span.days
+            );
+        }
+        let missing: usize = spans
+            .iter()
+            .filter(|s| s.kind == CoverageKind::Missing)
+            .map(|s| s.days)
+            .sum();
+        if missing > 0 {
+            warn!(
+                "{}: {} of {} dates have no cached reward",
+                name,
+                missing,
+                dates.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Apply `--genesis-block` to `chain` and warn when the genesis date doesn't
+/// match the date of the chain's (possibly overridden) genesis block
+async fn check_genesis_override(args: &Args, chain: &mut ChainConnector) -> Result<()> {
+    if let Some(block) = args.genesis_block {
+        chain.set_genesis_block(block);
+    }
+    let genesis_ts = chain.get_genesis_timestamp().await?;
+    let chain_date = chrono::DateTime::from_timestamp(genesis_ts as i64, 0)
+        .map(|t| t.date_naive())
+        .ok_or_else(|| anyhow::anyhow!("Invalid genesis timestamp {}", genesis_ts))?;
+    let genesis_date = args.genesis_date();
+    if chain_date != genesis_date {
+        warn!(
+            "Genesis date {} doesn't match the chain: block {} is from {}",
+            genesis_date,
+            args.genesis_block.unwrap_or(1),
+            chain_date
+        );
+    } else {
+        info!(
+            "Genesis override: {} (block {})",
+            genesis_date,
+            args.genesis_block.unwrap_or(1)
+        );
+    }
+    Ok(())
+}
+
+/// Print every account of the file with its chain-prefixed address and current
+/// balance. Fails when any address is invalid; zero balances only get a warning,
+/// as they often point at a typo.
+async fn run_accounts_validate(args: &Args, entries: &[AccountEntry]) -> Result<()> {
+    anyhow::ensure!(
+        !entries.is_empty(),
+        "No accounts found in the accounts file"
+    );
+
+    let mut chain = ChainConnector::new(Some(NODE_URL));
+    chain.set_head_mode(args.head);
+    chain.connect().await?;
+    let info = chain.get_chain_info().await?;
+    let latest = chain.get_latest_block_number().await?;
+    let hash = chain.get_block_hash(latest).await?;
+    info!("Checking {} accounts at block {}", entries.len(), latest);
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+
+    let mut invalid = 0;
+    let mut zero = 0;
+    println!(
+        "\n  {:<5} {:<20} {:<50} {:<8} {:>16}",
+        "line", "name", "address", "valid", "balance"
+    );
+    for entry in entries {
+        let (address, valid, balance) = match ctc_balance::parse_ss58_address(&entry.address) {
+            Ok(id) => {
+                let balance = tracker
+                    .get_balance(&entry.address, &hash)
+                    .await?
+                    .metric(args.balance_metric);
+                if balance == 0.0 {
+                    zero += 1;
+                }
+                let balance = format!("{:.4} {}", balance, args.unit());
+                (ctc_balance::to_ss58(&id, info.ss58_format), "yes", balance)
+            }
+            Err(_) => {
+                invalid += 1;
+                (entry.address.clone(), "NO", "-".to_string())
+            }
+        };
+        println!(
+            "  {:<5} {:<20} {:<50} {:<8} {:>16}",
+            entry.line, entry.name, address, valid, balance
+        );
+    }
+
+    if zero > 0 {
+        warn!(
+            "{} account(s) have a zero balance right now; check them for typos",
+            zero
+        );
+    }
+    anyhow::ensure!(invalid == 0, "{} invalid address(es)", invalid);
+    println!("\n  All {} addresses are valid", entries.len());
+    Ok(())
+}
+
+/// Dump the raw `System.Account` entry of every account at a date's block
+async fn run_account_dump(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    chain: &mut ChainConnector,
+    date: NaiveDate,
+) -> Result<()> {
+    let snapshot = args.snapshot();
+    chain.set_max_block_error(args.max_block_error_secs);
+    let block = chain
+        .find_block_near(snapshot.timestamp(date), 60, None)
+        .await?;
+    info!("Account dump for {} at block {}", date, block.block);
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+
+    let mut dump = serde_json::Map::new();
+    for name in column_order(&accounts.keys().cloned().collect::<Vec<_>>()) {
+        let address = &accounts[&name];
+        let raw = tracker.get_raw_account(address, &block.hash).await?;
+        dump.insert(
+            name,
+            serde_json::json!({ "address": address, "account": raw }),
+        );
+    }
+
+    let output = serde_json::json!({
+        "date": date.to_string(),
+        "block": block.block,
+        "hash": block.hash,
+        "accounts": dump,
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+    Ok(())
+}
+
+/// Flag days in the saved history CSV where an account's balance change is not
+/// explained by its rewards
+fn run_reward_check(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    source_name: &str,
+    tolerance: f64,
+) -> Result<()> {
+    let output_dir = args.output_dir();
+    let output_file = args.history_file(&output_dir, source_name);
+    anyhow::ensure!(output_file.exists(), "CSV not found: {:?}", output_file);
+    let balances: HashMap<String, HashMap<String, f64>> = load_existing_csv(&output_file)?
+        .into_iter()
+        .filter(|(name, _)| accounts.contains_key(name))
+        .collect();
+    let rewards = load_csv_rewards(&output_file)?;
+    anyhow::ensure!(
+        !rewards.is_empty(),
+        "No reward columns in {:?}; run without --no-rewards first",
+        output_file
+    );
+    if args.reward_basis == RewardBasis::Earned {
+        warn!(
+            "Earned rewards reach the balance only when paid out; \
+             use --reward-basis claimed to avoid flagging payout delays"
+        );
+    }
+
+    let mismatches = reward_balance_mismatches(&balances, &rewards, tolerance);
+    let check_file = ctc_balance::sidecar_file(&output_file, "reward_check.csv");
+    save_reward_check_csv(&check_file, &mismatches)?;
+
+    println!("\nReward vs balance check (tolerance {} CTC):", tolerance);
+    let mut names: Vec<&String> = balances.keys().collect();
+    names.sort();
+    for name in names {
+        let flagged: Vec<_> = mismatches.iter().filter(|m| &m.account == name).collect();
+        if flagged.is_empty() {
+            println!("  {}: OK", name);
+            continue;
+        }
+        // Net of all flagged days: transfers in (+) or out (-), or rewards misread
+        let net: f64 = flagged.iter().map(|m| m.residual).sum();
+        println!(
+            "  {}: {} day(s) diverge, {:+.2} CTC unexplained by rewards",
+            name,
+            flagged.len(),
+            net
+        );
+        for m in flagged.iter().take(5) {
+            println!(
+                "    {}: balance {:+.2}, reward {:.2}, residual {:+.2}",
+                m.date, m.balance_change, m.reward, m.residual
+            );
+        }
+        if flagged.len() > 5 {
+            println!("    ... {} more", flagged.len() - 5);
+        }
+    }
+    info!(
+        "Saved {} mismatch(es) to {:?}",
+        mismatches.len(),
+        check_file
+    );
+    Ok(())
+}
+
+/// Reconcile the saved history CSV against the chain for a sample of dates
+async fn run_verify(
+    args: &Args,
+    accounts: &HashMap<String, String>,
+    windows: &HashMap<String, DateWindow>,
+    source_name: &str,
+    chain: &ChainConnector,
+    samples: usize,
+    tolerance: f64,
+) -> Result<()> {
+    let output_dir = args.output_dir();
+    let snapshot = args.snapshot();
+    let output_file = args.history_file(&output_dir, source_name);
+    let cache = snapshot_view(
+        &CacheStore::open(args, &output_dir)?.load_blocks()?,
+        &snapshot,
+    );
+    let stored = load_existing_csv(&output_file)?;
+    anyhow::ensure!(!stored.is_empty(), "No data in {:?}", output_file);
+
+    let account_names: Vec<String> = column_order(&accounts.keys().cloned().collect::<Vec<_>>());
+
+    info!("Verifying {:?}...", output_file);
+    let total_names: Vec<String> = account_names
+        .iter()
+        .filter(|name| !args.exclude_from_total.contains(name))
+        .cloned()
+        .collect();
+    let mut issues = check_csv_consistency(&output_file, &total_names, tolerance)?;
+
+    // Evenly spaced sample of dates that have a cached block, always including the last
+    let mut dates: Vec<&String> = stored
+        .values()
+        .flat_map(|h| h.keys())
+        .filter(|d| cache.contains_key(*d))
+        .collect();
+    dates.sort();
+    dates.dedup();
+    let step = dates.len().div_ceil(samples.max(1)).max(1);
+    let mut sampled: Vec<&String> = dates.iter().rev().step_by(step).copied().collect();
+    sampled.reverse();
+
+    let mut tracker = BalanceTracker::new(NODE_URL);
+    tracker.set_client((**chain.client()?).clone());
+    tracker.set_asset(args.asset);
+    tracker.set_divisor(chain.divisor());
+    for date in &sampled {
+        let date_accounts = active_accounts(accounts, windows, date);
+        let balances = tracker
+            .get_all_balances(&date_accounts, &cache[*date].hash)
+            .await?
+            .complete()?;
+        for (name, balance) in balances {
+            let Some(&csv_value) = stored.get(&name).and_then(|h| h.get(*date)) else {
+                continue;
+            };
+            let chain_value = balance.metric(args.balance_metric);
+            if (chain_value - csv_value).abs() > tolerance {
+                issues.push(format!(
+                    "{} {}: CSV {:.1} but chain {:.4}",
+                    date, name, csv_value, chain_value
+                ));
+            }
+        }
+        debug!("Verified {}", date);
+    }
+
+    println!(
+        "\n  Checked {} rows and {} sampled dates: {} issue(s)",
+        stored.values().map(|h| h.len()).max().unwrap_or(0),
+        sampled.len(),
+        issues.len()
+    );
+    for issue in &issues {
+        println!("    {}", issue);
+    }
+    anyhow::ensure!(
+        issues.is_empty(),
+        "Verification found {} issue(s)",
+        issues.len()
+    );
+
+    println!("\n{}\nVERIFIED!\n{}", "=".repeat(60), "=".repeat(60));
+    Ok(())
+}
+
+/// Rebuild the history entries of a saved CSV from its balance and reward
+/// columns, then rewrite the CSV and its graphs with the current options
+fn run_recompute(args: &Args, csv: &std::path::Path) -> Result<()> {
+    anyhow::ensure!(csv.exists(), "CSV not found: {:?}", csv);
+    let balances = load_existing_csv(csv)?;
+    let rewards = if args.no_rewards {
+        HashMap::new()
+    } else {
+        load_csv_rewards(csv)?
+    };
+    let account_names = load_csv_accounts(csv)?;
+    anyhow::ensure!(!account_names.is_empty(), "No account columns in {:?}", csv);
+
+    let (mut account_windows, mut account_groups, mut account_colors) =
+        (HashMap::new(), None, HashMap::new());
+    match &args.file {
+        Some(file) if file.as_os_str() != "-" => {
+            let entries = load_account_entries(file)?;
+            account_windows = entries_to_windows(&entries);
+            account_groups = entries_to_groups(&entries);
+            account_colors = entries_to_colors(&entries);
+        }
+        _ => {
+            // Without an accounts file, an account's window is the span of its cells
+            for (name, history) in &balances {
+                let parse = |d: &String| NaiveDate::parse_from_str(d, "%Y-%m-%d").ok();
+                account_windows.insert(
+                    name.clone(),
+                    DateWindow {
+                        start: history.keys().min().and_then(parse),
+                        end: history.keys().max().and_then(parse),
+                    },
+                );
+            }
+        }
+    }
+
+    let dates: Vec<String> = balances
+        .values()
+        .chain(rewards.values())
+        .flat_map(|h| h.keys().cloned())
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let mut entries = build_history_entries(
+        &dates,
+        &account_names,
+        &account_windows,
+        account_groups.as_ref(),
+        &balances,
+        &rewards,
+        &args.exclude_from_total,
+    );
+    calculate_diffs(&mut entries);
+
+    let include_rewards = !rewards.is_empty();
+    let output_file = match &args.output {
+        Some(_) => args.output_or(&args.output_dir(), String::new()),
+        None => {
+            let stem = csv
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("history");
+            csv.with_file_name(format!("{}_recomputed.csv", stem))
+        }
+    };
+    save_combined_csv(
+        &output_file,
+        &account_names,
+        &entries,
+        include_rewards,
+        args.total_with_rewards,
+        false,
+        args.rounding,
+        args.checksum,
+    )?;
+    info!(
+        "Recomputed {} rows from {:?}: {:?}",
+        entries.len(),
+        csv,
+        output_file
+    );
+
+    let source_name = csv
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .map(|s| s.split("_history").next().unwrap_or(s))
+        .unwrap_or("history")
+        .to_string();
+    let daily_total_rewards: HashMap<String, f64> = entries
+        .iter()
+        .map(|e| (e.date.clone(), e.total_reward))
+        .collect();
+    let plot_options = PlotOptions {
+        stacked: args.stacked,
+        individual_dir: args.individual_dir.clone(),
+        plot_data: args.plot_data,
+        account_colors,
+        excluded_from_total: args.exclude_from_total.clone(),
+        rotate_x_labels: args.rotate_x_labels,
+        total_with_rewards: args.total_with_rewards,
+    };
+    plot_balances(
+        &output_file,
+        &dates,
+        &balances,
+        &account_names,
+        &source_name,
+        include_rewards.then_some(&daily_total_rewards),
+        include_rewards.then_some(&rewards),
+        None,
+        &plot_options,
+    )?;
+    plot_groups(&output_file, &entries, &source_name, &plot_options)?;
+    if args.diff_plot {
+        plot_diffs(&output_file, &entries, &source_name, &plot_options)?;
+    }
+    Ok(())
+}
+
+/// Print the differences between two history CSVs, optionally saving the
+/// changed values
+fn run_compare(
+    old: &std::path::Path,
+    new: &std::path::Path,
+    tolerance: f64,
+    output: Option<&std::path::Path>,
+) -> Result<()> {
+    info!("Comparing {:?} -> {:?}...", old, new);
+    let comparison = compare_csvs(old, new, tolerance)?;
+
+    let list = |label: &str, items: &[String]| {
+        if !items.is_empty() {
+            println!("  {} ({}): {}", label, items.len(), items.join(", "));
+        }
+    };
+    println!();
+    list("Added dates", &comparison.added_dates);
+    list("Removed dates", &comparison.removed_dates);
+    list("Added accounts", &comparison.added_accounts);
+    list("Removed accounts", &comparison.removed_accounts);
+
+    let cell = |v: Option<f64>| v.map_or("-".to_string(), |v| format!("{:.1}", v));
+    println!("  Changed values: {}", comparison.changes.len());
+    for change in &comparison.changes {
+        println!(
+            "    {} {}: {} -> {}",
+            change.date,
+            change.account,
+            cell(change.old),
+            cell(change.new)
+        );
+    }
+    if comparison.is_empty() {
+        println!("  No differences beyond {} CTC", tolerance);
+    }
+
+    if let Some(path) = output {
+        save_comparison_csv(path, &comparison)?;
+        info!("Saved comparison: {:?}", path);
+    }
+    Ok(())
+}
+
+/// The known (block, timestamp) point closest in time to `timestamp`
+fn nearest_anchor(anchors: &BTreeMap<u64, u64>, timestamp: u64) -> Option<(u64, u64)> {
+    let before = anchors.range(..=timestamp).next_back();
+    let after = anchors.range(timestamp..).next();
+    let (ts, block) = match (before, after) {
+        (Some(b), Some(a)) if a.0 - timestamp < timestamp - b.0 => a,
+        (Some(b), _) => b,
+        (None, a) => a?,
+    };
+    Some((*block, *ts))
+}
+
+/// Current CTC price, or `None` after a warning when it can't be fetched.
+/// With `--require-price` the failure ends the run instead.
+async fn current_price(args: &Args) -> Result<Option<f64>> {
+    match fetch_ctc_price().await {
+        Ok(price) => Ok(Some(price)),
+        Err(e) if args.require_price => Err(e.context("Price fetch failed (--require-price)")),
+        Err(e) => {
+            warn!("Price fetch failed, leaving out USD values: {}", e);
+            Ok(None)
+        }
+    }
+}
+
+/// Parse a `--snapshot-time` value (HH:MM, UTC)
+fn parse_snapshot_time(s: &str) -> std::result::Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .map_err(|e| format!("Invalid snapshot time '{}': {} (expected HH:MM)", s, e))
+}
+
+/// Parse a `--rpc-header` value (`Key: Value`)
+fn parse_rpc_header(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once(':') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("Invalid header '{}' (expected \"Key: Value\")", s)),
+    }
+}
+
+/// Install the log subscriber. `--log-level` wins over RUST_LOG; a bare
+/// level only applies to this crate so dependency chatter stays at warn.
+fn init_logging(level: Option<&str>) -> Result<()> {
+    let filter = match level {
+        Some(l) if l.contains('=') || l.contains(',') => EnvFilter::try_new(l)?,
+        Some(l) => EnvFilter::try_new(format!("warn,ctc_balance={}", l))?,
+        None => EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| EnvFilter::new("warn,ctc_balance=info")),
+    };
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .without_time()
+        .with_writer(std::io::stderr)
+        .init();
+    Ok(())
+}
+
+/// Log the dates where the runtime spec version changed within the range
+fn report_runtime_upgrades(cache: &BlockCache, dates: &[NaiveDate]) {
+    let mut prev: Option<(String, u32)> = None;
+    for d in dates {
+        let date_str = d.format("%Y-%m-%d").to_string();
+        let Some(spec) = cache.get(&date_str).and_then(|b| b.spec_version) else {
+            continue;
+        };
+        if let Some((prev_date, prev_spec)) = &prev {
+            if *prev_spec != spec {
+                info!(
+                    "Runtime upgrade between {} and {}: spec {} -> {}",
+                    prev_date, date_str, prev_spec, spec
+                );
+            }
+        }
+        prev = Some((date_str, spec));
+    }
+}
+
+/// First block the local node can serve: events *and* state must be there.
+///
+/// A pruned node can keep old events but drop old state, so the event boundary
+/// alone would send balance queries to blocks it cannot answer.
 async fn detect_first_block(url: &str, latest_block: u64) -> u64 {
     let mut tracker = RewardTracker::new(url);
     if tracker.connect().await.is_err() {
         return 0;
     }
-    let mut low = 0u64;
-    let mut high = latest_block;
-    let mut first_block = 0;
 
     // Fast path: check block 0 and 1
-    if tracker.has_events(0).await && tracker.has_events(1).await {
-        return 0;
-    }
-
-    while low <= high {
-        let mid = low + (high - low) / 2;
-        if mid == 0 {
-            low = 1;
-            continue;
+    let events_first = if tracker.has_events(0).await && tracker.has_events(1).await {
+        0
+    } else {
+        let mut low = 1u64;
+        let mut high = latest_block;
+        let mut first_block = 0;
+        while low <= high {
+            let mid = low + (high - low) / 2;
+            if tracker.has_events(mid).await {
+                first_block = mid;
+                high = mid - 1;
+            } else {
+                low = mid + 1;
+            }
         }
-        if tracker.has_events(mid).await {
-            first_block = mid;
-            if mid == 0 {
-                break;
+        first_block
+    };
+
+    let state_first = if tracker.has_state(events_first).await {
+        events_first
+    } else {
+        let mut low = events_first + 1;
+        let mut high = latest_block;
+        let mut first_block = latest_block;
+        while low <= high {
+            let mid = low + (high - low) / 2;
+            if tracker.has_state(mid).await {
+                first_block = mid;
+                high = mid - 1;
+            } else {
+                low = mid + 1;
             }
-            high = mid - 1;
-        } else {
-            low = mid + 1;
         }
+        first_block
+    };
+
+    info!(
+        "Local RPC boundaries: events from block {}, state from block {}",
+        events_first, state_first
+    );
+    if state_first > events_first {
+        warn!(
+            "Local node looks pruned: state starts {} blocks after events",
+            state_first - events_first
+        );
     }
-    first_block
+    state_first
 }
@@ -2,15 +2,152 @@
 //!
 //! Generates PNG graphs for balance history visualization.
 
-use crate::cache::RewardCache;
+use crate::cache::RewardTotals;
+use crate::csv_output::{column_order, HistoryEntry};
 use anyhow::{Context, Result};
-use chrono::NaiveDate;
+use chrono::{Datelike, Months, NaiveDate};
+use plotters::coord::ranged1d::{KeyPointHint, NoDefaultFormatting, ValueFormatter};
+use plotters::coord::types::RangedCoordf64;
 use plotters::prelude::*;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Height of the optional price panel in the main graph
+const PRICE_PANEL_HEIGHT: u32 = 400;
+
+/// Most date labels on an x-axis
+const X_LABELS: usize = 12;
+
+/// Line colors for accounts and groups
+const SERIES_COLORS: [RGBColor; 10] = [
+    RGBColor(31, 119, 180),  // Blue
+    RGBColor(255, 127, 14),  // Orange
+    RGBColor(44, 160, 44),   // Green
+    RGBColor(214, 39, 40),   // Red
+    RGBColor(148, 103, 189), // Purple
+    RGBColor(140, 86, 75),   // Brown
+    RGBColor(227, 119, 194), // Pink
+    RGBColor(127, 127, 127), // Gray
+    RGBColor(188, 189, 34),  // Olive
+    RGBColor(23, 190, 207),  // Cyan
+];
+
+/// Presentation options for `plot_balances`
+#[derive(Debug, Clone, Default)]
+pub struct PlotOptions {
+    /// Draw the total panel as per-account stacked areas instead of a single total area
+    pub stacked: bool,
+    /// Directory for per-account graphs (default: `individual/` next to the output)
+    pub individual_dir: Option<PathBuf>,
+    /// Also write the plotted series to `<stem>_plotdata.csv`
+    pub plot_data: bool,
+    /// Per-account colors (`#rrggbb`); other accounts take the palette color
+    pub account_colors: HashMap<String, String>,
+    /// Accounts drawn on their own but left out of the total panel
+    pub excluded_from_total: Vec<String>,
+    /// Draw date labels vertically
+    pub rotate_x_labels: bool,
+    /// Add a balance + cumulative rewards line to the total panel
+    pub total_with_rewards: bool,
+}
+
+impl PlotOptions {
+    /// Color of the `index`-th account: its own color if set and valid,
+    /// otherwise the palette entry
+    fn color_for(&self, name: &str, index: usize) -> RGBColor {
+        self.account_colors
+            .get(name)
+            .and_then(|hex| parse_hex_color(hex))
+            .unwrap_or(SERIES_COLORS[index % SERIES_COLORS.len()])
+    }
+
+    /// Height of the date label area, grown to fit rotated labels
+    fn x_label_area(&self, base: u32) -> u32 {
+        if self.rotate_x_labels {
+            base + 45
+        } else {
+            base
+        }
+    }
+
+    fn x_label_style(&self) -> TextStyle<'static> {
+        let font = ("sans-serif", 12).into_font();
+        if self.rotate_x_labels {
+            font.transform(FontTransform::Rotate90).into()
+        } else {
+            font.into()
+        }
+    }
+}
+
+/// Parse `#rrggbb` (the `#` is optional)
+fn parse_hex_color(hex: &str) -> Option<RGBColor> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some(RGBColor(channel(0)?, channel(2)?, channel(4)?))
+}
 
-/// Generate main balance graph (combined + total + rewards if available)
+/// Write the series behind the main graph to `<stem>_plotdata.csv`.
+///
+/// Missing values are left empty, as gaps are in the graph.
+#[allow(clippy::too_many_arguments)]
+fn save_plot_data(
+    path: &Path,
+    dates: &[String],
+    all_history: &HashMap<String, HashMap<String, f64>>,
+    account_names: &[String],
+    totals: &[f64],
+    total_reward_history: Option<&HashMap<String, f64>>,
+    totals_with_rewards: Option<&[f64]>,
+    price_history: Option<&HashMap<String, f64>>,
+) -> Result<PathBuf> {
+    let data_path = crate::sidecar_file(path, "plotdata.csv");
+    let mut file = fs::File::create(&data_path).context("Failed to create plot data CSV")?;
+
+    let mut header = vec!["date".to_string()];
+    header.extend(account_names.iter().cloned());
+    header.push("total".to_string());
+    if total_reward_history.is_some() {
+        header.push("total_reward".to_string());
+    }
+    if totals_with_rewards.is_some() {
+        header.push("total_with_rewards".to_string());
+    }
+    if price_history.is_some() {
+        header.push("price_usd".to_string());
+    }
+    writeln!(file, "{}", header.join(","))?;
+
+    let cell = |value: Option<&f64>, precision: usize| {
+        value.map_or(String::new(), |v| format!("{:.*}", precision, v))
+    };
+    for (i, (date, total)) in dates.iter().zip(totals).enumerate() {
+        let mut row = vec![date.clone()];
+        for name in account_names {
+            row.push(cell(all_history.get(name).and_then(|h| h.get(date)), 1));
+        }
+        row.push(format!("{:.1}", total));
+        if let Some(rewards) = total_reward_history {
+            row.push(cell(rewards.get(date), 4));
+        }
+        if let Some(with_rewards) = totals_with_rewards {
+            row.push(format!("{:.1}", with_rewards[i]));
+        }
+        if let Some(prices) = price_history {
+            row.push(cell(prices.get(date), 6));
+        }
+        writeln!(file, "{}", row.join(","))?;
+    }
+    Ok(data_path)
+}
+
+/// Generate main balance graph (combined + total + rewards and price if available)
+#[allow(clippy::too_many_arguments)]
 pub fn plot_balances<P: AsRef<Path>>(
     output_file: P,
     dates: &[String],
@@ -18,10 +155,14 @@ pub fn plot_balances<P: AsRef<Path>>(
     account_names: &[String],
     source_name: &str,
     total_reward_history: Option<&HashMap<String, f64>>, // date -> total_reward
-    individual_reward_history: Option<&RewardCache>,     // account -> date -> reward
+    individual_reward_history: Option<&RewardTotals>,    // account -> date -> reward
+    price_history: Option<&HashMap<String, f64>>,        // date -> USD price
+    options: &PlotOptions,
 ) -> Result<Vec<std::path::PathBuf>> {
     let path = output_file.as_ref();
     let mut generated_files = Vec::new();
+    // Sorted like the CSV columns, so each account keeps its color between runs
+    let account_names = &column_order(account_names)[..];
 
     // Ensure parent directory exists
     if let Some(parent) = path.parent() {
@@ -38,25 +179,19 @@ pub fn plot_balances<P: AsRef<Path>>(
         return Ok(generated_files);
     }
 
-    // Colors for accounts
-    let colors = [
-        RGBColor(31, 119, 180),  // Blue
-        RGBColor(255, 127, 14),  // Orange
-        RGBColor(44, 160, 44),   // Green
-        RGBColor(214, 39, 40),   // Red
-        RGBColor(148, 103, 189), // Purple
-        RGBColor(140, 86, 75),   // Brown
-        RGBColor(227, 119, 194), // Pink
-        RGBColor(127, 127, 127), // Gray
-        RGBColor(188, 189, 34),  // Olive
-        RGBColor(23, 190, 207),  // Cyan
-    ];
+    // A single date renders as labeled markers, since a one-point line draws nothing
+    let single_date = date_objects.first() == date_objects.last();
 
     // Calculate totals
+    let total_names: Vec<String> = account_names
+        .iter()
+        .filter(|name| !options.excluded_from_total.contains(name))
+        .cloned()
+        .collect();
     let totals: Vec<f64> = dates
         .iter()
         .map(|d| {
-            account_names
+            total_names
                 .iter()
                 .map(|name| {
                     all_history
@@ -80,25 +215,66 @@ pub fn plot_balances<P: AsRef<Path>>(
         })
         .fold(0.0f64, |a, b| a.max(b));
 
-    let max_total: f64 = totals.iter().cloned().fold(0.0f64, |a, b| a.max(b));
+    // Cumulative per-account layers for the stacked total panel
+    let stacked_layers = if options.stacked {
+        stack_layers(dates, all_history, &total_names)
+    } else {
+        Vec::new()
+    };
+
+    // Balance plus rewards paid so far, for rewards that leave the tracked balance
+    let totals_with_rewards: Option<Vec<f64>> = total_reward_history
+        .filter(|_| options.total_with_rewards)
+        .map(|rewards| {
+            let mut cumulative = 0.0;
+            dates
+                .iter()
+                .zip(&totals)
+                .map(|(d, total)| {
+                    cumulative += rewards.get(d).copied().unwrap_or(0.0);
+                    total + cumulative
+                })
+                .collect()
+        });
 
-    // Determine if we have reward data
-    let has_rewards = total_reward_history.is_some();
-    let graph_height = if has_rewards { 1400 } else { 1000 };
+    let max_total: f64 = totals
+        .iter()
+        .chain(stacked_layers.last().into_iter().flatten())
+        .chain(totals_with_rewards.iter().flatten())
+        .cloned()
+        .fold(0.0f64, |a, b| a.max(b));
 
-    // Create the main graph (2 or 3-panel)
+    // Determine if we have reward and price data
+    let has_rewards = total_reward_history.is_some();
+    let has_price = price_history.is_some_and(|p| !p.is_empty());
+    let balance_height = if has_rewards { 1400 } else { 1000 };
+    let graph_height = if has_price {
+        balance_height + PRICE_PANEL_HEIGHT
+    } else {
+        balance_height
+    };
+
+    // Create the main graph (2, 3 or 4-panel)
     let png_path = path.with_extension("png");
     {
         let root = BitMapBackend::new(&png_path, (1400, graph_height)).into_drawing_area();
         root.fill(&WHITE)?;
 
+        // Price panel takes a fixed strip at the bottom; the rest keeps the usual split
+        let (balance_area, price_panel) = if has_price {
+            let (balance_area, price_panel) = root.split_vertically(balance_height);
+            (balance_area, Some(price_panel))
+        } else {
+            (root.clone(), None)
+        };
+
         let panels = if has_rewards {
             // 3-panel layout: top (400), middle (400), bottom (500)
-            let (top_mid, bottom) = root.split_vertically((graph_height * 6) / 10);
-            let (upper, lower) = top_mid.split_vertically((graph_height * 3) / 10);
+            let (top_mid, bottom) = balance_area.split_vertically((balance_height * 6) / 10);
+            let (upper, lower) = top_mid.split_vertically((balance_height * 3) / 10);
             (upper, lower, Some(bottom))
         } else {
-            let (upper, lower) = root.split_vertically(500);
+            let (upper, lower) = balance_area.split_vertically(500);
             (upper, lower, None)
         };
 
@@ -111,31 +287,27 @@ pub fn plot_balances<P: AsRef<Path>>(
 
         // Upper panel: Individual balances
         {
-            let x_range = if date_objects.len() > 1 {
-                *date_objects.first().unwrap()..*date_objects.last().unwrap()
-            } else {
-                let d = date_objects[0];
-                d.pred_opt().unwrap_or(d)..d.succ_opt().unwrap_or(d)
-            };
-            let y_max = max_individual * 1.1;
+            let x_range = date_x_range(&date_objects);
+            let y_max = y_upper_bound(max_individual * 1.1);
 
             let mut chart = ChartBuilder::on(&panels.0)
                 .margin(40)
-                .x_label_area_size(30)
+                .x_label_area_size(options.x_label_area(30))
                 .y_label_area_size(80)
                 .caption("Individual Account Balances", ("sans-serif", 18))
                 .build_cartesian_2d(x_range.clone(), 0.0..y_max)?;
 
             chart
                 .configure_mesh()
-                .x_labels(12)
+                .x_labels(X_LABELS)
+                .x_label_style(options.x_label_style())
                 .y_labels(10)
                 .y_label_formatter(&|v| format_ctc(*v))
                 .draw()?;
 
             // Draw each account
             for (i, name) in account_names.iter().enumerate() {
-                let color = colors[i % colors.len()];
+                let color = options.color_for(name, i);
 
                 let data: Vec<(NaiveDate, f64)> = date_objects
                     .iter()
@@ -148,6 +320,10 @@ pub fn plot_balances<P: AsRef<Path>>(
                     })
                     .collect();
 
+                if single_date {
+                    draw_point_markers(&mut chart, &data, color)?;
+                }
+
                 chart
                     .draw_series(LineSeries::new(data, color.stroke_width(2)))?
                     .label(name)
@@ -164,34 +340,104 @@ pub fn plot_balances<P: AsRef<Path>>(
 
         // Middle panel: Total balance
         {
-            let x_range = *date_objects.first().unwrap()..*date_objects.last().unwrap();
-            let y_max = max_total * 1.1;
+            let x_range = date_x_range(&date_objects);
+            let y_max = y_upper_bound(max_total * 1.1);
 
             let mut chart = ChartBuilder::on(&panels.1)
                 .margin(40)
-                .x_label_area_size(30)
+                .x_label_area_size(options.x_label_area(30))
                 .y_label_area_size(80)
                 .caption("Total Balance Over Time", ("sans-serif", 18))
                 .build_cartesian_2d(x_range, 0.0..y_max)?;
 
             chart
                 .configure_mesh()
-                .x_labels(12)
+                .x_labels(X_LABELS)
+                .x_label_style(options.x_label_style())
                 .y_labels(10)
                 .y_label_formatter(&|v| format_ctc(*v))
                 .draw()?;
 
-            let total_data: Vec<(NaiveDate, f64)> = date_objects
-                .iter()
-                .cloned()
-                .zip(totals.iter().cloned())
-                .collect();
+            if options.stacked {
+                // One band per account between the previous layer and this one
+                let mut lower = vec![0.0; date_objects.len()];
+                // Indexed over all accounts so colors match the per-account panel
+                let stacked_names = account_names
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, name)| !options.excluded_from_total.contains(name));
+                for ((i, name), upper) in stacked_names.zip(&stacked_layers) {
+                    let color = options.color_for(name, i);
+                    let band: Vec<(NaiveDate, f64)> = date_objects
+                        .iter()
+                        .cloned()
+                        .zip(upper.iter().cloned())
+                        .chain(
+                            date_objects
+                                .iter()
+                                .cloned()
+                                .zip(lower.iter().cloned())
+                                .rev(),
+                        )
+                        .collect();
+
+                    chart
+                        .draw_series(std::iter::once(Polygon::new(band, color.mix(0.6).filled())))?
+                        .label(name)
+                        .legend(move |(x, y)| {
+                            Rectangle::new([(x, y - 5), (x + 20, y + 5)], color.filled())
+                        });
+                    lower = upper.clone();
+                }
+            } else {
+                let total_data: Vec<(NaiveDate, f64)> = date_objects
+                    .iter()
+                    .cloned()
+                    .zip(totals.iter().cloned())
+                    .collect();
 
-            // Area fill
-            chart.draw_series(AreaSeries::new(total_data.clone(), 0.0, BLUE.mix(0.3)))?;
+                // Area fill
+                chart.draw_series(AreaSeries::new(total_data.clone(), 0.0, BLUE.mix(0.3)))?;
 
-            // Line
-            chart.draw_series(LineSeries::new(total_data, BLUE.stroke_width(2)))?;
+                if single_date {
+                    draw_point_markers(&mut chart, &total_data, BLUE)?;
+                }
+
+                // Line
+                chart
+                    .draw_series(LineSeries::new(total_data, BLUE.stroke_width(2)))?
+                    .label("Balance")
+                    .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+            }
+
+            if let Some(with_rewards) = &totals_with_rewards {
+                let color = RGBColor(76, 175, 80); // Green, as the reward bars
+                let data: Vec<(NaiveDate, f64)> = date_objects
+                    .iter()
+                    .cloned()
+                    .zip(with_rewards.iter().cloned())
+                    .collect();
+
+                if single_date {
+                    draw_point_markers(&mut chart, &data, color)?;
+                }
+
+                chart
+                    .draw_series(LineSeries::new(data, color.stroke_width(2)))?
+                    .label(
+                        "Balance + cumulative rewards (as if rewards were paid out, not restaked)",
+                    )
+                    .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+            }
+
+            if options.stacked || totals_with_rewards.is_some() {
+                chart
+                    .configure_series_labels()
+                    .position(SeriesLabelPosition::UpperLeft)
+                    .background_style(WHITE.mix(0.8))
+                    .border_style(BLACK)
+                    .draw()?;
+            }
         }
 
         // Bottom panel: Daily rewards (if available)
@@ -201,26 +447,22 @@ pub fn plot_balances<P: AsRef<Path>>(
                 .map(|d| reward_data.get(d).copied().unwrap_or(0.0))
                 .collect();
 
-            let max_reward = rewards.iter().cloned().fold(0.0f64, |a, b| a.max(b)) * 1.2;
-            let max_reward = if max_reward <= 0.0 { 1.0 } else { max_reward };
+            let max_reward =
+                y_upper_bound(rewards.iter().cloned().fold(0.0f64, |a, b| a.max(b)) * 1.2);
 
-            let x_range = if date_objects.len() > 1 {
-                *date_objects.first().unwrap()..*date_objects.last().unwrap()
-            } else {
-                let d = date_objects[0];
-                d.pred_opt().unwrap_or(d)..d.succ_opt().unwrap_or(d)
-            };
+            let x_range = date_x_range(&date_objects);
 
             let mut chart = ChartBuilder::on(&bottom_panel)
                 .margin(40)
-                .x_label_area_size(30)
+                .x_label_area_size(options.x_label_area(30))
                 .y_label_area_size(80)
                 .caption("Daily Staking Rewards", ("sans-serif", 18))
                 .build_cartesian_2d(x_range, 0.0..max_reward)?;
 
             chart
                 .configure_mesh()
-                .x_labels(12)
+                .x_labels(X_LABELS)
+                .x_label_style(options.x_label_style())
                 .y_labels(10)
                 .y_label_formatter(&|v| format!("{:.2}", v))
                 .draw()?;
@@ -241,18 +483,112 @@ pub fn plot_balances<P: AsRef<Path>>(
             )?;
         }
 
+        // Price panel: total USD value, with the CTC price on the right axis
+        if let (Some(prices), Some(price_panel)) = (price_history, price_panel) {
+            let price_data: Vec<(NaiveDate, f64)> = date_objects
+                .iter()
+                .zip(dates.iter())
+                .filter_map(|(date_obj, date_str)| prices.get(date_str).map(|&p| (*date_obj, p)))
+                .collect();
+            let usd_data: Vec<(NaiveDate, f64)> = date_objects
+                .iter()
+                .zip(dates.iter())
+                .zip(totals.iter())
+                .filter_map(|((date_obj, date_str), total)| {
+                    prices.get(date_str).map(|&p| (*date_obj, total * p))
+                })
+                .collect();
+
+            let max_usd =
+                y_upper_bound(usd_data.iter().map(|(_, v)| *v).fold(0.0f64, f64::max) * 1.1);
+            let max_price =
+                y_upper_bound(price_data.iter().map(|(_, v)| *v).fold(0.0f64, f64::max) * 1.1);
+
+            let mut chart = ChartBuilder::on(&price_panel)
+                .margin(40)
+                .x_label_area_size(options.x_label_area(30))
+                .y_label_area_size(80)
+                .right_y_label_area_size(80)
+                .caption("Portfolio Value (USD) and CTC Price", ("sans-serif", 18))
+                .build_cartesian_2d(date_x_range(&date_objects), 0.0..max_usd)?
+                .set_secondary_coord(date_x_range(&date_objects), 0.0..max_price);
+
+            chart
+                .configure_mesh()
+                .x_labels(X_LABELS)
+                .x_label_style(options.x_label_style())
+                .y_labels(10)
+                .y_desc("USD value")
+                .y_label_formatter(&|v| format!("${:.0}", v))
+                .draw()?;
+            chart
+                .configure_secondary_axes()
+                .y_desc("CTC price")
+                .y_label_formatter(&|v| format!("${:.3}", v))
+                .draw()?;
+
+            let usd_color = RGBColor(0, 128, 128); // Teal
+            let price_color = RGBColor(255, 140, 0); // Dark orange
+
+            if single_date {
+                chart.draw_series(
+                    usd_data
+                        .iter()
+                        .map(|&point| Circle::new(point, 5, usd_color.filled())),
+                )?;
+                chart.draw_secondary_series(
+                    price_data
+                        .iter()
+                        .map(|&point| Circle::new(point, 5, price_color.filled())),
+                )?;
+            }
+
+            chart
+                .draw_series(LineSeries::new(usd_data, usd_color.stroke_width(2)))?
+                .label("Total value (USD)")
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], usd_color));
+            chart
+                .draw_secondary_series(LineSeries::new(price_data, price_color.stroke_width(2)))?
+                .label("CTC price (USD)")
+                .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], price_color));
+
+            chart
+                .configure_series_labels()
+                .position(SeriesLabelPosition::UpperLeft)
+                .background_style(WHITE.mix(0.8))
+                .border_style(BLACK)
+                .draw()?;
+        }
+
         root.present()?;
     }
     generated_files.push(png_path);
 
+    if options.plot_data {
+        let data_path = save_plot_data(
+            path,
+            dates,
+            all_history,
+            account_names,
+            &totals,
+            total_reward_history,
+            totals_with_rewards.as_deref(),
+            price_history.filter(|_| has_price),
+        )?;
+        generated_files.push(data_path);
+    }
+
     // Create individual graphs
-    let individual_dir = path.parent().unwrap_or(Path::new(".")).join("individual");
+    let individual_dir = options
+        .individual_dir
+        .clone()
+        .unwrap_or_else(|| path.parent().unwrap_or(Path::new(".")).join("individual"));
     fs::create_dir_all(&individual_dir)?;
 
     for (i, name) in account_names.iter().enumerate() {
         let individual_path = individual_dir.join(format!("{}.png", name));
         let individual_path_clone = individual_path.clone();
-        let color = colors[i % colors.len()];
+        let color = options.color_for(name, i);
 
         let balances: Vec<f64> = dates
             .iter()
@@ -266,6 +602,7 @@ pub fn plot_balances<P: AsRef<Path>>(
             .collect();
 
         let max_balance = balances.iter().cloned().fold(0.0f64, |a, b| a.max(b)) * 1.1;
+        let y_max = y_upper_bound(max_balance);
 
         // Check if this account has any reward data
         let has_individual_rewards = individual_reward_history
@@ -286,12 +623,7 @@ pub fn plot_balances<P: AsRef<Path>>(
         let root = BitMapBackend::new(&individual_path, (1200, graph_height)).into_drawing_area();
         root.fill(&WHITE)?;
 
-        let x_range = if date_objects.len() > 1 {
-            *date_objects.first().unwrap()..*date_objects.last().unwrap()
-        } else {
-            let d = date_objects[0];
-            d.pred_opt().unwrap_or(d)..d.succ_opt().unwrap_or(d)
-        };
+        let x_range = date_x_range(&date_objects);
 
         if has_account_rewards {
             // 2-panel layout: balance on top, reward on bottom
@@ -301,17 +633,18 @@ pub fn plot_balances<P: AsRef<Path>>(
             {
                 let mut chart = ChartBuilder::on(&upper)
                     .margin(40)
-                    .x_label_area_size(40)
+                    .x_label_area_size(options.x_label_area(40))
                     .y_label_area_size(80)
                     .caption(
                         format!("CTC Balance History - {}", name),
                         ("sans-serif", 20),
                     )
-                    .build_cartesian_2d(x_range.clone(), 0.0..max_balance)?;
+                    .build_cartesian_2d(x_range.clone(), 0.0..y_max)?;
 
                 chart
                     .configure_mesh()
-                    .x_labels(12)
+                    .x_labels(X_LABELS)
+                    .x_label_style(options.x_label_style())
                     .y_labels(10)
                     .y_label_formatter(&|v| format_ctc(*v))
                     .draw()?;
@@ -322,6 +655,10 @@ pub fn plot_balances<P: AsRef<Path>>(
                     .zip(balances.iter().cloned())
                     .collect();
 
+                if single_date {
+                    draw_point_markers(&mut chart, &data, color)?;
+                }
+
                 // Area fill
                 chart.draw_series(AreaSeries::new(data.clone(), 0.0, color.mix(0.3)))?;
 
@@ -341,12 +678,12 @@ pub fn plot_balances<P: AsRef<Path>>(
                     .map(|d| reward_map.get(d).copied().unwrap_or(0.0))
                     .collect();
 
-                let max_reward = rewards.iter().cloned().fold(0.0f64, |a, b| a.max(b)) * 1.2;
-                let max_reward = if max_reward <= 0.0 { 1.0 } else { max_reward };
+                let max_reward =
+                    y_upper_bound(rewards.iter().cloned().fold(0.0f64, |a, b| a.max(b)) * 1.2);
 
                 let mut chart = ChartBuilder::on(&lower)
                     .margin(40)
-                    .x_label_area_size(40)
+                    .x_label_area_size(options.x_label_area(40))
                     .y_label_area_size(80)
                     .caption(
                         format!("Daily Staking Rewards - {}", name),
@@ -356,7 +693,8 @@ pub fn plot_balances<P: AsRef<Path>>(
 
                 chart
                     .configure_mesh()
-                    .x_labels(12)
+                    .x_labels(X_LABELS)
+                    .x_label_style(options.x_label_style())
                     .y_labels(8)
                     .y_label_formatter(&|v| format!("{:.2}", v))
                     .draw()?;
@@ -380,17 +718,18 @@ pub fn plot_balances<P: AsRef<Path>>(
             // Single panel: Balance only
             let mut chart = ChartBuilder::on(&root)
                 .margin(40)
-                .x_label_area_size(40)
+                .x_label_area_size(options.x_label_area(40))
                 .y_label_area_size(80)
                 .caption(
                     format!("CTC Balance History - {}", name),
                     ("sans-serif", 20),
                 )
-                .build_cartesian_2d(x_range, 0.0..max_balance)?;
+                .build_cartesian_2d(x_range, 0.0..y_max)?;
 
             chart
                 .configure_mesh()
-                .x_labels(12)
+                .x_labels(X_LABELS)
+                .x_label_style(options.x_label_style())
                 .y_labels(10)
                 .y_label_formatter(&|v| format_ctc(*v))
                 .draw()?;
@@ -401,6 +740,10 @@ pub fn plot_balances<P: AsRef<Path>>(
                 .zip(balances.iter().cloned())
                 .collect();
 
+            if single_date {
+                draw_point_markers(&mut chart, &data, color)?;
+            }
+
             // Area fill
             chart.draw_series(AreaSeries::new(data.clone(), 0.0, color.mix(0.3)))?;
 
@@ -415,6 +758,360 @@ pub fn plot_balances<P: AsRef<Path>>(
     Ok(generated_files)
 }
 
+/// Generate the daily balance change graph (`<stem>_diff.png`)
+///
+/// Each entry's `diff` is drawn as a bar from zero: green for inflows, red for
+/// outflows. The reward income part of each change (`diff - principal_flow`)
+/// is drawn over the bars as a blue line.
+pub fn plot_diffs<P: AsRef<Path>>(
+    output_file: P,
+    entries: &[HistoryEntry],
+    source_name: &str,
+    options: &PlotOptions,
+) -> Result<Option<std::path::PathBuf>> {
+    let path = output_file.as_ref();
+
+    let data: Vec<(NaiveDate, f64)> = entries
+        .iter()
+        .filter_map(|e| {
+            NaiveDate::parse_from_str(&e.date, "%Y-%m-%d")
+                .ok()
+                .map(|d| (d, e.diff))
+        })
+        .collect();
+    if data.is_empty() {
+        return Ok(None);
+    }
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let png_path = crate::sidecar_file(path, "diff.png");
+
+    let date_objects: Vec<NaiveDate> = data.iter().map(|(d, _)| *d).collect();
+    let max_up = data.iter().map(|(_, v)| *v).fold(0.0f64, f64::max);
+    let max_down = data.iter().map(|(_, v)| -*v).fold(0.0f64, f64::max);
+    // Keep some room on both sides of zero even when all changes share a sign
+    let y_top = y_upper_bound(max_up * 1.1);
+    let y_bottom = -y_upper_bound(max_down * 1.1);
+
+    {
+        let root = BitMapBackend::new(&png_path, (1400, 600)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(40)
+            .x_label_area_size(options.x_label_area(40))
+            .y_label_area_size(80)
+            .caption(
+                format!("Daily Balance Change - {}", source_name),
+                ("sans-serif", 22),
+            )
+            .build_cartesian_2d(date_x_range(&date_objects), y_bottom..y_top)?;
+
+        chart
+            .configure_mesh()
+            .x_labels(X_LABELS)
+            .x_label_style(options.x_label_style())
+            .y_labels(10)
+            .y_label_formatter(&|v| format_ctc(*v))
+            .draw()?;
+
+        let up = RGBColor(76, 175, 80); // Green
+        let down = RGBColor(214, 39, 40); // Red
+        chart.draw_series(data.iter().filter(|(_, v)| *v != 0.0).map(|&(date, diff)| {
+            let color = if diff > 0.0 { up } else { down };
+            let x1 = date.succ_opt().unwrap_or(date);
+            Rectangle::new([(date, 0.0), (x1, diff)], color.filled())
+        }))?;
+
+        let income: Vec<(NaiveDate, f64)> = entries
+            .iter()
+            .filter_map(|e| {
+                NaiveDate::parse_from_str(&e.date, "%Y-%m-%d")
+                    .ok()
+                    .map(|d| (d, e.diff - e.principal_flow))
+            })
+            .collect();
+        if income.iter().any(|(_, v)| *v != 0.0) {
+            let blue = RGBColor(31, 119, 180);
+            chart.draw_series(LineSeries::new(income, blue.stroke_width(2)))?;
+        }
+
+        // Zero baseline
+        let x_range = date_x_range(&date_objects);
+        chart.draw_series(LineSeries::new(
+            [(x_range.start, 0.0), (x_range.end, 0.0)],
+            BLACK.stroke_width(1),
+        ))?;
+
+        root.present()?;
+    }
+
+    Ok(Some(png_path))
+}
+
+/// Generate the group subtotal graph (`<stem>_groups.png`), one line per group
+pub fn plot_groups<P: AsRef<Path>>(
+    output_file: P,
+    entries: &[HistoryEntry],
+    source_name: &str,
+    options: &PlotOptions,
+) -> Result<Option<PathBuf>> {
+    let path = output_file.as_ref();
+
+    let dated: Vec<(NaiveDate, &HistoryEntry)> = entries
+        .iter()
+        .filter_map(|e| {
+            NaiveDate::parse_from_str(&e.date, "%Y-%m-%d")
+                .ok()
+                .map(|d| (d, e))
+        })
+        .collect();
+    let mut groups: Vec<&String> = dated
+        .iter()
+        .flat_map(|(_, e)| e.group_totals.keys())
+        .collect();
+    groups.sort();
+    groups.dedup();
+    if dated.is_empty() || groups.is_empty() {
+        return Ok(None);
+    }
+
+    // Ensure parent directory exists
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create output directory")?;
+    }
+
+    let png_path = crate::sidecar_file(path, "groups.png");
+
+    let date_objects: Vec<NaiveDate> = dated.iter().map(|(d, _)| *d).collect();
+    let max_total = dated
+        .iter()
+        .flat_map(|(_, e)| e.group_totals.values().copied())
+        .fold(0.0f64, f64::max);
+
+    {
+        let root = BitMapBackend::new(&png_path, (1400, 600)).into_drawing_area();
+        root.fill(&WHITE)?;
+
+        let mut chart = ChartBuilder::on(&root)
+            .margin(40)
+            .x_label_area_size(options.x_label_area(40))
+            .y_label_area_size(80)
+            .caption(
+                format!("CTC Balance by Group - {}", source_name),
+                ("sans-serif", 22),
+            )
+            .build_cartesian_2d(
+                date_x_range(&date_objects),
+                0.0..y_upper_bound(max_total * 1.1),
+            )?;
+
+        chart
+            .configure_mesh()
+            .x_labels(X_LABELS)
+            .x_label_style(options.x_label_style())
+            .y_labels(10)
+            .y_label_formatter(&|v| format_ctc(*v))
+            .draw()?;
+
+        for (i, group) in groups.iter().enumerate() {
+            let color = SERIES_COLORS[i % SERIES_COLORS.len()];
+            let data: Vec<(NaiveDate, f64)> = dated
+                .iter()
+                .filter_map(|(d, e)| e.group_totals.get(*group).map(|&v| (*d, v)))
+                .collect();
+            if date_objects.len() == 1 {
+                draw_point_markers(&mut chart, &data, color)?;
+            }
+            chart
+                .draw_series(LineSeries::new(data, color.stroke_width(2)))?
+                .label(group.as_str())
+                .legend(move |(x, y)| {
+                    PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+                });
+        }
+
+        chart
+            .configure_series_labels()
+            .background_style(WHITE.mix(0.8))
+            .border_style(BLACK)
+            .draw()?;
+
+        root.present()?;
+    }
+
+    Ok(Some(png_path))
+}
+
+/// X-axis range covering all dates.
+///
+/// A single date (or a range whose ends coincide) is widened by one day on each
+/// side, since plotters can't build a zero-width coordinate range.
+fn date_x_range(date_objects: &[NaiveDate]) -> DateAxis {
+    let first = date_objects[0];
+    let last = date_objects[date_objects.len() - 1];
+    if first < last {
+        DateAxis::new(first, last)
+    } else {
+        DateAxis::new(
+            first.pred_opt().unwrap_or(first),
+            last.succ_opt().unwrap_or(last),
+        )
+    }
+}
+
+/// Spacing of date ticks
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TickStep {
+    Days(u32),
+    /// Calendar months; 3 for quarters, 12 for years
+    Months(u32),
+}
+
+/// Date x-axis with ticks on calendar boundaries chosen by the span: days
+/// up to two months, months under two years, quarters under four, years
+/// beyond. Labels are abbreviated to match.
+#[derive(Clone)]
+struct DateAxis {
+    start: NaiveDate,
+    end: NaiveDate,
+    step: TickStep,
+}
+
+impl DateAxis {
+    fn new(start: NaiveDate, end: NaiveDate) -> Self {
+        let days = (end - start).num_days().max(1) as u32;
+        let step = match days {
+            0..=62 => TickStep::Days(days.div_ceil(X_LABELS as u32)),
+            63..=729 => TickStep::Months(1),
+            730..=1460 => TickStep::Months(3),
+            _ => TickStep::Months(12),
+        };
+        Self { start, end, step }
+    }
+
+    /// Every tick in the range, before thinning
+    fn ticks(&self) -> Vec<NaiveDate> {
+        match self.step {
+            TickStep::Days(n) => self
+                .start
+                .iter_days()
+                .step_by(n.max(1) as usize)
+                .take_while(|d| *d <= self.end)
+                .collect(),
+            TickStep::Months(n) => {
+                // First month boundary on or after the start that is a multiple
+                // of the step (Jan/Apr/Jul/Oct for quarters, Jan for years)
+                let mut date = self.start.with_day(1).unwrap_or(self.start);
+                if date < self.start {
+                    date = date + Months::new(1);
+                }
+                while !date.month0().is_multiple_of(n) {
+                    date = date + Months::new(1);
+                }
+                let mut ticks = Vec::new();
+                while date <= self.end {
+                    ticks.push(date);
+                    date = date + Months::new(n);
+                }
+                ticks
+            }
+        }
+    }
+}
+
+impl Ranged for DateAxis {
+    type FormatOption = NoDefaultFormatting;
+    type ValueType = NaiveDate;
+
+    fn map(&self, value: &NaiveDate, limit: (i32, i32)) -> i32 {
+        RangedDate::from(self.start..self.end).map(value, limit)
+    }
+
+    fn key_points<Hint: KeyPointHint>(&self, hint: Hint) -> Vec<NaiveDate> {
+        let ticks = self.ticks();
+        let max = hint.max_num_points().max(1);
+        let every = ticks.len().div_ceil(max).max(1);
+        ticks.into_iter().step_by(every).collect()
+    }
+
+    fn range(&self) -> std::ops::Range<NaiveDate> {
+        self.start..self.end
+    }
+}
+
+impl ValueFormatter<NaiveDate> for DateAxis {
+    fn format_ext(&self, value: &NaiveDate) -> String {
+        let format = match self.step {
+            TickStep::Days(_) => "%b %d",
+            TickStep::Months(12) => "%Y",
+            TickStep::Months(_) => "%b %Y",
+        };
+        value.format(format).to_string()
+    }
+}
+
+/// Upper Y bound, falling back to 1.0 when all values are zero
+fn y_upper_bound(max: f64) -> f64 {
+    if max <= 0.0 {
+        1.0
+    } else {
+        max
+    }
+}
+
+/// Draw circle markers with value labels, for series too short to draw as a line
+fn draw_point_markers<DB: DrawingBackend>(
+    chart: &mut ChartContext<'_, DB, Cartesian2d<DateAxis, RangedCoordf64>>,
+    data: &[(NaiveDate, f64)],
+    color: RGBColor,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    chart.draw_series(data.iter().map(|&(date, value)| {
+        EmptyElement::at((date, value))
+            + Circle::new((0, 0), 5, color.filled())
+            + Text::new(
+                format!("{} CTC", format_ctc(value)),
+                (8, -16),
+                ("sans-serif", 14).into_font(),
+            )
+    }))?;
+    Ok(())
+}
+
+/// Build cumulative stacked layers (one per account, in `account_names` order).
+///
+/// Missing dates carry the account's last known balance forward so a gap in
+/// one account's history doesn't make the whole stack dip to zero.
+fn stack_layers(
+    dates: &[String],
+    all_history: &HashMap<String, HashMap<String, f64>>,
+    account_names: &[String],
+) -> Vec<Vec<f64>> {
+    let mut layers = Vec::with_capacity(account_names.len());
+    let mut running = vec![0.0; dates.len()];
+
+    for name in account_names {
+        let history = all_history.get(name);
+        let mut last = 0.0;
+        for (i, date) in dates.iter().enumerate() {
+            if let Some(&v) = history.and_then(|h| h.get(date)) {
+                last = v;
+            }
+            running[i] += last;
+        }
+        layers.push(running.clone());
+    }
+
+    layers
+}
+
 /// Format CTC amount with commas
 fn format_ctc(amount: f64) -> String {
     let formatted = format!("{:.0}", amount);
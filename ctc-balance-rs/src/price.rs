@@ -1,13 +1,27 @@
 use anyhow::{anyhow, Result};
 use std::collections::HashMap;
+use std::time::Duration;
+
+/// Timeout for a CoinGecko request, so a stalled API can't hold up the run
+const PRICE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Client for CoinGecko requests, through the `--proxy` proxy if one is set
+/// (reqwest honors `HTTPS_PROXY` on its own)
+fn http_client() -> Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent("ctc-balance-tracker/0.1.0")
+        .timeout(PRICE_TIMEOUT);
+    if let Some(proxy) = crate::proxy::explicit_proxy() {
+        builder = builder.proxy(reqwest::Proxy::all(proxy.clone())?);
+    }
+    Ok(builder.build()?)
+}
 
 /// Fetch the current CTC price in USD from CoinGecko
 pub async fn fetch_ctc_price() -> Result<f64> {
     let url = "https://api.coingecko.com/api/v3/simple/price?ids=creditcoin-2&vs_currencies=usd";
 
-    let client = reqwest::Client::builder()
-        .user_agent("ctc-balance-tracker/0.1.0")
-        .build()?;
+    let client = http_client()?;
 
     let response = client.get(url).send().await?;
 
@@ -31,3 +45,48 @@ pub async fn fetch_ctc_price() -> Result<f64> {
             )
         })
 }
+
+/// Fetch daily CTC prices in USD from CoinGecko, keyed by date (YYYY-MM-DD)
+///
+/// CoinGecko's free tier only serves the last 365 days of daily data, so older
+/// dates are simply absent from the returned map.
+pub async fn fetch_ctc_price_history(days: u32) -> Result<HashMap<String, f64>> {
+    let url = format!(
+        "https://api.coingecko.com/api/v3/coins/creditcoin-2/market_chart?vs_currency=usd&days={}&interval=daily",
+        days.clamp(1, 365)
+    );
+
+    let client = http_client()?;
+
+    let response = client.get(&url).send().await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch price history from CoinGecko: {}",
+            response.status()
+        ));
+    }
+
+    // Response format: {"prices": [[1725062400000, 0.262483], ...], ...}
+    let data: HashMap<String, serde_json::Value> = response.json().await?;
+    let points = data
+        .get("prices")
+        .and_then(|p| p.as_array())
+        .ok_or_else(|| anyhow!("Price history missing from CoinGecko response"))?;
+
+    let mut prices = HashMap::new();
+    for point in points {
+        let (Some(ms), Some(price)) = (
+            point.get(0).and_then(|v| v.as_i64()),
+            point.get(1).and_then(|v| v.as_f64()),
+        ) else {
+            continue;
+        };
+        if let Some(time) = chrono::DateTime::from_timestamp_millis(ms) {
+            // Later points for the same day (e.g. the live price) overwrite earlier ones
+            prices.insert(time.format("%Y-%m-%d").to_string(), price);
+        }
+    }
+
+    Ok(prices)
+}
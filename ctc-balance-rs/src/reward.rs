@@ -4,34 +4,181 @@
 
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use subxt::{
     backend::{legacy::LegacyRpcMethods, rpc::RpcClient},
     ext::scale_value::{Composite, Primitive, Value, ValueDef},
     OnlineClient, PolkadotConfig,
 };
 
-use crate::CTC_DIVISOR;
+use crate::{CtcError, CTC_DIVISOR};
 
 /// Staking reward data for an account
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StakingReward {
-    /// Claimed reward
+    /// Claimed reward (actually paid, from `Rewarded` events)
     pub claimed: f64,
+    /// Earned reward (era share computed from exposures, paid out or not)
+    #[serde(default)]
+    pub earned: f64,
+    /// Where the reward went, when it was not simply the stash's free balance
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payee: Option<String>,
+    /// Earned reward per validator (SS58 address) that produced it
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub by_validator: BTreeMap<String, f64>,
 }
 
 impl StakingReward {
     /// Create a zero reward
     pub fn zero() -> Self {
-        Self { claimed: 0.0 }
+        Self {
+            claimed: 0.0,
+            earned: 0.0,
+            payee: None,
+            by_validator: BTreeMap::new(),
+        }
+    }
+
+    /// Reward amount under the given basis
+    pub fn amount(&self, basis: RewardBasis) -> f64 {
+        match basis {
+            RewardBasis::Earned => self.earned,
+            RewardBasis::Claimed => self.claimed,
+        }
+    }
+}
+
+/// Reward points and payout of one era (`ErasRewardPoints`, `ErasValidatorReward`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct EraPoints {
+    pub era: u32,
+    pub total_points: f64,
+    /// Total validator payout of the era (CTC)
+    pub validator_reward: f64,
+    /// Points per validator (SS58 address)
+    pub validators: BTreeMap<String, f64>,
+}
+
+/// Which reward figure drives the output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RewardBasis {
+    /// Era share computed from exposures (fast, includes unclaimed payouts)
+    #[default]
+    Earned,
+    /// Actually paid rewards from events (requires scanning every block)
+    Claimed,
+}
+
+impl std::str::FromStr for RewardBasis {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "earned" => Ok(Self::Earned),
+            "claimed" => Ok(Self::Claimed),
+            _ => Err(format!(
+                "Invalid reward basis '{}': expected earned or claimed",
+                s
+            )),
+        }
+    }
+}
+
+/// Staking role of an account at a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccountRole {
+    /// Registered validator (`Staking.Validators`); earns commission plus its own stake share
+    Validator,
+    /// Nominator (`Staking.Nominators`); earns only its share of backed validators' rewards
+    Nominator,
+    /// Neither; no staking rewards expected
+    None,
+}
+
+impl std::fmt::Display for AccountRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Validator => "validator",
+            Self::Nominator => "nominator",
+            Self::None => "none",
+        })
+    }
+}
+
+/// Where a stash's staking rewards are paid (`Staking.Payee`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RewardDestination {
+    /// Paid to the stash and bonded (raises reserved, not free)
+    Staked,
+    /// Paid to the stash's free balance
+    Stash,
+    /// Paid to the controller account
+    Controller,
+    /// Paid to an arbitrary account
+    Account([u8; 32]),
+    /// Not paid out
+    None,
+}
+
+/// Storage layout used for era exposures.
+///
+/// Staking moved from `ErasStakersClipped` to `ErasStakersOverview` +
+/// `ErasStakersPaged` in a runtime upgrade, so which item holds an era's
+/// exposures depends on the runtime that was active for that era.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExposureLayout {
+    /// `ErasStakersOverview` with nominators in `ErasStakersPaged`
+    Paged,
+    /// Legacy `ErasStakersClipped` with inline nominators
+    Clipped,
+}
+
+impl ExposureLayout {
+    /// The layout to fall back to when this one holds nothing
+    fn other(self) -> Self {
+        match self {
+            ExposureLayout::Paged => ExposureLayout::Clipped,
+            ExposureLayout::Clipped => ExposureLayout::Paged,
+        }
     }
 }
 
+/// Shared inputs for processing the eras of one block range
+struct EraScan<'a> {
+    /// Block at which the current era's storage is read
+    at_hash: subxt::utils::H256,
+    client: &'a OnlineClient<PolkadotConfig>,
+    /// Tracked account id -> account name
+    account_map: &'a HashMap<[u8; 32], String>,
+    /// Total and per-validator reward points of the current era, read once
+    reward_points: Option<(f64, HashMap<[u8; 32], f64>)>,
+    /// Validators worth fetching exposures for in the current era
+    /// (`backed_validators` of every tracked account)
+    backed: HashSet<[u8; 32]>,
+}
+
 /// Reward tracker for Creditcoin3 accounts
 pub struct RewardTracker {
     url: String,
     client: Option<OnlineClient<PolkadotConfig>>,
     rpc: Option<LegacyRpcMethods<PolkadotConfig>>,
+    /// Exposure layout observed per runtime spec version
+    exposure_layouts: HashMap<u32, ExposureLayout>,
+    /// Known spec version per block number (from the block cache)
+    spec_versions: BTreeMap<u64, u32>,
+    /// Largest block range the event-scanning fallback will accept
+    max_scan_blocks: Option<u64>,
+    /// Divisor for native amounts (see `ChainConnector::divisor`)
+    divisor: f64,
+    /// Blocks this close to the best head are only scanned once finalized (0 = off)
+    finality_margin: u64,
+    /// Keep each processed era's points for `take_era_points`
+    record_era_points: bool,
+    /// Address prefix of validator and payee addresses in results
+    ss58_format: u16,
+    era_points: BTreeMap<u32, EraPoints>,
 }
 
 impl RewardTracker {
@@ -41,9 +188,67 @@ impl RewardTracker {
             url: url.to_string(),
             client: None,
             rpc: None,
+            exposure_layouts: HashMap::new(),
+            spec_versions: BTreeMap::new(),
+            max_scan_blocks: None,
+            divisor: CTC_DIVISOR,
+            finality_margin: 0,
+            record_era_points: false,
+            ss58_format: crate::DEFAULT_SS58_PREFIX,
+            era_points: BTreeMap::new(),
         }
     }
 
+    /// Create a tracker on clients that are already connected (see `rpc_client_from_url`)
+    pub fn from_clients(
+        client: OnlineClient<PolkadotConfig>,
+        rpc: LegacyRpcMethods<PolkadotConfig>,
+    ) -> Self {
+        let mut tracker = Self::new("");
+        tracker.client = Some(client);
+        tracker.rpc = Some(rpc);
+        tracker
+    }
+
+    /// Set the divisor for native amounts (defaults to `CTC_DIVISOR`)
+    pub fn set_divisor(&mut self, divisor: f64) {
+        self.divisor = divisor;
+    }
+
+    /// Set the address prefix results are written with (see `ChainConnector::ss58_format`)
+    pub fn set_ss58_format(&mut self, ss58_format: u16) {
+        self.ss58_format = ss58_format;
+    }
+
+    /// Cap the block range scanned by `get_all_rewards_in_range` (`None` = unlimited)
+    pub fn set_max_scan_blocks(&mut self, max: Option<u64>) {
+        self.max_scan_blocks = max;
+    }
+
+    /// Only scan events of blocks within `margin` of the best head once they are
+    /// finalized, so reorgs can't change reward totals between runs (0 = off)
+    pub fn set_finality_margin(&mut self, margin: u64) {
+        self.finality_margin = margin;
+    }
+
+    /// Keep the reward points of every era the era path processes (eras with
+    /// zero total points are skipped)
+    pub fn set_record_era_points(&mut self, record: bool) {
+        self.record_era_points = record;
+    }
+
+    /// Seed the spec versions of known blocks (`BlockInfo::spec_version` of the
+    /// block cache), so blocks between two of them with the same version need
+    /// no runtime version query
+    pub fn set_spec_versions(&mut self, versions: impl IntoIterator<Item = (u64, u32)>) {
+        self.spec_versions.extend(versions);
+    }
+
+    /// Era points recorded since the last call, by era
+    pub fn take_era_points(&mut self) -> Vec<EraPoints> {
+        std::mem::take(&mut self.era_points).into_values().collect()
+    }
+
     /// Set the online client (injection for tracker reuse)
     pub fn set_client(&mut self, client: OnlineClient<PolkadotConfig>) {
         self.client = Some(client);
@@ -51,13 +256,15 @@ impl RewardTracker {
 
     /// Connect to the node
     pub async fn connect(&mut self) -> Result<()> {
-        let rpc_client = RpcClient::from_url(&self.url)
-            .await
-            .context("Failed to connect to RPC")?;
+        let rpc_client = crate::rpc_client_from_url(&self.url).await?;
+        self.connect_with(rpc_client).await
+    }
 
+    /// Connect over an existing RPC connection instead of opening one from the URL
+    pub async fn connect_with(&mut self, rpc_client: RpcClient) -> Result<()> {
         let client = OnlineClient::<PolkadotConfig>::from_rpc_client(rpc_client.clone())
             .await
-            .context("Failed to create online client")?;
+            .map_err(|e| CtcError::Connection(format!("Failed to create online client: {}", e)))?;
 
         let rpc = LegacyRpcMethods::<PolkadotConfig>::new(rpc_client);
 
@@ -81,28 +288,52 @@ impl RewardTracker {
 
     /// Get the client
     pub fn client(&self) -> Result<&OnlineClient<PolkadotConfig>> {
-        self.client
-            .as_ref()
-            .context("Not connected. Call connect() first.")
+        self.client.as_ref().ok_or_else(|| {
+            CtcError::Connection("Not connected. Call connect() first.".into()).into()
+        })
     }
 
     /// Get the RPC
     pub fn rpc(&self) -> Result<&LegacyRpcMethods<PolkadotConfig>> {
-        self.rpc
-            .as_ref()
-            .context("Not connected. Call connect() first.")
+        self.rpc.as_ref().ok_or_else(|| {
+            CtcError::Connection("Not connected. Call connect() first.".into()).into()
+        })
     }
 
     /// Get block hash for a block number
     pub async fn get_block_hash(&self, block_number: u64) -> Result<subxt::utils::H256> {
         let rpc = self.rpc()?;
-        let hash = rpc
-            .chain_get_block_hash(Some(block_number.into()))
-            .await?
-            .context(format!("Block {} not found", block_number))?;
+        let hash = crate::retry!(rpc.chain_get_block_hash(Some(block_number.into())))?
+            .ok_or_else(|| CtcError::NotFound(format!("Block {} not found", block_number)))?;
         Ok(hash)
     }
 
+    /// Get the runtime spec version active at a block
+    pub async fn spec_version_at(&self, block_hash: subxt::utils::H256) -> Result<u32> {
+        let rpc = self.rpc()?;
+        let version = crate::retry!(rpc.state_get_runtime_version(Some(block_hash)))?;
+        Ok(version.spec_version)
+    }
+
+    /// Spec version at `block`: taken from the known versions when the nearest
+    /// known blocks on both sides agree, queried otherwise. `None` if the query fails.
+    async fn spec_version_for(
+        &mut self,
+        block: u64,
+        block_hash: subxt::utils::H256,
+    ) -> Option<u32> {
+        let before = self.spec_versions.range(..=block).next_back();
+        let after = self.spec_versions.range(block..).next();
+        if let (Some((_, &a)), Some((_, &b))) = (before, after) {
+            if a == b {
+                return Some(a);
+            }
+        }
+        let version = self.spec_version_at(block_hash).await.ok()?;
+        self.spec_versions.insert(block, version);
+        Some(version)
+    }
+
     /// Get active era at a specific block hash
     pub async fn get_active_era(&self, block_hash: subxt::utils::H256) -> Result<u32> {
         let client = self.client()?;
@@ -121,18 +352,314 @@ impl RewardTracker {
                 }
             }
         }
-        anyhow::bail!("ActiveEra not found at block {:?}", block_hash)
+        Err(CtcError::NotFound(format!("ActiveEra at block {:?}", block_hash)).into())
+    }
+
+    /// First block in `(low, high]` whose active era is past `era`.
+    ///
+    /// Staking records an era's total payout (`ErasValidatorReward`) in the block
+    /// that starts the next era, so this is the earliest block at which the era's
+    /// storage is complete. The active era at `low` must be `era` or earlier and
+    /// the one at `high` must be later.
+    async fn era_payout_block(&self, era: u32, low: u64, high: u64) -> Result<u64> {
+        let (mut lo, mut hi) = (low, high);
+        while hi - lo > 1 {
+            let mid = lo + (hi - lo) / 2;
+            let hash = self.get_block_hash(mid).await?;
+            if self.get_active_era(hash).await? > era {
+                hi = mid;
+            } else {
+                lo = mid;
+            }
+        }
+        Ok(hi)
+    }
+
+    /// Get the validators an account nominates (`Staking.Nominators`) at a block
+    pub async fn get_nominations(
+        &self,
+        account: [u8; 32],
+        block_hash: subxt::utils::H256,
+    ) -> Result<Vec<[u8; 32]>> {
+        let client = self.client()?;
+        let storage_address = subxt::dynamic::storage(
+            "Staking",
+            "Nominators",
+            vec![subxt::dynamic::Value::from_bytes(account)],
+        );
+        let storage_value = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?;
+
+        let mut targets = Vec::new();
+        if let Some(value) = storage_value {
+            let decoded = value.to_value()?;
+            if let ValueDef::Composite(Composite::Named(fields)) = decoded.value {
+                for (name, field) in fields {
+                    if name == "targets" {
+                        targets = parse_account_list(&field);
+                    }
+                }
+            }
+        }
+        Ok(targets)
+    }
+
+    /// Get the validators an account backs in `era`: its `Staking.Nominators`
+    /// targets (read at `block_hash`) that are in the era's active set, plus the
+    /// account itself if it validated in that era.
+    ///
+    /// The active set is taken from `ErasRewardPoints`, so an era with no points
+    /// recorded yet yields no validators.
+    pub async fn get_nominated_validators(
+        &self,
+        account: [u8; 32],
+        era: u32,
+        block_hash: subxt::utils::H256,
+    ) -> Result<Vec<[u8; 32]>> {
+        match self.get_era_reward_points(era, block_hash).await? {
+            Some((_, active)) => self.backed_validators(account, &active, block_hash).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// `get_nominated_validators` against an already fetched active set
+    async fn backed_validators(
+        &self,
+        account: [u8; 32],
+        active: &HashMap<[u8; 32], f64>,
+        block_hash: subxt::utils::H256,
+    ) -> Result<Vec<[u8; 32]>> {
+        let mut validators: Vec<[u8; 32]> = self
+            .get_nominations(account, block_hash)
+            .await?
+            .into_iter()
+            .filter(|v| active.contains_key(v))
+            .collect();
+        if active.contains_key(&account) && !validators.contains(&account) {
+            validators.push(account);
+        }
+        Ok(validators)
+    }
+
+    /// Reward points of an era (`Staking.ErasRewardPoints`): total and per validator
+    async fn get_era_reward_points(
+        &self,
+        era: u32,
+        block_hash: subxt::utils::H256,
+    ) -> Result<Option<(f64, HashMap<[u8; 32], f64>)>> {
+        let client = self.client()?;
+        let points_addr = subxt::dynamic::storage(
+            "Staking",
+            "ErasRewardPoints",
+            vec![subxt::dynamic::Value::u128(era as u128)],
+        );
+        match crate::retry!(client.storage().at(block_hash).fetch(&points_addr))? {
+            Some(v) => Ok(Some(parse_reward_points_def(v.to_value()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Staking role of each account at a block; accounts with an invalid address
+    /// are left out
+    pub async fn get_account_roles(
+        &self,
+        accounts: &HashMap<String, String>,
+        block_hash: subxt::utils::H256,
+    ) -> Result<HashMap<String, AccountRole>> {
+        let mut roles = HashMap::new();
+        for (name, address) in accounts {
+            let Ok(id) = crate::parse_ss58_address(address) else {
+                continue;
+            };
+            let role = if self.is_validator(id.0, block_hash).await? {
+                AccountRole::Validator
+            } else if !self.get_nominations(id.0, block_hash).await?.is_empty() {
+                AccountRole::Nominator
+            } else {
+                AccountRole::None
+            };
+            roles.insert(name.clone(), role);
+        }
+        Ok(roles)
+    }
+
+    /// Check whether an account is a registered validator (`Staking.Validators`) at a block
+    pub async fn is_validator(
+        &self,
+        account: [u8; 32],
+        block_hash: subxt::utils::H256,
+    ) -> Result<bool> {
+        let client = self.client()?;
+        let storage_address = subxt::dynamic::storage(
+            "Staking",
+            "Validators",
+            vec![subxt::dynamic::Value::from_bytes(account)],
+        );
+        let storage_value = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?;
+        Ok(storage_value.is_some())
+    }
+
+    /// Get the reward destination of a stash (`Staking.Payee`) at a block
+    pub async fn get_payee(
+        &self,
+        account: [u8; 32],
+        block_hash: subxt::utils::H256,
+    ) -> Result<RewardDestination> {
+        let client = self.client()?;
+        let storage_address = subxt::dynamic::storage(
+            "Staking",
+            "Payee",
+            vec![subxt::dynamic::Value::from_bytes(account)],
+        );
+        let Some(value) = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?
+        else {
+            return Ok(RewardDestination::Stash);
+        };
+
+        let ValueDef::Variant(variant) = value.to_value()?.value else {
+            return Ok(RewardDestination::Stash);
+        };
+        Ok(match variant.name.as_str() {
+            "Staked" => RewardDestination::Staked,
+            "Controller" => RewardDestination::Controller,
+            "Account" => variant
+                .values
+                .values()
+                .next()
+                .and_then(extract_account_id_from_value)
+                .map(RewardDestination::Account)
+                .unwrap_or(RewardDestination::Stash),
+            "None" => RewardDestination::None,
+            _ => RewardDestination::Stash,
+        })
+    }
+
+    /// Get the controller bonded to a stash (`Staking.Bonded`) at a block
+    async fn get_controller(
+        &self,
+        account: [u8; 32],
+        block_hash: subxt::utils::H256,
+    ) -> Result<Option<[u8; 32]>> {
+        let client = self.client()?;
+        let storage_address = subxt::dynamic::storage(
+            "Staking",
+            "Bonded",
+            vec![subxt::dynamic::Value::from_bytes(account)],
+        );
+        let storage_value = crate::retry!(client.storage().at(block_hash).fetch(&storage_address))?;
+        Ok(match storage_value {
+            Some(v) => extract_account_id_from_value(&v.to_value()?),
+            None => None,
+        })
+    }
+
+    /// Move each stash's reward to the tracked account that actually receives it
+    /// according to its `RewardDestination`, and annotate where it went.
+    async fn attribute_to_payees(
+        &self,
+        accounts: &HashMap<String, String>,
+        mut results: HashMap<String, StakingReward>,
+        at_hash: subxt::utils::H256,
+    ) -> HashMap<String, StakingReward> {
+        let mut by_id: HashMap<[u8; 32], String> = HashMap::new();
+        for (name, address) in accounts {
+            if let Ok(id) = crate::parse_ss58_address(address) {
+                by_id.insert(id.0, name.clone());
+            }
+        }
+
+        let mut transfers = Vec::new();
+        for (id, name) in &by_id {
+            let dest = match self.get_payee(*id, at_hash).await {
+                Ok(RewardDestination::Controller) => {
+                    match self.get_controller(*id, at_hash).await {
+                        Ok(Some(c)) if c != *id => RewardDestination::Account(c),
+                        _ => RewardDestination::Stash,
+                    }
+                }
+                Ok(d) => d,
+                Err(_) => continue,
+            };
+            let note = match dest {
+                RewardDestination::Stash | RewardDestination::Controller => continue,
+                RewardDestination::Staked => "staked (bonded, not free)".to_string(),
+                RewardDestination::None => "not paid out".to_string(),
+                RewardDestination::Account(payee) if payee == *id => continue,
+                RewardDestination::Account(payee) => match by_id.get(&payee) {
+                    Some(payee_name) => {
+                        transfers.push((name.clone(), payee_name.clone()));
+                        format!("paid to {}", payee_name)
+                    }
+                    None => format!(
+                        "paid to untracked {}",
+                        crate::to_ss58(&payee.into(), self.ss58_format)
+                    ),
+                },
+            };
+            if let Some(reward) = results.get_mut(name) {
+                reward.payee = Some(note);
+            }
+        }
+
+        // Amounts are read before any move so chained destinations don't cascade
+        let moves: Vec<(String, String, StakingReward)> = transfers
+            .into_iter()
+            .map(|(from, to)| {
+                let moved = results.get(&from).cloned().unwrap_or_default();
+                (from, to, moved)
+            })
+            .collect();
+        for (from, _, moved) in &moves {
+            if let Some(reward) = results.get_mut(from) {
+                reward.claimed -= moved.claimed;
+                reward.earned -= moved.earned;
+                reward.by_validator.clear();
+            }
+        }
+        for (_, to, moved) in moves {
+            let reward = results.entry(to).or_insert_with(StakingReward::zero);
+            reward.claimed += moved.claimed;
+            reward.earned += moved.earned;
+            for (validator, amount) in moved.by_validator {
+                *reward.by_validator.entry(validator).or_insert(0.0) += amount;
+            }
+        }
+        results
+    }
+
+    /// Highest block whose events may be read under the finality margin:
+    /// everything up to `margin` below the best head, or up to the finalized
+    /// head if that is later. `None` when the margin is off.
+    async fn scan_limit(&self) -> Result<Option<u64>> {
+        if self.finality_margin == 0 {
+            return Ok(None);
+        }
+        let rpc = self.rpc()?;
+        let best = crate::retry!(rpc.chain_get_header(None))?
+            .context("No header")?
+            .number as u64;
+        let finalized_hash = crate::retry!(rpc.chain_get_finalized_head())?;
+        let finalized = crate::retry!(rpc.chain_get_header(Some(finalized_hash)))?
+            .context("No header")?
+            .number as u64;
+        Ok(Some(
+            finalized.max(best.saturating_sub(self.finality_margin)),
+        ))
     }
 
     /// Check if a block has staking events
     pub async fn has_events(&mut self, block_number: u64) -> bool {
         self.ensure_connected().await.ok();
-        let hash_res = crate::retry!(async { self.get_block_hash(block_number).await });
+        if let Ok(Some(limit)) = self.scan_limit().await {
+            if block_number > limit {
+                return false;
+            }
+        }
+        let hash_res = self.get_block_hash(block_number).await;
 
         if let Ok(hash) = hash_res {
             if let Ok(client) = self.client() {
                 if let Ok(block) = crate::retry!(client.blocks().at(hash)) {
-                    if let Ok(events) = block.events().await {
+                    if let Ok(events) = crate::retry!(block.events()) {
                         for event in events.iter().flatten() {
                             if event.pallet_name() == "Staking"
                                 && (event.variant_name() == "Rewarded"
@@ -148,6 +675,61 @@ impl RewardTracker {
         false
     }
 
+    /// Whether the node still has state at a block.
+    ///
+    /// Reads `System.Account` for the all-zero account: a missing entry is fine,
+    /// but a pruned node fails the read.
+    pub async fn has_state(&mut self, block_number: u64) -> bool {
+        self.ensure_connected().await.ok();
+        let Ok(hash) = self.get_block_hash(block_number).await else {
+            return false;
+        };
+        let Ok(client) = self.client() else {
+            return false;
+        };
+        let storage_address = subxt::dynamic::storage(
+            "System",
+            "Account",
+            vec![subxt::dynamic::Value::from_bytes([0u8; 32])],
+        );
+        crate::retry!(client.storage().at(hash).fetch(&storage_address)).is_ok()
+    }
+
+    /// Get rewards for a block range under the given basis.
+    ///
+    /// `Earned` only computes era shares. `Claimed` scans events for paid rewards
+    /// and also fills `earned` when the era data is available.
+    pub async fn get_rewards(
+        &mut self,
+        accounts: &HashMap<String, String>,
+        start_block: u64,
+        end_block: u64,
+        basis: RewardBasis,
+    ) -> Result<HashMap<String, StakingReward>> {
+        match basis {
+            RewardBasis::Earned => {
+                self.get_rewards_via_eras(accounts, start_block, end_block)
+                    .await
+            }
+            RewardBasis::Claimed => {
+                let mut rewards = self
+                    .get_all_rewards_in_range(accounts, start_block, end_block)
+                    .await?;
+                if let Ok(earned) = self
+                    .get_rewards_via_eras(accounts, start_block, end_block)
+                    .await
+                {
+                    for (name, e) in earned {
+                        let reward = rewards.entry(name).or_insert_with(StakingReward::zero);
+                        reward.earned = e.earned;
+                        reward.by_validator = e.by_validator;
+                    }
+                }
+                Ok(rewards)
+            }
+        }
+    }
+
     /// Get rewards for accounts in a block range using Eras
     pub async fn get_rewards_via_eras(
         &mut self,
@@ -156,7 +738,10 @@ impl RewardTracker {
         end_block: u64,
     ) -> Result<HashMap<String, StakingReward>> {
         self.ensure_connected().await?;
-        let client = self.client.clone().context("Client not initialized")?;
+        let client = self
+            .client
+            .clone()
+            .ok_or_else(|| CtcError::Connection("Client not initialized".into()))?;
 
         let start_hash = self.get_block_hash(start_block).await?;
         let end_hash = self.get_block_hash(end_block).await?;
@@ -166,16 +751,18 @@ impl RewardTracker {
 
         let mut results = HashMap::new();
         for name in accounts.keys() {
-            results.insert(name.clone(), StakingReward { claimed: 0.0 });
+            results.insert(name.clone(), StakingReward::zero());
         }
 
-        if start_era == 0 || end_era == 0 {
+        let Some(eras) = era_span(start_era, end_era) else {
             return Ok(results);
-        }
+        };
+        let (start_era, end_era) = (*eras.start(), *eras.end());
 
-        let mut cumulative_reward_map: HashMap<String, f64> = HashMap::new();
+        // account name -> validator -> raw earned amount
+        let mut cumulative_reward_map: HashMap<String, HashMap<[u8; 32], f64>> = HashMap::new();
         for name in accounts.keys() {
-            cumulative_reward_map.insert(name.clone(), 0.0);
+            cumulative_reward_map.insert(name.clone(), HashMap::new());
         }
 
         let mut account_map: HashMap<[u8; 32], String> = HashMap::new();
@@ -185,52 +772,138 @@ impl RewardTracker {
             }
         }
 
+        // Skip the eras entirely when no tracked account nominates or validates
+        // at either end of the range; a nomination made and dropped entirely
+        // within it is missed
+        let mut staking = false;
+        'accounts: for id in account_map.keys() {
+            for at in [start_hash, end_hash] {
+                if !self.get_nominations(*id, at).await?.is_empty()
+                    || self.is_validator(*id, at).await?
+                {
+                    staking = true;
+                    break 'accounts;
+                }
+            }
+        }
+        if !staking {
+            return Ok(results);
+        }
+
+        let mut scan = EraScan {
+            at_hash: end_hash,
+            client: &client,
+            account_map: &account_map,
+            reward_points: None,
+            backed: HashSet::new(),
+        };
+
+        let start_spec = self.spec_version_for(start_block, start_hash).await;
+        let end_spec = self.spec_version_for(end_block, end_hash).await;
+        if let (Some(from), Some(to)) = (start_spec, end_spec) {
+            if from != to {
+                tracing::info!(
+                    "Runtime upgrade between blocks {} and {}: spec {} -> {}",
+                    start_block,
+                    end_block,
+                    from,
+                    to
+                );
+            }
+        }
+
+        // Each finished era is read at its payout block, so its storage is taken
+        // from inside the era rather than wherever the range happens to end
+        let mut recorded = BTreeMap::new();
+        let mut search_from = start_block;
         for era in start_era..=end_era {
-            self.process_era_rewards(
-                era,
-                end_hash,
-                &client,
-                &account_map,
-                &mut cumulative_reward_map,
-            )
-            .await?;
+            let spec = if era < end_era {
+                let block = self.era_payout_block(era, search_from, end_block).await?;
+                search_from = block;
+                scan.at_hash = self.get_block_hash(block).await?;
+                self.spec_version_for(block, scan.at_hash).await
+            } else {
+                scan.at_hash = end_hash;
+                end_spec
+            };
+            // Only validators a tracked account backs in this era can produce
+            // rewards for us; the era's points are read once for all of them
+            scan.reward_points = self.get_era_reward_points(era, scan.at_hash).await?;
+            scan.backed.clear();
+            if let Some((_, active)) = &scan.reward_points {
+                for id in account_map.keys() {
+                    scan.backed
+                        .extend(self.backed_validators(*id, active, scan.at_hash).await?);
+                }
+            }
+
+            // The runtime at the block the era is read at decides its layout
+            let layout = spec.and_then(|v| self.exposure_layouts.get(&v).copied());
+
+            let observed = self
+                .process_era_rewards(
+                    era,
+                    &scan,
+                    &mut cumulative_reward_map,
+                    layout,
+                    self.record_era_points.then_some(&mut recorded),
+                )
+                .await?;
+
+            if let (Some(spec), Some(observed)) = (spec, observed) {
+                self.exposure_layouts.insert(spec, observed);
+            }
         }
+        self.era_points.extend(recorded);
 
         let mut final_results = HashMap::new();
-        for (name, amt) in cumulative_reward_map {
+        for (name, per_validator) in cumulative_reward_map {
+            let by_validator: BTreeMap<String, f64> = per_validator
+                .into_iter()
+                .map(|(v, amt)| {
+                    let address = crate::to_ss58(&v.into(), self.ss58_format);
+                    (address, amt / self.divisor)
+                })
+                .collect();
             final_results.insert(
                 name,
                 StakingReward {
-                    claimed: amt / CTC_DIVISOR,
+                    earned: by_validator.values().sum(),
+                    by_validator,
+                    ..StakingReward::zero()
                 },
             );
         }
 
-        Ok(final_results)
+        Ok(self
+            .attribute_to_payees(accounts, final_results, end_hash)
+            .await)
     }
 
     /// Process rewards for a single era
+    ///
+    /// When `layout` is known that exposure item is read first, and the other one
+    /// only if it holds nothing; otherwise both are tried, paged first. Returns
+    /// the layout that actually held the era's exposures, if any.
     async fn process_era_rewards(
         &self,
         era: u32,
-        end_hash: subxt::utils::H256,
-        client: &OnlineClient<PolkadotConfig>,
-        account_map: &HashMap<[u8; 32], String>,
-        cumulative_reward_map: &mut HashMap<String, f64>,
-    ) -> Result<()> {
+        scan: &EraScan<'_>,
+        cumulative_reward_map: &mut HashMap<String, HashMap<[u8; 32], f64>>,
+        layout: Option<ExposureLayout>,
+        era_points: Option<&mut BTreeMap<u32, EraPoints>>,
+    ) -> Result<Option<ExposureLayout>> {
+        let at_hash = scan.at_hash;
+        let client = scan.client;
+        let account_map = scan.account_map;
         let total_reward_addr = subxt::dynamic::storage(
             "Staking",
             "ErasValidatorReward",
             vec![subxt::dynamic::Value::u128(era as u128)],
         );
-        let points_addr = subxt::dynamic::storage(
-            "Staking",
-            "ErasRewardPoints",
-            vec![subxt::dynamic::Value::u128(era as u128)],
-        );
 
         let total_reward_val =
-            match crate::retry!(client.storage().at(end_hash).fetch(&total_reward_addr))? {
+            match crate::retry!(client.storage().at(at_hash).fetch(&total_reward_addr))? {
                 Some(v) => {
                     let val = v.to_value()?;
                     match val.value {
@@ -238,22 +911,40 @@ impl RewardTracker {
                         _ => 0.0,
                     }
                 }
-                None => return Ok(()),
+                None => return Ok(None),
             };
 
-        let points_data = match crate::retry!(client.storage().at(end_hash).fetch(&points_addr))? {
-            Some(v) => v.to_value()?,
-            None => return Ok(()),
+        let Some((total_points, validator_points)) = &scan.reward_points else {
+            return Ok(None);
         };
-
-        let (total_points, validator_points) = parse_reward_points_def(points_data);
+        let total_points = *total_points;
 
         if total_points == 0.0 || total_reward_val == 0.0 {
-            return Ok(());
+            return Ok(None);
+        }
+
+        if let Some(era_points) = era_points {
+            let validators = validator_points
+                .iter()
+                .map(|(v, points)| (crate::to_ss58(&(*v).into(), self.ss58_format), *points))
+                .collect();
+            era_points.insert(
+                era,
+                EraPoints {
+                    era,
+                    total_points,
+                    validator_reward: total_reward_val / self.divisor,
+                    validators,
+                },
+            );
         }
 
         use futures::stream::{self, StreamExt};
-        let validator_keys: Vec<[u8; 32]> = validator_points.keys().cloned().collect();
+        let validator_keys: Vec<[u8; 32]> = validator_points
+            .keys()
+            .filter(|v| scan.backed.contains(*v))
+            .cloned()
+            .collect();
 
         let mut stream = stream::iter(validator_keys)
             .map(|v_bytes| {
@@ -284,17 +975,22 @@ impl RewardTracker {
                         ],
                     );
 
-                    let exposure =
-                        match crate::retry!(client.storage().at(end_hash).fetch(&exposure_addr)) {
-                            Ok(Some(e)) => Some(e),
-                            _ => crate::retry!(client
-                                .storage()
-                                .at(end_hash)
-                                .fetch(&legacy_exposure_addr))
-                            .ok()
-                            .flatten(),
+                    let first = layout.unwrap_or(ExposureLayout::Paged);
+                    let mut exposure = None;
+                    for candidate in [first, first.other()] {
+                        let address = match candidate {
+                            ExposureLayout::Paged => &exposure_addr,
+                            ExposureLayout::Clipped => &legacy_exposure_addr,
                         };
-                    let prefs = crate::retry!(client.storage().at(end_hash).fetch(&prefs_addr))
+                        if let Some(e) = crate::retry!(client.storage().at(at_hash).fetch(address))
+                            .ok()
+                            .flatten()
+                        {
+                            exposure = Some((candidate, e));
+                            break;
+                        }
+                    }
+                    let prefs = crate::retry!(client.storage().at(at_hash).fetch(&prefs_addr))
                         .ok()
                         .flatten();
 
@@ -303,6 +999,7 @@ impl RewardTracker {
             })
             .buffer_unordered(crate::CONCURRENCY_EXPOSURES);
 
+        let mut observed = None;
         while let Some((v_bytes, exposure_val, prefs_val)) = stream.next().await {
             let p_v = *validator_points.get(&v_bytes).unwrap_or(&0.0);
             if p_v == 0.0 {
@@ -318,7 +1015,8 @@ impl RewardTracker {
                 0.0
             };
 
-            if let Some(e) = exposure_val {
+            if let Some((used_layout, e)) = exposure_val {
+                observed.get_or_insert(used_layout);
                 let decoded = e.to_value()?;
                 let (e_total, e_own, mut nominators, page_count) = parse_exposure_def(decoded);
 
@@ -339,7 +1037,7 @@ impl RewardTracker {
                             ],
                         );
                         if let Ok(Some(page_val)) =
-                            crate::retry!(client.storage().at(end_hash).fetch(&paged_addr))
+                            crate::retry!(client.storage().at(at_hash).fetch(&paged_addr))
                         {
                             if let Ok(page_decoded) = page_val.to_value() {
                                 let page_nominators = parse_paged_exposure(page_decoded);
@@ -352,7 +1050,11 @@ impl RewardTracker {
                 if let Some(name) = account_map.get(&v_bytes) {
                     let validator_reward = (r_v_total * commission_ratio)
                         + (r_v_total * (1.0 - commission_ratio) * (e_own / e_total));
-                    *cumulative_reward_map.entry(name.clone()).or_insert(0.0) += validator_reward;
+                    *cumulative_reward_map
+                        .entry(name.clone())
+                        .or_default()
+                        .entry(v_bytes)
+                        .or_insert(0.0) += validator_reward;
                 }
 
                 for (n_bytes, n_value) in nominators {
@@ -360,13 +1062,16 @@ impl RewardTracker {
                         let nominator_reward =
                             r_v_total * (1.0 - commission_ratio) * (n_value / e_total);
 
-                        *cumulative_reward_map.entry(name.clone()).or_insert(0.0) +=
-                            nominator_reward;
+                        *cumulative_reward_map
+                            .entry(name.clone())
+                            .or_default()
+                            .entry(v_bytes)
+                            .or_insert(0.0) += nominator_reward;
                     }
                 }
             }
         }
-        Ok(())
+        Ok(observed)
     }
 
     /// Fallback method using event scanning
@@ -376,9 +1081,41 @@ impl RewardTracker {
         start_block: u64,
         end_block: u64,
     ) -> Result<HashMap<String, StakingReward>> {
+        let range = end_block.saturating_sub(start_block) + 1;
+        if let Some(max) = self.max_scan_blocks {
+            anyhow::ensure!(
+                range <= max,
+                "Refusing to scan {} blocks ({}..={}) for rewards; the limit is {}. \
+                 Make sure the block cache covers the following date, or raise --max-blocks-scan",
+                range,
+                start_block,
+                end_block,
+                max
+            );
+        }
+
         self.ensure_connected().await?;
-        let client = self.client.clone().context("Client not initialized")?;
-        let rpc = self.rpc.clone().context("RPC not initialized")?;
+        let client = self
+            .client
+            .clone()
+            .ok_or_else(|| CtcError::Connection("Client not initialized".into()))?;
+        let rpc = self
+            .rpc
+            .clone()
+            .ok_or_else(|| CtcError::Connection("RPC not initialized".into()))?;
+
+        // Near the head, stop at the last block that can no longer be reorged
+        let end_block = match self.scan_limit().await? {
+            Some(limit) if limit < end_block => {
+                tracing::warn!(
+                    "Skipping blocks {}..={}: not finalized yet",
+                    start_block.max(limit + 1),
+                    end_block
+                );
+                limit
+            }
+            _ => end_block,
+        };
 
         let mut results = HashMap::new();
 
@@ -404,7 +1141,7 @@ impl RewardTracker {
                         _ => return (block, None),
                     };
                     let events = crate::retry!(client.blocks().at(hash))
-                        .map(|b| async move { b.events().await.ok() })
+                        .map(|b| async move { crate::retry!(b.events()).ok() })
                         .ok();
                     match events {
                         Some(f) => (block, f.await),
@@ -418,8 +1155,8 @@ impl RewardTracker {
             processed_count += 1;
             if total_blocks > 100 && (processed_count % 100 == 0 || processed_count == total_blocks)
             {
-                println!(
-                    "    Scanning blocks: {}% ({}/{})",
+                tracing::debug!(
+                    "Scanning blocks: {}% ({}/{})",
                     processed_count * 100 / total_blocks,
                     processed_count,
                     total_blocks
@@ -482,7 +1219,8 @@ impl RewardTracker {
             results.insert(
                 name,
                 StakingReward {
-                    claimed: amount as f64 / CTC_DIVISOR,
+                    claimed: amount as f64 / self.divisor,
+                    ..StakingReward::zero()
                 },
             );
         }
@@ -490,10 +1228,20 @@ impl RewardTracker {
             results.entry(name.clone()).or_insert(StakingReward::zero());
         }
 
-        Ok(results)
+        let end_hash = self.get_block_hash(end_block).await?;
+        Ok(self.attribute_to_payees(accounts, results, end_hash).await)
     }
 }
 
+/// Eras to process for a range whose ends are in `start_era` and `end_era`.
+///
+/// A range starting at genesis begins in era 0, before staking has paid
+/// anything, so the scan starts at era 1. `None` while the range end is still
+/// in era 0.
+fn era_span(start_era: u32, end_era: u32) -> Option<std::ops::RangeInclusive<u32>> {
+    (end_era > 0).then(|| start_era.max(1)..=end_era)
+}
+
 fn parse_reward_points_def(val: Value<u32>) -> (f64, HashMap<[u8; 32], f64>) {
     let mut total = 0.0;
     let mut map = HashMap::new();
@@ -504,37 +1252,39 @@ fn parse_reward_points_def(val: Value<u32>) -> (f64, HashMap<[u8; 32], f64>) {
                     total = t as f64;
                 }
             } else if name == "individual" {
-                if let ValueDef::Composite(Composite::Unnamed(outer_items)) = field.value {
-                    let validator_list: &[Value<u32>] = if outer_items.len() == 1 {
-                        if let ValueDef::Composite(Composite::Unnamed(ref inner)) =
-                            outer_items[0].value
-                        {
-                            inner.as_slice()
-                        } else {
-                            outer_items.as_slice()
-                        }
-                    } else {
-                        outer_items.as_slice()
-                    };
-
-                    for item in validator_list {
-                        if let ValueDef::Composite(Composite::Unnamed(ref pair)) = item.value {
-                            if pair.len() == 2 {
-                                if let Some(id) = extract_account_id_from_value(&pair[0]) {
-                                    if let ValueDef::Primitive(Primitive::U128(pts)) = pair[1].value
-                                    {
-                                        map.insert(id, pts as f64);
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
+                collect_reward_points(&field, &mut map);
             }
         }
     }
     (total, map)
 }
+
+/// Collect `(AccountId, points)` pairs from `individual`, descending through
+/// wrapper composites by structure: a pair is recognised by its shape, so a
+/// list holding a single pair is not mistaken for a wrapper.
+fn collect_reward_points(val: &Value<u32>, map: &mut HashMap<[u8; 32], f64>) {
+    if let Some((id, points)) = reward_points_pair(val) {
+        map.insert(id, points);
+    } else if let ValueDef::Composite(Composite::Unnamed(items)) = &val.value {
+        for item in items {
+            collect_reward_points(item, map);
+        }
+    }
+}
+
+fn reward_points_pair(val: &Value<u32>) -> Option<([u8; 32], f64)> {
+    let ValueDef::Composite(Composite::Unnamed(pair)) = &val.value else {
+        return None;
+    };
+    if pair.len() != 2 {
+        return None;
+    }
+    let ValueDef::Primitive(Primitive::U128(points)) = pair[1].value else {
+        return None;
+    };
+    let id = extract_account_id_from_value(&pair[0])?;
+    Some((id, points as f64))
+}
 fn parse_commission_def(val: Value<u32>) -> f64 {
     if let ValueDef::Composite(Composite::Named(fields)) = val.value {
         for (name, field) in fields {
@@ -661,6 +1411,24 @@ fn parse_paged_exposure(val: Value<u32>) -> Vec<([u8; 32], f64)> {
     nominators
 }
 
+/// Parse a (possibly wrapped) list of account ids, e.g. a `BoundedVec<AccountId>`
+fn parse_account_list(val: &Value<u32>) -> Vec<[u8; 32]> {
+    if let ValueDef::Composite(Composite::Unnamed(items)) = &val.value {
+        let ids: Vec<[u8; 32]> = items
+            .iter()
+            .filter_map(extract_account_id_from_value)
+            .collect();
+        if ids.is_empty() && items.len() == 1 {
+            return parse_account_list(&items[0]);
+        }
+        return ids;
+    }
+    Vec::new()
+}
+
+/// Read a 32-byte account id, unwrapping single-field composites such as
+/// `AccountId32([u8; 32])`. Anything else, including an element outside
+/// `0..=255`, gives `None` rather than a truncated id.
 fn extract_account_id_from_value(mut val: &Value<u32>) -> Option<[u8; 32]> {
     loop {
         match &val.value {
@@ -669,7 +1437,7 @@ fn extract_account_id_from_value(mut val: &Value<u32>) -> Option<[u8; 32]> {
                     let mut bytes = [0u8; 32];
                     for (i, v) in items.iter().enumerate() {
                         if let ValueDef::Primitive(Primitive::U128(b)) = v.value {
-                            bytes[i] = b as u8;
+                            bytes[i] = u8::try_from(b).ok()?;
                         } else {
                             return None;
                         }
@@ -682,7 +1450,88 @@ fn extract_account_id_from_value(mut val: &Value<u32>) -> Option<[u8; 32]> {
                     return None;
                 }
             }
+            ValueDef::Composite(Composite::Named(fields)) if fields.len() == 1 => {
+                val = &fields[0].1;
+            }
             _ => return None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(byte: u8, pts: u128) -> Value {
+        let account = Value::unnamed_composite((0..32).map(|_| Value::u128(byte as u128)));
+        Value::unnamed_composite(vec![account, Value::u128(pts)])
+    }
+
+    #[test]
+    fn test_extract_account_id_bounds_and_nesting() {
+        let bytes =
+            |values: Vec<u128>| Value::unnamed_composite(values.into_iter().map(Value::u128));
+        let extract = |value: Value| extract_account_id_from_value(&value.map_context(|_| 0));
+
+        let valid = bytes((0..32).collect());
+        let mut expected = [0u8; 32];
+        for (i, b) in expected.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(extract(valid), Some(expected));
+
+        // 256 would truncate to 0
+        let mut values = vec![7; 32];
+        values[5] = 256;
+        assert_eq!(extract(bytes(values)), None);
+
+        // AccountId32([u8; 32]), as a tuple struct and with a named field
+        let nested =
+            Value::unnamed_composite(vec![Value::unnamed_composite(vec![bytes(vec![9; 32])])]);
+        assert_eq!(extract(nested), Some([9u8; 32]));
+        let named = Value::named_composite(vec![("id".to_string(), bytes(vec![9; 32]))]);
+        assert_eq!(extract(named), Some([9u8; 32]));
+    }
+
+    fn points(entries: Vec<Value>, wrap: bool) -> Value<u32> {
+        let list = Value::unnamed_composite(entries);
+        let individual = if wrap {
+            Value::unnamed_composite(vec![list])
+        } else {
+            list
+        };
+        Value::named_composite(vec![
+            ("total".to_string(), Value::u128(30)),
+            ("individual".to_string(), individual),
+        ])
+        .map_context(|_| 0)
+    }
+
+    #[test]
+    fn test_era_span_from_genesis() {
+        assert_eq!(era_span(0, 5), Some(1..=5));
+        assert_eq!(era_span(3, 5), Some(3..=5));
+        assert_eq!(era_span(0, 1), Some(1..=1));
+        assert_eq!(era_span(0, 0), None);
+    }
+
+    #[test]
+    fn test_reward_points_single_validator() {
+        // One rewarded validator, with and without the extra wrapper
+        for wrap in [false, true] {
+            let (total, map) = parse_reward_points_def(points(vec![pair(1, 30)], wrap));
+            assert_eq!(total, 30.0);
+            assert_eq!(map.len(), 1, "wrap = {}", wrap);
+            assert_eq!(map[&[1u8; 32]], 30.0);
+        }
+    }
+
+    #[test]
+    fn test_reward_points_many_validators() {
+        for wrap in [false, true] {
+            let (_, map) = parse_reward_points_def(points(vec![pair(1, 10), pair(2, 20)], wrap));
+            assert_eq!(map.len(), 2);
+            assert_eq!(map[&[2u8; 32]], 20.0);
+        }
+    }
+}
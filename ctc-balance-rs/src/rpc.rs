@@ -0,0 +1,107 @@
+//! Reconnecting RPC transport.
+//!
+//! Every connector and tracker talks to the node through this client. When the
+//! WebSocket drops (node restart, idle proxy, "connection reset" hours into a
+//! scan) the connection is reopened and the request sent again, so the
+//! `OnlineClient`s built on top keep working instead of failing for good.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use subxt::backend::rpc::{RawRpcFuture, RawRpcSubscription, RawValue, RpcClient, RpcClientT};
+use subxt::error::RpcError;
+use tokio::sync::RwLock;
+use tracing::warn;
+
+use crate::CtcError;
+
+/// Reconnects tried for one request before its error is returned
+const MAX_RECONNECTS: u32 = 3;
+
+static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+/// Connections reopened so far in this process
+pub fn reconnect_count() -> u64 {
+    RECONNECTS.load(Ordering::Relaxed)
+}
+
+/// RPC client that reopens its connection when it finds it closed
+pub struct ReconnectingClient {
+    url: String,
+    /// Current connection, and how many times it has been replaced
+    inner: RwLock<(RpcClient, u64)>,
+}
+
+impl ReconnectingClient {
+    /// Open the first connection to `url`
+    pub async fn connect(url: &str) -> anyhow::Result<Self> {
+        let client = crate::open_rpc_client(url).await?;
+        Ok(Self {
+            url: url.to_string(),
+            inner: RwLock::new((client, 0)),
+        })
+    }
+
+    /// Replace connection `generation`, unless a concurrent request already has
+    async fn reconnect(&self, generation: u64, error: &RpcError) -> Result<(), RpcError> {
+        let mut inner = self.inner.write().await;
+        if inner.1 != generation {
+            return Ok(());
+        }
+        let count = RECONNECTS.fetch_add(1, Ordering::Relaxed) + 1;
+        warn!(
+            "RPC connection to {} lost ({}); reconnecting (#{})",
+            self.url, error, count
+        );
+        let client = crate::open_rpc_client(&self.url)
+            .await
+            .map_err(|e| RpcError::ClientError(e.into()))?;
+        *inner = (client, generation + 1);
+        Ok(())
+    }
+}
+
+fn is_closed(error: &RpcError) -> bool {
+    CtcError::from_message(&error.to_string()).is_disconnect()
+}
+
+impl RpcClientT for ReconnectingClient {
+    fn request_raw<'a>(
+        &'a self,
+        method: &'a str,
+        params: Option<Box<RawValue>>,
+    ) -> RawRpcFuture<'a, Box<RawValue>> {
+        Box::pin(async move {
+            let mut attempts = 0;
+            loop {
+                let (client, generation) = self.inner.read().await.clone();
+                match client.request_raw(method, params.clone()).await {
+                    Err(e) if attempts < MAX_RECONNECTS && is_closed(&e) => {
+                        attempts += 1;
+                        self.reconnect(generation, &e).await?;
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+
+    fn subscribe_raw<'a>(
+        &'a self,
+        sub: &'a str,
+        params: Option<Box<RawValue>>,
+        unsub: &'a str,
+    ) -> RawRpcFuture<'a, RawRpcSubscription> {
+        Box::pin(async move {
+            let mut attempts = 0;
+            loop {
+                let (client, generation) = self.inner.read().await.clone();
+                match client.subscribe_raw(sub, params.clone(), unsub).await {
+                    Err(e) if attempts < MAX_RECONNECTS && is_closed(&e) => {
+                        attempts += 1;
+                        self.reconnect(generation, &e).await?;
+                    }
+                    result => return result,
+                }
+            }
+        })
+    }
+}